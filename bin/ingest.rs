@@ -0,0 +1,22 @@
+use anyhow::Result;
+use std::net::TcpListener;
+use toypay::{cli, ingest};
+
+fn main() -> Result<()> {
+    let args = cli::parse_ingest_args()?;
+    args.engine.init_logging()?;
+    let engine = args.engine.build_engine()?;
+
+    let listener = TcpListener::bind(&args.listen)?;
+    eprintln!("Listening on tcp://{}", listener.local_addr()?);
+    let metrics = match &args.metrics_listen {
+        Some(addr) => {
+            let metrics = TcpListener::bind(addr)?;
+            eprintln!("Metrics on http://{}/metrics", metrics.local_addr()?);
+            Some(metrics)
+        }
+        None => None,
+    };
+
+    ingest::serve_with_metrics(listener, engine, args.queue_capacity, metrics)
+}
@@ -0,0 +1,31 @@
+use anyhow::Result;
+use csv::WriterBuilder;
+use std::io;
+use toypay::{as_of, cli, output::OutputOptions, project, LedgerFile, OpenDisputes, ToyEngine};
+
+// Prints the balances of the engine rebuilt from a ledger, or the disputes
+// still open, as of a point of the ledger.
+fn main() -> Result<()> {
+    let args = cli::parse_ledger_args()?;
+    args.engine.init_logging()?;
+
+    let records = LedgerFile::read(&args.path)?;
+    let records = match args.as_of {
+        Some(point) => as_of(&records, point),
+        None => &records,
+    };
+
+    if args.open_disputes {
+        let mut writer = WriterBuilder::new().from_writer(io::stdout());
+        for dispute in project::<OpenDisputes>(records).disputes() {
+            writer.serialize(dispute)?;
+        }
+        writer.flush()?;
+    } else {
+        let config = args.engine.engine_config()?;
+        let engine = ToyEngine::from_ledger(&config, records.to_vec())?;
+        OutputOptions::default().write(engine.get_all_accounts(), io::stdout())?;
+    }
+
+    Ok(())
+}
@@ -1,29 +1,120 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use csv::{ReaderBuilder, WriterBuilder};
 use std::io;
-use toypay::{cli, ToyEngine};
+use toypay::{
+    cli,
+    engine::{
+        config::ErrorMode,
+        reorder::ReorderBuffer,
+        tenants::{TenantRouter, TenantsConfig},
+    },
+    models::input_transaction::InputTransaction,
+    ToyEngine,
+};
+use tracing::warn;
+
+// A single engine, or one per tenant with `--by-tenant`.
+enum Engines {
+    Single(Box<ToyEngine>),
+    ByTenant(TenantRouter),
+}
 
 fn main() -> Result<()> {
-    let file_path = cli::parse_args()?;
+    let args = cli::parse_args()?;
+    args.engine.init_logging()?;
 
     let mut reader = ReaderBuilder::new()
         .trim(csv::Trim::All)
         .flexible(true)
-        .from_path(&file_path)?;
+        .from_path(&args.input)?;
 
-    let mut engine = ToyEngine::new();
+    let errors = args.engine.engine_config()?.errors;
+    let mut engines = if args.by_tenant {
+        let tenants = match &args.tenants {
+            Some(path) => TenantsConfig::from_file(path)?,
+            None => TenantsConfig::default(),
+        };
+        Engines::ByTenant(TenantRouter::new(args.engine.engine_factory()?).with_tenants(tenants))
+    } else {
+        Engines::Single(Box::new(args.engine.build_engine()?))
+    };
+    // Rejected transactions are only logged by the engine, unless aborting.
+    let mut dispatch = |transaction: InputTransaction| -> Result<()> {
+        let tx = transaction.tx;
+        let result = match &mut engines {
+            Engines::Single(engine) => engine.dispatch(transaction),
+            Engines::ByTenant(router) => router.dispatch(transaction),
+        };
+        match result {
+            Err(e) if errors == ErrorMode::Abort => {
+                Err(e.context(format!("Transaction {} rejected", tx)))
+            }
+            _ => Ok(()),
+        }
+    };
 
-    for result in reader.deserialize() {
-        if let Ok(transaction) = result {
-            let _ = engine.dispatch(transaction);
+    // Malformed rows are skipped, not fatal, unless aborting.
+    let transactions = reader
+        .deserialize::<InputTransaction>()
+        .filter_map(|row| match row {
+            Ok(transaction) => Some(Ok(transaction)),
+            Err(e) if errors == ErrorMode::Abort => Some(Err(e).context("Malformed row")),
+            Err(e) => {
+                warn!(error = %e, "malformed row");
+                None
+            }
+        });
+
+    match args.reorder_tolerance {
+        Some(tolerance) => {
+            let mut buffer = ReorderBuffer::new(tolerance);
+            for transaction in transactions {
+                match buffer.push(transaction?) {
+                    Ok(()) => {
+                        while let Some(transaction) = buffer.pop_ready() {
+                            dispatch(transaction)?;
+                        }
+                    }
+                    Err(e) if errors == ErrorMode::Abort => return Err(e),
+                    Err(e) => warn!(error = %e, "transaction dropped"),
+                }
+            }
+            while let Some(transaction) = buffer.pop() {
+                dispatch(transaction)?;
+            }
+        }
+        None => {
+            for transaction in transactions {
+                dispatch(transaction?)?;
+            }
         }
     }
 
-    let mut writer = WriterBuilder::new().from_writer(io::stdout());
-    for record in engine.get_all_accounts() {
-        writer.serialize(record)?;
+    let metrics = match &engines {
+        Engines::Single(engine) if args.client_details => {
+            args.output
+                .write(engine.get_detailed_accounts(), io::stdout())?;
+            engine.metrics()
+        }
+        Engines::Single(engine) => {
+            args.output.write(engine.get_all_accounts(), io::stdout())?;
+            engine.metrics()
+        }
+        Engines::ByTenant(router) => {
+            args.output.write(router.get_all_accounts(), io::stdout())?;
+            router.metrics()
+        }
+    };
+    eprint!("{}", metrics.summary());
+
+    // Not supported per tenant.
+    if let (Some(path), Engines::Single(engine)) = (&args.risk_report, &engines) {
+        let mut writer = WriterBuilder::new().from_path(path)?;
+        for flag in engine.risk_flags() {
+            writer.serialize(flag)?;
+        }
+        writer.flush()?;
     }
-    writer.flush()?;
 
     Ok(())
 }
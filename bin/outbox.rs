@@ -0,0 +1,38 @@
+use anyhow::Result;
+use std::{
+    io::{self, Write},
+    thread,
+    time::Duration,
+};
+use toypay::{cli, engine::outbox::OutboxEntry, OutboxConsumer};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+fn print(entries: &[OutboxEntry]) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    for entry in entries {
+        serde_json::to_writer(&mut stdout, entry)?;
+        writeln!(stdout)?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+// Prints the events not acknowledged yet, then acknowledges up to `--ack`
+// and, with `--follow`, prints new events as they are appended.
+fn main() -> Result<()> {
+    let args = cli::parse_outbox_args()?;
+    let mut consumer = OutboxConsumer::open(&args.path)?;
+
+    print(&consumer.poll()?)?;
+    if let Some(seq) = args.ack {
+        consumer.ack(seq)?;
+    }
+    if !args.follow {
+        return Ok(());
+    }
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        print(&consumer.poll()?)?;
+    }
+}
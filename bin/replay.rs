@@ -0,0 +1,43 @@
+use anyhow::{Context, Result};
+use csv::WriterBuilder;
+use std::{fs::File, io};
+use toypay::{
+    cli::{self, Comparison},
+    replay,
+};
+
+fn main() -> Result<()> {
+    let args = cli::parse_replay_args()?;
+    args.engine.init_logging()?;
+
+    let errors = args.engine.engine_config()?.errors;
+    let input = File::open(&args.input).with_context(|| format!("Cannot read {}", args.input))?;
+    let (transactions, malformed) = replay::read_transactions(input, errors)?;
+
+    let mut actual = args.engine.build_engine()?;
+    let diffs = match &args.compare {
+        Comparison::Snapshot(path) => {
+            let expected = replay::read_snapshot(path)?;
+            for transaction in transactions {
+                replay::dispatch(&mut actual, transaction, errors)?;
+            }
+            replay::compare_snapshot(expected, &actual)
+        }
+        Comparison::Engine(options) => {
+            let mut expected = options.build_engine()?;
+            replay::compare_engines(transactions, &mut expected, &mut actual, errors)?
+        }
+    };
+
+    let mut writer = WriterBuilder::new().from_writer(io::stdout());
+    for diff in &diffs {
+        writer.serialize(diff)?;
+    }
+    writer.flush()?;
+    if malformed > 0 {
+        eprintln!("{} malformed row(s) skipped", malformed);
+    }
+    eprintln!("{} client(s) differ", diffs.len());
+
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use anyhow::{Context, Result};
+use csv::ReaderBuilder;
+use toypay::{
+    cli,
+    engine::config::ErrorMode,
+    models::input_transaction::InputTransaction,
+    report::{ReportBuilder, ReportFormat},
+};
+use tracing::warn;
+
+fn main() -> Result<()> {
+    let args = cli::parse_report_args()?;
+    args.engine.init_logging()?;
+
+    let mut reader = ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_path(&args.input)?;
+
+    let errors = args.engine.engine_config()?.errors;
+    let mut engine = args.engine.build_engine()?;
+    let mut report = ReportBuilder::new();
+    for row in reader.deserialize::<InputTransaction>() {
+        match row {
+            Ok(transaction) => {
+                let result = engine.dispatch(transaction.clone());
+                report.record(&engine, &transaction, &result);
+                if errors == ErrorMode::Abort {
+                    result.with_context(|| format!("Transaction {} rejected", transaction.tx))?;
+                }
+            }
+            Err(e) if errors == ErrorMode::Abort => return Err(e).context("Malformed row"),
+            Err(e) => {
+                warn!(error = %e, "malformed row");
+                report.malformed();
+            }
+        }
+    }
+
+    let report = report.build(&engine, args.top);
+    match args.format {
+        ReportFormat::Text => print!("{}", report.to_text()),
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,14 @@
+use anyhow::Result;
+use std::net::TcpListener;
+use toypay::{cli, server};
+
+fn main() -> Result<()> {
+    let args = cli::parse_server_args()?;
+    args.engine.init_logging()?;
+    let engine = args.engine.build_engine()?;
+
+    let listener = TcpListener::bind(&args.listen)?;
+    eprintln!("Listening on http://{}", listener.local_addr()?);
+
+    server::serve(listener, engine)
+}
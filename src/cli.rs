@@ -1,10 +1,608 @@
+use crate::{
+    engine::{
+        archive::TransactionArchive,
+        config::{EngineConfig, ErrorMode},
+        fees::FeeSchedule,
+        ledger::{LedgerFile, PointInTime},
+        observer::JsonLinesObserver,
+        outbox::Outbox,
+        policy::{AccountPolicy, LockedAccountPolicy},
+        registry::ClientRegistry,
+        risk::RiskConfig,
+        rules::LimitsConfig,
+        tenants::TenantConfig,
+        EngineBuilder,
+    },
+    ingest, logging,
+    output::OutputOptions,
+    report::{ReportFormat, DEFAULT_TOP_CLIENTS},
+    ToyEngine,
+};
 use anyhow::{anyhow, Result};
-use std::env;
+use std::{env, num::NonZeroUsize, time::Duration};
+use tracing::level_filters::LevelFilter;
 
-pub fn parse_args() -> Result<String> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        return Err(anyhow!("Usage: {} <transactions.csv>", args[0]));
+const ENGINE_USAGE: &str =
+    "[--config <engine.toml>] [--fees <fees.toml>] [--limits <limits.toml>] [--risk <risk.toml>] \
+[--dispute-window <days>] [--registry <clients.csv>] \
+[--accounts <on-first-deposit|on-reference|registered>] [--shards <count>] \
+[--log-level <off|error|warn|info|debug|trace>] [--log-file <path>] \
+[--cache-capacity <transactions>] [--archive <path>] [--events <path>] [--outbox <path>] [--ledger <path>] [--log-evictions] [--amount-scale <places>] \
+[--locked-accounts <frozen|accept-deposits>] [--errors <skip|abort>]";
+const USAGE: &str = "<transactions.csv> [--risk-report <report.csv>] [--reorder-tolerance <ms>] \
+[--client-details] [--by-tenant] [--tenants <tenants.toml>] [--sort <client|total|held>] [--desc] \
+[--locked-only] [--non-zero] [--clients <id,...>] [--min-total <amount>] \
+[--columns <column[=name],...>] [--footer]";
+const SERVER_USAGE: &str = "[--listen <127.0.0.1:8080>]";
+const INGEST_USAGE: &str =
+    "[--listen <127.0.0.1:9000>] [--queue <capacity>] [--metrics-listen <127.0.0.1:9100>]";
+const REPLAY_USAGE: &str = "<transactions.csv> --compare <expected.csv | engine options...>";
+const REPORT_USAGE: &str = "<transactions.csv> [--format <text|json>] [--top <count>]";
+const LEDGER_USAGE: &str = "<ledger.jsonl> [--as-of <seq>] [--as-of-tx <tx>] [--open-disputes] \
+[--config <engine.toml>] [--shards <count>] [--cache-capacity <transactions>] \
+[--amount-scale <places>] [--log-level <off|error|warn|info|debug|trace>] [--log-file <path>]";
+const OUTBOX_USAGE: &str = "<outbox.jsonl> [--follow] [--ack <seq>]";
+
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:8080";
+pub const DEFAULT_INGEST_LISTEN: &str = "127.0.0.1:9000";
+
+#[derive(Debug, Default)]
+pub struct EngineOptions {
+    // The flags below override the settings of this file.
+    pub config: Option<String>,
+    pub fees: Option<String>,
+    pub limits: Option<String>,
+    pub risk: Option<String>,
+    // In days.
+    pub dispute_window: Option<u64>,
+    pub accounts: Option<AccountPolicy>,
+    pub registry: Option<String>,
+    pub shard_count: Option<usize>,
+    pub log_level: Option<LevelFilter>,
+    pub log_file: Option<String>,
+    pub cache_capacity: Option<NonZeroUsize>,
+    pub archive: Option<String>,
+    // Where the engine events are written as JSON lines.
+    pub events: Option<String>,
+    // Where the domain events are appended for downstream consumers.
+    pub outbox: Option<String>,
+    // Where the ledger records are written, to rebuild the engine from.
+    pub ledger: Option<String>,
+    pub log_evictions: bool,
+    pub amount_scale: Option<u32>,
+    pub locked_accounts: Option<LockedAccountPolicy>,
+    pub errors: Option<ErrorMode>,
+}
+
+#[derive(Debug, Default)]
+pub struct Args {
+    pub input: String,
+    pub engine: EngineOptions,
+    pub risk_report: Option<String>,
+    pub reorder_tolerance: Option<Duration>,
+    pub client_details: bool,
+    // Set by `--by-tenant` or implied by `--tenants`.
+    pub by_tenant: bool,
+    pub tenants: Option<String>,
+    pub output: OutputOptions,
+}
+
+#[derive(Debug)]
+pub struct ServerArgs {
+    pub listen: String,
+    pub engine: EngineOptions,
+}
+
+#[derive(Debug)]
+pub struct IngestArgs {
+    pub listen: String,
+    pub queue_capacity: usize,
+    pub metrics_listen: Option<String>,
+    pub engine: EngineOptions,
+}
+
+// What the replay is compared to: a saved output, or the same input run
+// through a second engine configuration.
+#[derive(Debug)]
+pub enum Comparison {
+    Snapshot(String),
+    Engine(Box<EngineOptions>),
+}
+
+#[derive(Debug)]
+pub struct ReplayArgs {
+    pub input: String,
+    pub engine: EngineOptions,
+    pub compare: Comparison,
+}
+
+#[derive(Debug)]
+pub struct ReportArgs {
+    pub input: String,
+    pub engine: EngineOptions,
+    pub format: ReportFormat,
+    pub top: usize,
+}
+
+#[derive(Debug)]
+pub struct LedgerArgs {
+    pub path: String,
+    pub engine: EngineOptions,
+    // The whole ledger when not set.
+    pub as_of: Option<PointInTime>,
+    pub open_disputes: bool,
+}
+
+#[derive(Debug)]
+pub struct OutboxArgs {
+    pub path: String,
+    // Keep polling for new events once the pending ones are printed.
+    pub follow: bool,
+    pub ack: Option<u64>,
+}
+
+pub fn parse_args() -> Result<Args> {
+    let mut args = env::args();
+    let program = args.next().unwrap_or_else(|| "toypay".to_string());
+    parse_from(args).map_err(|e| usage(e, &program, USAGE))
+}
+
+pub fn parse_server_args() -> Result<ServerArgs> {
+    let mut args = env::args();
+    let program = args.next().unwrap_or_else(|| "server".to_string());
+    parse_server_from(args).map_err(|e| usage(e, &program, SERVER_USAGE))
+}
+
+pub fn parse_ingest_args() -> Result<IngestArgs> {
+    let mut args = env::args();
+    let program = args.next().unwrap_or_else(|| "ingest".to_string());
+    parse_ingest_from(args).map_err(|e| usage(e, &program, INGEST_USAGE))
+}
+
+pub fn parse_replay_args() -> Result<ReplayArgs> {
+    let mut args = env::args();
+    let program = args.next().unwrap_or_else(|| "replay".to_string());
+    parse_replay_from(args).map_err(|e| usage(e, &program, REPLAY_USAGE))
+}
+
+pub fn parse_report_args() -> Result<ReportArgs> {
+    let mut args = env::args();
+    let program = args.next().unwrap_or_else(|| "report".to_string());
+    parse_report_from(args).map_err(|e| usage(e, &program, REPORT_USAGE))
+}
+
+pub fn parse_ledger_args() -> Result<LedgerArgs> {
+    let mut args = env::args();
+    let program = args.next().unwrap_or_else(|| "ledger".to_string());
+    parse_ledger_from(args).map_err(|e| anyhow!("{}\nUsage: {} {}", e, program, LEDGER_USAGE))
+}
+
+pub fn parse_outbox_args() -> Result<OutboxArgs> {
+    let mut args = env::args();
+    let program = args.next().unwrap_or_else(|| "outbox".to_string());
+    parse_outbox_from(args).map_err(|e| anyhow!("{}\nUsage: {} {}", e, program, OUTBOX_USAGE))
+}
+
+fn usage(e: anyhow::Error, program: &str, usage: &str) -> anyhow::Error {
+    anyhow!("{}\nUsage: {} {} {}", e, program, usage, ENGINE_USAGE)
+}
+
+pub fn parse_from(args: impl IntoIterator<Item = String>) -> Result<Args> {
+    let mut parsed = Args::default();
+    let mut input = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if parsed.engine.parse_flag(&arg, &mut args)?
+            || parse_output_flag(&mut parsed.output, &arg, &mut args)?
+        {
+            continue;
+        }
+        match arg.as_str() {
+            "--client-details" => parsed.client_details = true,
+            "--by-tenant" => parsed.by_tenant = true,
+            "--tenants" => {
+                parsed.tenants = Some(flag_value(&mut args, &arg)?);
+                parsed.by_tenant = true;
+            }
+            "--risk-report" => parsed.risk_report = Some(flag_value(&mut args, &arg)?),
+            "--reorder-tolerance" => {
+                let millis: u64 = parse_value(&mut args, &arg)?;
+                parsed.reorder_tolerance = Some(Duration::from_millis(millis));
+            }
+            flag if flag.starts_with("--") => return Err(anyhow!("Unknown option: {}", flag)),
+            _ if input.is_none() => input = Some(arg),
+            _ => return Err(anyhow!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    parsed.input = input.ok_or_else(|| anyhow!("Missing input file"))?;
+    let per_engine = parsed.client_details
+        || parsed.risk_report.is_some()
+        || parsed.engine.archive.is_some()
+        || parsed.engine.events.is_some()
+        || parsed.engine.outbox.is_some()
+        || parsed.engine.ledger.is_some();
+    if parsed.by_tenant && per_engine {
+        return Err(anyhow!(
+            "--client-details, --risk-report, --archive, --events, --outbox and --ledger are not \
+supported per tenant"
+        ));
+    }
+    Ok(parsed)
+}
+
+pub fn parse_server_from(args: impl IntoIterator<Item = String>) -> Result<ServerArgs> {
+    let mut parsed = ServerArgs {
+        listen: DEFAULT_LISTEN.to_string(),
+        engine: EngineOptions::default(),
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if parsed.engine.parse_flag(&arg, &mut args)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--listen" => parsed.listen = flag_value(&mut args, &arg)?,
+            _ => return Err(anyhow!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    Ok(parsed)
+}
+
+pub fn parse_ingest_from(args: impl IntoIterator<Item = String>) -> Result<IngestArgs> {
+    let mut parsed = IngestArgs {
+        listen: DEFAULT_INGEST_LISTEN.to_string(),
+        queue_capacity: ingest::DEFAULT_QUEUE_CAPACITY,
+        metrics_listen: None,
+        engine: EngineOptions::default(),
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if parsed.engine.parse_flag(&arg, &mut args)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--listen" => parsed.listen = flag_value(&mut args, &arg)?,
+            "--queue" => parsed.queue_capacity = parse_value(&mut args, &arg)?,
+            "--metrics-listen" => parsed.metrics_listen = Some(flag_value(&mut args, &arg)?),
+            _ => return Err(anyhow!("Unexpected argument: {}", arg)),
+        }
     }
-    Ok(args[1].clone())
+
+    Ok(parsed)
+}
+
+// Engine options after `--compare` configure the expected side, so they
+// must come last.
+pub fn parse_replay_from(args: impl IntoIterator<Item = String>) -> Result<ReplayArgs> {
+    let mut engine = EngineOptions::default();
+    let mut input = None;
+    let mut compare = None;
+
+    let mut args = args.into_iter().peekable();
+    while let Some(arg) = args.next() {
+        if engine.parse_flag(&arg, &mut args)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--compare" if args.peek().is_some_and(|next| next.starts_with("--")) => {
+                let mut expected = EngineOptions::default();
+                while let Some(arg) = args.next() {
+                    if !expected.parse_flag(&arg, &mut args)? {
+                        return Err(anyhow!("Unexpected engine option: {}", arg));
+                    }
+                }
+                compare = Some(Comparison::Engine(Box::new(expected)));
+            }
+            "--compare" => compare = Some(Comparison::Snapshot(flag_value(&mut args, &arg)?)),
+            flag if flag.starts_with("--") => return Err(anyhow!("Unknown option: {}", flag)),
+            _ if input.is_none() => input = Some(arg),
+            _ => return Err(anyhow!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    Ok(ReplayArgs {
+        input: input.ok_or_else(|| anyhow!("Missing input file"))?,
+        engine,
+        compare: compare.ok_or_else(|| anyhow!("Missing --compare"))?,
+    })
+}
+
+pub fn parse_report_from(args: impl IntoIterator<Item = String>) -> Result<ReportArgs> {
+    let mut engine = EngineOptions::default();
+    let mut input = None;
+    let mut format = ReportFormat::default();
+    let mut top = DEFAULT_TOP_CLIENTS;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if engine.parse_flag(&arg, &mut args)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--format" => format = parse_value(&mut args, &arg)?,
+            "--top" => top = parse_value(&mut args, &arg)?,
+            flag if flag.starts_with("--") => return Err(anyhow!("Unknown option: {}", flag)),
+            _ if input.is_none() => input = Some(arg),
+            _ => return Err(anyhow!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    Ok(ReportArgs {
+        input: input.ok_or_else(|| anyhow!("Missing input file"))?,
+        engine,
+        format,
+        top,
+    })
+}
+
+pub fn parse_ledger_from(args: impl IntoIterator<Item = String>) -> Result<LedgerArgs> {
+    let mut engine = EngineOptions::default();
+    let mut path = None;
+    let mut as_of = None;
+    let mut open_disputes = false;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if engine.parse_flag(&arg, &mut args)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--as-of" => as_of = Some(PointInTime::Seq(parse_value(&mut args, &arg)?)),
+            "--as-of-tx" => as_of = Some(PointInTime::Tx(parse_value(&mut args, &arg)?)),
+            "--open-disputes" => open_disputes = true,
+            flag if flag.starts_with("--") => return Err(anyhow!("Unknown option: {}", flag)),
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(anyhow!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    // The ledger alone rebuilds the engine, accounts opened by the fees or
+    // the registry included: the options about how transactions are applied
+    // would be silently ignored.
+    let unsupported = [
+        ("--fees", engine.fees.is_some()),
+        ("--limits", engine.limits.is_some()),
+        ("--risk", engine.risk.is_some()),
+        ("--dispute-window", engine.dispute_window.is_some()),
+        ("--accounts", engine.accounts.is_some()),
+        ("--registry", engine.registry.is_some()),
+        ("--archive", engine.archive.is_some()),
+        ("--events", engine.events.is_some()),
+        ("--outbox", engine.outbox.is_some()),
+        ("--ledger", engine.ledger.is_some()),
+        ("--log-evictions", engine.log_evictions),
+        ("--locked-accounts", engine.locked_accounts.is_some()),
+        ("--errors", engine.errors.is_some()),
+    ];
+    if let Some((flag, _)) = unsupported.iter().find(|(_, set)| *set) {
+        return Err(anyhow!("{} is not supported by ledger", flag));
+    }
+
+    Ok(LedgerArgs {
+        path: path.ok_or_else(|| anyhow!("Missing ledger file"))?,
+        engine,
+        as_of,
+        open_disputes,
+    })
+}
+
+pub fn parse_outbox_from(args: impl IntoIterator<Item = String>) -> Result<OutboxArgs> {
+    let mut path = None;
+    let mut follow = false;
+    let mut ack = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--follow" => follow = true,
+            "--ack" => ack = Some(parse_value(&mut args, &arg)?),
+            flag if flag.starts_with("--") => return Err(anyhow!("Unknown option: {}", flag)),
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(anyhow!("Unexpected argument: {}", arg)),
+        }
+    }
+
+    Ok(OutboxArgs {
+        path: path.ok_or_else(|| anyhow!("Missing outbox file"))?,
+        follow,
+        ack,
+    })
+}
+
+impl EngineOptions {
+    fn parse_flag(&mut self, arg: &str, args: &mut impl Iterator<Item = String>) -> Result<bool> {
+        match arg {
+            "--config" => self.config = Some(flag_value(args, arg)?),
+            "--fees" => self.fees = Some(flag_value(args, arg)?),
+            "--limits" => self.limits = Some(flag_value(args, arg)?),
+            "--risk" => self.risk = Some(flag_value(args, arg)?),
+            "--dispute-window" => self.dispute_window = Some(parse_value(args, arg)?),
+            "--accounts" => self.accounts = Some(parse_value(args, arg)?),
+            "--registry" => self.registry = Some(flag_value(args, arg)?),
+            "--shards" => {
+                let count: usize = parse_value(args, arg)?;
+                if count == 0 {
+                    return Err(anyhow!("--shards must be positive"));
+                }
+                self.shard_count = Some(count);
+            }
+            "--log-level" => self.log_level = Some(parse_value(args, arg)?),
+            "--log-file" => self.log_file = Some(flag_value(args, arg)?),
+            "--cache-capacity" => self.cache_capacity = Some(parse_value(args, arg)?),
+            "--archive" => self.archive = Some(flag_value(args, arg)?),
+            "--events" => self.events = Some(flag_value(args, arg)?),
+            "--outbox" => self.outbox = Some(flag_value(args, arg)?),
+            "--ledger" => self.ledger = Some(flag_value(args, arg)?),
+            "--log-evictions" => self.log_evictions = true,
+            "--amount-scale" => self.amount_scale = Some(parse_value(args, arg)?),
+            "--locked-accounts" => self.locked_accounts = Some(parse_value(args, arg)?),
+            "--errors" => self.errors = Some(parse_value(args, arg)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    pub fn init_logging(&self) -> Result<()> {
+        logging::init(
+            self.log_level.unwrap_or(logging::DEFAULT_LOG_LEVEL),
+            self.log_file.as_deref(),
+        )
+    }
+
+    // The config file, or the defaults, with the flags applied over it.
+    pub fn engine_config(&self) -> Result<EngineConfig> {
+        let mut config = match &self.config {
+            Some(path) => EngineConfig::from_file(path)?,
+            None => EngineConfig::default(),
+        };
+        if let Some(count) = self.shard_count {
+            config.storage.shards = count;
+        }
+        if let Some(capacity) = self.cache_capacity {
+            config.storage.cache_capacity = capacity.get();
+        }
+        if let Some(places) = self.amount_scale {
+            config.amount_scale = places;
+        }
+        if let Some(days) = self.dispute_window {
+            config.dispute_window = Some(days);
+        }
+        if let Some(accounts) = self.accounts {
+            config.accounts = accounts;
+        }
+        if let Some(locked) = self.locked_accounts {
+            config.locked_accounts = locked;
+        }
+        if let Some(errors) = self.errors {
+            config.errors = errors;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn build_engine(&self) -> Result<ToyEngine> {
+        let mut builder = self.builder_factory()?(&TenantConfig::default());
+        if let Some(path) = &self.archive {
+            // Kept along with the ledger the engine is rebuilt from.
+            let archive = match self.ledger {
+                Some(_) => TransactionArchive::open(path)?,
+                None => TransactionArchive::create(path)?,
+            };
+            builder = builder.with_archive(archive);
+        }
+        if let Some(path) = &self.events {
+            builder = builder.with_observer(JsonLinesObserver::create(path)?);
+        }
+        if let Some(path) = &self.ledger {
+            // An existing ledger is picked up where it stopped.
+            let ledger = LedgerFile::open(path)?;
+            builder = builder
+                .with_history(LedgerFile::read(path)?)
+                .with_ledger(ledger);
+        }
+        if let Some(path) = &self.outbox {
+            builder = builder.with_ledger(Outbox::open(path)?);
+        }
+        builder.build()
+    }
+
+    // Reads the configuration files once and returns a function building
+    // engines from them, with the settings of a tenant taking precedence.
+    pub fn engine_factory(&self) -> Result<impl Fn(&TenantConfig) -> Result<ToyEngine> + 'static> {
+        let builder = self.builder_factory()?;
+        Ok(move |tenant: &TenantConfig| builder(tenant).build())
+    }
+
+    fn builder_factory(&self) -> Result<impl Fn(&TenantConfig) -> EngineBuilder + 'static> {
+        let fees = match &self.fees {
+            Some(path) => FeeSchedule::from_file(path)?,
+            None => FeeSchedule::default(),
+        };
+        let limits = match &self.limits {
+            Some(path) => LimitsConfig::from_file(path)?,
+            None => LimitsConfig::default(),
+        };
+        let risk = match &self.risk {
+            Some(path) => RiskConfig::from_file(path)?,
+            None => RiskConfig::default(),
+        };
+
+        let registry = match &self.registry {
+            Some(path) => ClientRegistry::from_file(path)?,
+            None => ClientRegistry::default(),
+        };
+        let config = self.engine_config()?;
+        if config.accounts == AccountPolicy::Registered && self.registry.is_none() {
+            return Err(anyhow!("--accounts registered requires --registry"));
+        }
+
+        let log_evictions = self.log_evictions;
+        Ok(move |tenant: &TenantConfig| {
+            let config = EngineConfig {
+                dispute_window: tenant.dispute_window.unwrap_or(config.dispute_window),
+                ..config.clone()
+            };
+            ToyEngine::builder()
+                .with_config(config)
+                .with_eviction_logging(log_evictions)
+                .with_fees(tenant.fees.clone().unwrap_or_else(|| fees.clone()))
+                .with_registry(registry.clone())
+                .with_limits(limits.clone())
+                .with_risk(risk.clone())
+        })
+    }
+}
+
+fn parse_output_flag(
+    output: &mut OutputOptions,
+    arg: &str,
+    args: &mut impl Iterator<Item = String>,
+) -> Result<bool> {
+    match arg {
+        "--sort" => output.sort = Some(parse_value(args, arg)?),
+        "--desc" => output.descending = true,
+        "--locked-only" => output.locked_only = true,
+        "--non-zero" => output.non_zero = true,
+        "--clients" => output.clients = Some(parse_list(args, arg)?.into_iter().collect()),
+        "--min-total" => output.min_total = Some(parse_value(args, arg)?),
+        "--columns" => output.columns = Some(parse_list(args, arg)?),
+        "--footer" => output.footer = true,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn parse_list<T: std::str::FromStr>(
+    args: &mut impl Iterator<Item = String>,
+    flag: &str,
+) -> Result<Vec<T>> {
+    let value = flag_value(args, flag)?;
+    value
+        .split(',')
+        .map(|item| {
+            item.trim()
+                .parse()
+                .map_err(|_| anyhow!("Invalid value for {}: {}", flag, item))
+        })
+        .collect()
+}
+
+fn flag_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String> {
+    args.next()
+        .ok_or_else(|| anyhow!("Missing value for {}", flag))
+}
+
+fn parse_value<T: std::str::FromStr>(
+    args: &mut impl Iterator<Item = String>,
+    flag: &str,
+) -> Result<T> {
+    let value = flag_value(args, flag)?;
+    value
+        .parse()
+        .map_err(|_| anyhow!("Invalid value for {}: {}", flag, value))
 }
@@ -0,0 +1,214 @@
+use crate::models::{ids::TxId, transaction::Transaction};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+/// A transaction evicted from the caches, as the engine keeps it. Opaque, but
+/// serializable for backends keeping it out of memory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ArchivedTransaction(pub(crate) Transaction);
+
+#[derive(Serialize, Deserialize)]
+struct ArchiveLine {
+    tx: TxId,
+    #[serde(flatten)]
+    transaction: Transaction,
+}
+
+/// Where the transactions evicted from the LRU caches go, to be found again
+/// by disputes, resolves, chargebacks and lookups. A transaction can be
+/// evicted several times: `get` must return the latest version appended.
+pub trait ArchiveBackend: Send {
+    fn append(&mut self, tx_id: TxId, transaction: ArchivedTransaction) -> Result<()>;
+    fn get(&self, tx_id: TxId) -> Result<Option<ArchivedTransaction>>;
+}
+
+/// Append-only JSON lines file of the transactions evicted from the LRU
+/// caches, so that disputes on them still find them.
+///
+/// A transaction evicted several times is appended each time, the in-memory
+/// index pointing at its latest line. `create` starts an empty file, `open`
+/// keeps the transactions already archived, for an engine rebuilt from its
+/// ledger. The index keeps the id and offset of every archived transaction
+/// in memory, which grows with the archive: some tens of bytes each.
+#[derive(Debug)]
+pub struct TransactionArchive {
+    file: File,
+    index: HashMap<TxId, u64>,
+    end: u64,
+}
+
+impl TransactionArchive {
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .with_context(|| format!("Cannot create archive {}", path.display()))?;
+
+        Ok(Self {
+            file,
+            index: HashMap::new(),
+            end: 0,
+        })
+    }
+
+    /// Opens `path`, created if missing, and indexes the transactions in it.
+    /// A last line cut short by a crash is overwritten by the next append.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| format!("Cannot open archive {}", path.display()))?;
+
+        let mut index = HashMap::new();
+        let mut end = 0;
+        let mut reader = BufReader::new(&file);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 || !line.ends_with('\n') {
+                break;
+            }
+            let archived: ArchiveLine = serde_json::from_str(&line)
+                .with_context(|| format!("Corrupted archive {} at byte {}", path.display(), end))?;
+            index.insert(archived.tx, end);
+            end += read as u64;
+        }
+
+        Ok(Self { file, index, end })
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+}
+
+impl ArchiveBackend for TransactionArchive {
+    fn append(&mut self, tx_id: TxId, transaction: ArchivedTransaction) -> Result<()> {
+        let mut line = serde_json::to_string(&ArchiveLine {
+            tx: tx_id,
+            transaction: transaction.0,
+        })?;
+        line.push('\n');
+
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(line.as_bytes())?;
+        self.index.insert(tx_id, self.end);
+        self.end += line.len() as u64;
+        Ok(())
+    }
+
+    fn get(&self, tx_id: TxId) -> Result<Option<ArchivedTransaction>> {
+        let Some(&offset) = self.index.get(&tx_id) else {
+            return Ok(None);
+        };
+
+        // Reading through `&File` moves the shared cursor, which is fine as
+        // every access seeks first.
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut line = String::new();
+        BufReader::new(file).read_line(&mut line)?;
+
+        let archived: ArchiveLine = serde_json::from_str(&line)?;
+        if archived.tx != tx_id {
+            return Err(anyhow!("Corrupted archive entry for transaction {}", tx_id));
+        }
+        Ok(Some(ArchivedTransaction(archived.transaction)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        ids::ClientId, transaction::DisputeState, transaction_kind::TransactionKind,
+    };
+
+    fn transaction(state: DisputeState) -> ArchivedTransaction {
+        ArchivedTransaction(Transaction {
+            client: ClientId::Num(1),
+            kind: TransactionKind::Deposit,
+            amount: 1_000,
+            state,
+            timestamp: Some("2024-01-01T00:00:00Z".parse().unwrap()),
+            last_used: 0,
+        })
+    }
+
+    #[test]
+    fn test_latest_entry_wins() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut archive = TransactionArchive::create(dir.path().join("archive.jsonl")).unwrap();
+
+        archive
+            .append(TxId::Num(1), transaction(DisputeState::Undisputed))
+            .unwrap();
+        archive
+            .append(TxId::Num(2), transaction(DisputeState::Undisputed))
+            .unwrap();
+        archive
+            .append(TxId::Num(1), transaction(DisputeState::Disputed))
+            .unwrap();
+
+        assert_eq!(archive.len(), 2);
+        let tx = archive.get(TxId::Num(1)).unwrap().unwrap().0;
+        assert_eq!(tx.state, DisputeState::Disputed);
+        assert_eq!(
+            tx.timestamp,
+            transaction(DisputeState::Disputed).0.timestamp
+        );
+        assert_eq!(archive.get(TxId::Num(2)).unwrap().unwrap().0.amount, 1_000);
+        assert!(archive.get(TxId::Num(3)).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_open_keeps_archived_transactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("archive.jsonl");
+        let mut archive = TransactionArchive::create(&path).unwrap();
+        archive
+            .append(TxId::Num(1), transaction(DisputeState::Undisputed))
+            .unwrap();
+        archive
+            .append(TxId::Num(1), transaction(DisputeState::Disputed))
+            .unwrap();
+        drop(archive);
+        // A line cut short by a crash.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"tx\":2,").unwrap();
+
+        let mut archive = TransactionArchive::open(&path).unwrap();
+        assert_eq!(archive.len(), 1);
+        let tx = archive.get(TxId::Num(1)).unwrap().unwrap().0;
+        assert_eq!(tx.state, DisputeState::Disputed);
+
+        archive
+            .append(TxId::Num(2), transaction(DisputeState::Undisputed))
+            .unwrap();
+        let archive = TransactionArchive::open(&path).unwrap();
+        assert_eq!(archive.len(), 2);
+        assert!(archive.get(TxId::Num(2)).unwrap().is_some());
+
+        assert!(TransactionArchive::create(&path).unwrap().is_empty());
+    }
+}
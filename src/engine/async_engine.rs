@@ -0,0 +1,213 @@
+use crate::{
+    engine::{metrics::MetricsSnapshot, sharding::Shards, Outcome, ToyEngine, TransactionConflict},
+    models::{
+        ids::{ClientId, TxId},
+        input_transaction::InputTransaction,
+        output_record::OutputRecord,
+        transaction_kind::TransactionKind,
+    },
+};
+use anyhow::{anyhow, Result};
+use lru::LruCache;
+use std::{
+    collections::BTreeMap,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::{mpsc, oneshot};
+
+const QUEUE_CAPACITY: usize = 1024;
+
+enum Command {
+    Dispatch(InputTransaction, oneshot::Sender<Result<Outcome>>),
+    Metrics(oneshot::Sender<MetricsSnapshot>),
+    Shutdown(oneshot::Sender<Vec<OutputRecord>>),
+}
+
+/// Cloneable handle over one actor task per shard. Transactions are routed to
+/// their client's shard like `Storage` does, so a client's transactions are
+/// applied in submission order while different shards make progress
+/// concurrently.
+///
+/// Each shard owns its own `ToyEngine`, with its share of the cache capacity,
+/// and collects fees on its own house account, summed up on shutdown. The
+/// handle remembers which client used each deposit and withdrawal id, as many
+/// as the caches hold, so that an id reused by a client of another shard is
+/// rejected as a conflict too.
+#[derive(Clone)]
+pub struct AsyncEngine {
+    shards: Arc<Shards<mpsc::Sender<Command>>>,
+    owners: Arc<Mutex<LruCache<TxId, ClientId>>>,
+}
+
+impl AsyncEngine {
+    /// Must be called from within a Tokio runtime.
+    pub fn spawn(shard_count: usize, make_engine: impl Fn() -> ToyEngine) -> Self {
+        let shard_count = shard_count.max(1);
+        let mut total = 0;
+        let senders = (0..shard_count)
+            .map(|_| {
+                let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+                let engine = make_engine();
+                // The capacity the engine was configured with is shared by
+                // all the actors rather than given to each.
+                let capacity = engine.store.total_cache_capacity();
+                total = total.max(capacity);
+                let capacity =
+                    NonZeroUsize::new(capacity.div_ceil(shard_count)).unwrap_or(NonZeroUsize::MIN);
+                let engine = engine.with_shard_count(1).with_cache_capacity(capacity);
+                tokio::spawn(run_shard(engine, receiver));
+                sender
+            })
+            .collect();
+
+        let owners = LruCache::new(NonZeroUsize::new(total).unwrap_or(NonZeroUsize::MIN));
+        Self {
+            shards: Arc::new(Shards::new(senders)),
+            owners: Arc::new(Mutex::new(owners)),
+        }
+    }
+
+    pub async fn dispatch(&self, tx: impl Into<InputTransaction>) -> Result<Outcome> {
+        let tx = tx.into();
+        let claimed = self.claim(&tx)?;
+        let (reply, outcome) = oneshot::channel();
+        let (tx_id, client) = (tx.tx, tx.client.clone());
+        let sent = self
+            .shards
+            .get(&tx.client)
+            .send(Command::Dispatch(tx, reply))
+            .await;
+        let result = match sent {
+            Ok(()) => outcome.await.map_err(|_| anyhow!("Engine is shut down"))?,
+            Err(_) => Err(anyhow!("Engine is shut down")),
+        };
+        if claimed && !matches!(result, Ok(Outcome::Applied)) {
+            self.release(tx_id, &client);
+        }
+        result
+    }
+
+    // Fails when another client already used the id of a deposit or
+    // withdrawal, otherwise records `tx` as its user. True if it was not yet.
+    fn claim(&self, tx: &InputTransaction) -> Result<bool> {
+        let kind = tx.transaction_type.parse::<TransactionKind>();
+        if !matches!(
+            kind,
+            Ok(TransactionKind::Deposit | TransactionKind::Withdrawal)
+        ) {
+            return Ok(false);
+        }
+        let mut owners = self.owners.lock().map_err(|_| anyhow!("Engine poisoned"))?;
+        match owners.get(&tx.tx) {
+            Some(owner) if *owner != tx.client => Err(TransactionConflict { tx: tx.tx }.into()),
+            Some(_) => Ok(false),
+            None => {
+                owners.put(tx.tx, tx.client.clone());
+                Ok(true)
+            }
+        }
+    }
+
+    fn release(&self, tx: TxId, client: &ClientId) {
+        if let Ok(mut owners) = self.owners.lock() {
+            if owners.peek(&tx) == Some(client) {
+                owners.pop(&tx);
+            }
+        }
+    }
+
+    /// Commands waiting in the queue of each shard.
+    pub fn queue_depths(&self) -> Vec<usize> {
+        self.shards
+            .shards_slices()
+            .iter()
+            .map(|shard| shard.max_capacity() - shard.capacity())
+            .collect()
+    }
+
+    /// The metrics of all shards added up, each shard with its queue depth
+    /// as of the call. Answered by the shards after the transactions already
+    /// in their queue.
+    pub async fn metrics(&self) -> Result<MetricsSnapshot> {
+        let depths = self.queue_depths();
+        let mut pending = Vec::new();
+        for shard in self.shards.shards_slices() {
+            let (reply, metrics) = oneshot::channel();
+            shard
+                .send(Command::Metrics(reply))
+                .await
+                .map_err(|_| anyhow!("Engine is shut down"))?;
+            pending.push(metrics);
+        }
+
+        let mut merged = MetricsSnapshot::default();
+        let mut shards = Vec::new();
+        for (metrics, depth) in pending.into_iter().zip(depths) {
+            let mut metrics = metrics.await.map_err(|_| anyhow!("Engine is shut down"))?;
+            // Every engine has a single shard of its own, not to be added up
+            // with the others.
+            for shard in &mut metrics.shards {
+                shard.queue_depth = Some(depth);
+            }
+            shards.append(&mut metrics.shards);
+            merged.merge(&metrics);
+        }
+        merged.shards = shards;
+        Ok(merged)
+    }
+
+    /// Waits for every transaction submitted so far to be applied, stops the
+    /// shards and returns the final accounts. Later submissions, from any
+    /// clone of the handle, fail.
+    pub async fn shutdown(self) -> Result<Vec<OutputRecord>> {
+        let mut pending = Vec::new();
+        for shard in self.shards.shards_slices() {
+            let (reply, accounts) = oneshot::channel();
+            shard
+                .send(Command::Shutdown(reply))
+                .await
+                .map_err(|_| anyhow!("Engine is already shut down"))?;
+            pending.push(accounts);
+        }
+
+        let mut merged: BTreeMap<ClientId, OutputRecord> = BTreeMap::new();
+        for accounts in pending {
+            let accounts = accounts
+                .await
+                .map_err(|_| anyhow!("Engine is already shut down"))?;
+            for record in accounts {
+                match merged.get_mut(&record.client) {
+                    Some(existing) => {
+                        existing.available += record.available;
+                        existing.held += record.held;
+                        existing.total += record.total;
+                        existing.locked |= record.locked;
+                    }
+                    None => {
+                        merged.insert(record.client.clone(), record);
+                    }
+                }
+            }
+        }
+
+        Ok(merged.into_values().collect())
+    }
+}
+
+async fn run_shard(mut engine: ToyEngine, mut commands: mpsc::Receiver<Command>) {
+    while let Some(command) = commands.recv().await {
+        match command {
+            Command::Dispatch(tx, reply) => {
+                let _ = reply.send(engine.dispatch(tx));
+            }
+            Command::Metrics(reply) => {
+                let _ = reply.send(engine.metrics());
+            }
+            Command::Shutdown(reply) => {
+                let _ = reply.send(engine.get_all_accounts());
+                break;
+            }
+        }
+    }
+}
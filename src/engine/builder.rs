@@ -0,0 +1,128 @@
+use crate::{
+    engine::{
+        archive::ArchiveBackend,
+        clock::Clock,
+        config::EngineConfig,
+        fees::FeeSchedule,
+        ledger::{LedgerBackend, LedgerRecord},
+        observer::EngineObserver,
+        registry::ClientRegistry,
+        risk::RiskConfig,
+        rules::LimitsConfig,
+        DispatchHook, Outcome, ToyEngine,
+    },
+    models::input_transaction::InputTransaction,
+};
+use anyhow::Result;
+
+/// Collects everything an engine is made of, checking the config only when
+/// the engine is built, so that settings can be given in any order.
+#[derive(Default)]
+pub struct EngineBuilder {
+    config: EngineConfig,
+    fees: FeeSchedule,
+    limits: LimitsConfig,
+    risk: RiskConfig,
+    registry: ClientRegistry,
+    archive: Option<Box<dyn ArchiveBackend>>,
+    log_evictions: bool,
+    clock: Option<Box<dyn Clock>>,
+    hooks: Vec<DispatchHook>,
+    observers: Vec<Box<dyn EngineObserver>>,
+    ledgers: Vec<Box<dyn LedgerBackend>>,
+    history: Vec<LedgerRecord>,
+}
+
+impl EngineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_config(mut self, config: EngineConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn with_fees(mut self, fees: FeeSchedule) -> Self {
+        self.fees = fees;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: LimitsConfig) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_risk(mut self, risk: RiskConfig) -> Self {
+        self.risk = risk;
+        self
+    }
+
+    pub fn with_registry(mut self, registry: ClientRegistry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Where transactions evicted from the in-memory caches go. They are
+    /// dropped when no archive is set.
+    pub fn with_archive(mut self, archive: impl ArchiveBackend + 'static) -> Self {
+        self.archive = Some(Box::new(archive));
+        self
+    }
+
+    pub fn with_eviction_logging(mut self, enabled: bool) -> Self {
+        self.log_evictions = enabled;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Some(Box::new(clock));
+        self
+    }
+
+    /// Hooks are called in the order they were added.
+    pub fn with_hook(
+        mut self,
+        hook: impl FnMut(&InputTransaction, &Result<Outcome>) + Send + 'static,
+    ) -> Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    pub fn with_ledger(mut self, ledger: impl LedgerBackend + 'static) -> Self {
+        self.ledgers.push(Box::new(ledger));
+        self
+    }
+
+    /// Ledger records to rebuild the state from, see `ToyEngine::from_ledger`.
+    /// The fees and the registry then only open the accounts still missing.
+    pub fn with_history(mut self, records: Vec<LedgerRecord>) -> Self {
+        self.history = records;
+        self
+    }
+
+    /// Observers are notified in the order they were added.
+    pub fn with_observer(mut self, observer: impl EngineObserver + 'static) -> Self {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    pub fn build(self) -> Result<ToyEngine> {
+        // The archive and the eviction logging are there before the replay,
+        // which evicts transactions like the original run did.
+        let mut engine =
+            ToyEngine::from_config(&self.config)?.with_eviction_logging(self.log_evictions);
+        engine.store.archive = self.archive;
+        engine.replay(self.history)?;
+        let mut engine = engine
+            .with_fees(self.fees)
+            .with_registry(self.registry)
+            .with_limits(self.limits)
+            .with_risk(self.risk);
+        engine.clock = self.clock;
+        engine.hooks = self.hooks;
+        engine.observers = self.observers;
+        engine.ledgers = self.ledgers;
+        Ok(engine)
+    }
+}
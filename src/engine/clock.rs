@@ -0,0 +1,23 @@
+use crate::models::timestamp::Timestamp;
+
+/// Stamps the transactions dispatched without a timestamp, so that dispute
+/// windows and daily limits apply to them as well.
+pub trait Clock: Send {
+    fn now(&self) -> Timestamp;
+}
+
+/// The current time of the system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        Timestamp::now()
+    }
+}
+
+impl<F: Fn() -> Timestamp + Send> Clock for F {
+    fn now(&self) -> Timestamp {
+        self()
+    }
+}
@@ -0,0 +1,193 @@
+use crate::engine::{
+    policy::{AccountPolicy, LockedAccountPolicy},
+    sharding::DEFAULT_SHARD_COUNT,
+    storage::{DEFAULT_ACCOUNT_CAPACITY, DEFAULT_CACHE_CAPACITY},
+    utils::{AmountScale, DEFAULT_AMOUNT_SCALE},
+};
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::{fs, num::NonZeroUsize, path::Path, str::FromStr, time::Duration};
+
+/// What the batch binaries do with a malformed row or a rejected transaction.
+/// Ignored transactions are not errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorMode {
+    /// Log it and go on with the next row.
+    #[default]
+    Skip,
+    /// Stop processing and exit with the error.
+    Abort,
+}
+
+impl FromStr for ErrorMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "skip" => Ok(ErrorMode::Skip),
+            "abort" => Ok(ErrorMode::Abort),
+            _ => Err(anyhow!("Unexpected error mode: {}", s)),
+        }
+    }
+}
+
+/// How accounts and transactions are kept in memory.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub shards: usize,
+    /// Transactions kept in memory per shard for disputes.
+    pub cache_capacity: usize,
+    /// Accounts each shard has room for upfront.
+    pub account_capacity: usize,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            shards: DEFAULT_SHARD_COUNT,
+            cache_capacity: DEFAULT_CACHE_CAPACITY,
+            account_capacity: DEFAULT_ACCOUNT_CAPACITY,
+        }
+    }
+}
+
+// A century, well past any card network deadline.
+const MAX_DISPUTE_WINDOW: u64 = 36_500;
+
+/// The engine tunables, the defaults being those of `ToyEngine::new`. Fees,
+/// limits, risk heuristics and the client registry keep their own files.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineConfig {
+    pub storage: StorageConfig,
+    /// Decimal places kept on amounts, extra places being truncated.
+    pub amount_scale: u32,
+    /// In days. Disputes are accepted whatever their age when not set.
+    pub dispute_window: Option<u64>,
+    pub accounts: AccountPolicy,
+    pub locked_accounts: LockedAccountPolicy,
+    pub errors: ErrorMode,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            storage: StorageConfig::default(),
+            amount_scale: DEFAULT_AMOUNT_SCALE,
+            dispute_window: None,
+            accounts: AccountPolicy::default(),
+            locked_accounts: LockedAccountPolicy::default(),
+            errors: ErrorMode::default(),
+        }
+    }
+}
+
+impl EngineConfig {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Cannot read engine config {}", path.display()))?;
+        let config: EngineConfig = toml::from_str(&content)
+            .with_context(|| format!("Invalid engine config {}", path.display()))?;
+        config
+            .validate()
+            .with_context(|| format!("Invalid engine config {}", path.display()))?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.storage.shards == 0 {
+            return Err(anyhow!("storage.shards must be positive"));
+        }
+        self.cache_capacity()?;
+        self.amount_scale()?;
+        self.dispute_max_age()?;
+        Ok(())
+    }
+
+    pub(crate) fn cache_capacity(&self) -> Result<NonZeroUsize> {
+        NonZeroUsize::new(self.storage.cache_capacity)
+            .ok_or_else(|| anyhow!("storage.cache_capacity must be positive"))
+    }
+
+    pub(crate) fn amount_scale(&self) -> Result<AmountScale> {
+        AmountScale::new(self.amount_scale)
+    }
+
+    pub(crate) fn dispute_max_age(&self) -> Result<Option<Duration>> {
+        let Some(days) = self.dispute_window else {
+            return Ok(None);
+        };
+        if days > MAX_DISPUTE_WINDOW {
+            return Err(anyhow!(
+                "dispute_window must be at most {} days, got {}",
+                MAX_DISPUTE_WINDOW,
+                days
+            ));
+        }
+        let secs = days
+            .checked_mul(86_400)
+            .ok_or_else(|| anyhow!("dispute_window of {} days overflows", days))?;
+        Ok(Some(Duration::from_secs(secs)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_from_toml() {
+        let config: EngineConfig = toml::from_str(
+            r#"
+            amount_scale = 4
+            dispute_window = 90
+            accounts = "on-reference"
+            locked_accounts = "accept-deposits"
+            errors = "abort"
+
+            [storage]
+            shards = 2
+            cache_capacity = 500
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.storage.shards, 2);
+        assert_eq!(config.storage.cache_capacity, 500);
+        assert_eq!(config.storage.account_capacity, DEFAULT_ACCOUNT_CAPACITY);
+        assert_eq!(config.accounts, AccountPolicy::OnReference);
+        assert_eq!(config.locked_accounts, LockedAccountPolicy::AcceptDeposits);
+        assert_eq!(config.errors, ErrorMode::Abort);
+        assert!(config.validate().is_ok());
+
+        assert!(toml::from_str::<EngineConfig>("shards = 2").is_err());
+        let invalid = |config: EngineConfig| config.validate().unwrap_err().to_string();
+        let config = EngineConfig {
+            amount_scale: 6,
+            ..EngineConfig::default()
+        };
+        assert_eq!(invalid(config), "amount_scale must be at most 4, got 6");
+        let config = EngineConfig {
+            storage: StorageConfig {
+                cache_capacity: 0,
+                ..StorageConfig::default()
+            },
+            ..EngineConfig::default()
+        };
+        assert_eq!(invalid(config), "storage.cache_capacity must be positive");
+        let config = EngineConfig {
+            dispute_window: Some(u64::MAX),
+            ..EngineConfig::default()
+        };
+        assert_eq!(
+            invalid(config),
+            format!(
+                "dispute_window must be at most 36500 days, got {}",
+                u64::MAX
+            )
+        );
+    }
+}
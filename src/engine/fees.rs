@@ -0,0 +1,339 @@
+use crate::{
+    engine::{
+        ledger::LedgerEvent,
+        storage::Storage,
+        utils::{AmountScale, DecimalToU32},
+    },
+    models::ids::ClientId,
+};
+use anyhow::{anyhow, Context, Result};
+use rust_decimal::{prelude::ToPrimitive, Decimal, RoundingStrategy};
+use serde::Deserialize;
+use std::{fs, path::Path};
+
+/// The name of the house account when the schedule does not set one. Any
+/// number is a possible client id, this name is reserved: the engine rejects
+/// the transactions of the house account, whatever its name.
+pub const DEFAULT_HOUSE_ACCOUNT: &str = "house";
+
+/// Fees charged on withdrawals and chargebacks, credited to the house account.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FeeSchedule {
+    #[serde(default = "default_house_account")]
+    pub house_account: ClientId,
+    pub withdrawal: Option<Fee>,
+    pub chargeback: Option<Fee>,
+}
+
+/// A fee rule, its result clamped to `min` and `max` when set.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Fee {
+    #[serde(flatten)]
+    pub rule: FeeRule,
+    pub min: Option<Decimal>,
+    pub max: Option<Decimal>,
+}
+
+/// How a fee is computed from the transaction amount.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum FeeRule {
+    Flat { amount: Decimal },
+    Percentage { rate: Decimal },
+    Tiered { tiers: Vec<FeeTier> },
+}
+
+/// The rule applied to amounts from `from` on, up to the next tier.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FeeTier {
+    pub from: Decimal,
+    #[serde(flatten)]
+    pub rule: FeeRule,
+}
+
+fn default_house_account() -> ClientId {
+    ClientId::Name(DEFAULT_HOUSE_ACCOUNT.into())
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        Self {
+            house_account: default_house_account(),
+            withdrawal: None,
+            chargeback: None,
+        }
+    }
+}
+
+impl FeeSchedule {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Cannot read fee schedule {}", path.display()))?;
+        let schedule: Self = toml::from_str(&content)
+            .with_context(|| format!("Invalid fee schedule {}", path.display()))?;
+        schedule
+            .validate()
+            .with_context(|| format!("Invalid fee schedule {}", path.display()))?;
+        Ok(schedule)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for (name, fee) in [
+            ("withdrawal", &self.withdrawal),
+            ("chargeback", &self.chargeback),
+        ] {
+            if let Some(fee) = fee {
+                fee.validate()
+                    .with_context(|| format!("Invalid {} fee", name))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.withdrawal.is_none() && self.chargeback.is_none()
+    }
+
+    pub(crate) fn withdrawal_fee(&self, amount: u32, scale: AmountScale) -> Result<u32> {
+        self.withdrawal
+            .as_ref()
+            .map_or(Ok(0), |fee| fee.compute(amount, scale))
+    }
+
+    pub(crate) fn chargeback_fee(&self, amount: u32, scale: AmountScale) -> Result<u32> {
+        self.chargeback
+            .as_ref()
+            .map_or(Ok(0), |fee| fee.compute(amount, scale))
+    }
+}
+
+impl Fee {
+    fn validate(&self) -> Result<()> {
+        self.rule.validate()?;
+        for bound in [self.min, self.max].into_iter().flatten() {
+            if bound < Decimal::ZERO {
+                return Err(anyhow!("Fee bounds cannot be negative"));
+            }
+        }
+        if let (Some(min), Some(max)) = (self.min, self.max) {
+            if min > max {
+                return Err(anyhow!("Fee min {} is above its max {}", min, max));
+            }
+        }
+        Ok(())
+    }
+
+    // Fixed amounts are in currency units, converted with `scale`.
+    pub(crate) fn compute(&self, amount: u32, scale: AmountScale) -> Result<u32> {
+        let mut fee = self.rule.compute(amount, scale)?;
+
+        if let Some(min) = self.min {
+            fee = fee.max(min.decimal_to_u32(scale)?);
+        }
+        if let Some(max) = self.max {
+            fee = fee.min(max.decimal_to_u32(scale)?);
+        }
+
+        Ok(fee)
+    }
+}
+
+impl FeeRule {
+    fn validate(&self) -> Result<()> {
+        match self {
+            FeeRule::Flat { amount } if *amount < Decimal::ZERO => {
+                Err(anyhow!("Flat fee cannot be negative"))
+            }
+            FeeRule::Percentage { rate } if *rate < Decimal::ZERO || *rate > Decimal::ONE => {
+                Err(anyhow!("Fee rate {} is not between 0 and 1", rate))
+            }
+            FeeRule::Flat { .. } | FeeRule::Percentage { .. } => Ok(()),
+            FeeRule::Tiered { tiers } => tiers.iter().try_for_each(|tier| {
+                if tier.from < Decimal::ZERO {
+                    return Err(anyhow!("Fee tier cannot start below 0"));
+                }
+                tier.rule.validate()
+            }),
+        }
+    }
+
+    fn compute(&self, amount: u32, scale: AmountScale) -> Result<u32> {
+        match self {
+            FeeRule::Flat { amount: flat } => flat.decimal_to_u32(scale),
+            FeeRule::Percentage { rate } => {
+                if *rate < Decimal::ZERO {
+                    return Err(anyhow!("Fee rate cannot be negative"));
+                }
+                (Decimal::from(amount) * rate)
+                    .round_dp_with_strategy(0, RoundingStrategy::MidpointAwayFromZero)
+                    .to_u32()
+                    .ok_or_else(|| anyhow!("Fee too large"))
+            }
+            FeeRule::Tiered { tiers } => {
+                let mut selected: Option<&FeeTier> = None;
+                for tier in tiers {
+                    let reached = tier.from.decimal_to_u32(scale)? <= amount;
+                    if reached && selected.is_none_or(|current| tier.from >= current.from) {
+                        selected = Some(tier);
+                    }
+                }
+                selected.map_or(Ok(0), |tier| tier.rule.compute(amount, scale))
+            }
+        }
+    }
+}
+
+// Moves `fee` from the client's available funds to the house account. The
+// fee is capped by what the client actually has, so postings never create
+// money out of thin air.
+pub(crate) fn post_fee(
+    store: &mut Storage,
+    fees: &FeeSchedule,
+    client: &ClientId,
+    fee: u32,
+) -> Result<u64> {
+    if fee == 0 || *client == fees.house_account {
+        return Ok(0);
+    }
+
+    let available = store
+        .pending_account(client)?
+        .map_or(0, |account| account.available);
+    let charged = u64::from(fee).min(available);
+    store.record(LedgerEvent::FeeCharged {
+        client: client.clone(),
+        house: fees.house_account.clone(),
+        amount: store.amount_scale.to_decimal(charged),
+    });
+
+    Ok(charged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn dec(value: &str) -> Decimal {
+        Decimal::from_str(value).unwrap()
+    }
+
+    fn fee(rule: FeeRule) -> Fee {
+        Fee {
+            rule,
+            min: None,
+            max: None,
+        }
+    }
+
+    #[test]
+    fn test_flat_fee() {
+        let fee = fee(FeeRule::Flat {
+            amount: dec("1.25"),
+        });
+        assert_eq!(fee.compute(10_000, AmountScale::default()).unwrap(), 125);
+        assert_eq!(
+            fee.compute(10_000, AmountScale::new(3).unwrap()).unwrap(),
+            1_250
+        );
+    }
+
+    #[test]
+    fn test_percentage_fee_rounds_half_up() {
+        let fee = fee(FeeRule::Percentage { rate: dec("0.015") });
+        assert_eq!(fee.compute(1_000, AmountScale::default()).unwrap(), 15);
+        assert_eq!(fee.compute(100, AmountScale::default()).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_percentage_fee_caps() {
+        let mut fee = fee(FeeRule::Percentage { rate: dec("0.01") });
+        fee.min = Some(dec("0.50"));
+        fee.max = Some(dec("5.00"));
+
+        assert_eq!(fee.compute(1_000, AmountScale::default()).unwrap(), 50);
+        assert_eq!(fee.compute(20_000, AmountScale::default()).unwrap(), 200);
+        assert_eq!(fee.compute(1_000_000, AmountScale::default()).unwrap(), 500);
+    }
+
+    #[test]
+    fn test_tiered_fee_picks_highest_matching_tier() {
+        let fee = fee(FeeRule::Tiered {
+            tiers: vec![
+                FeeTier {
+                    from: dec("100"),
+                    rule: FeeRule::Percentage { rate: dec("0.01") },
+                },
+                FeeTier {
+                    from: dec("0"),
+                    rule: FeeRule::Flat {
+                        amount: dec("0.30"),
+                    },
+                },
+            ],
+        });
+
+        assert_eq!(fee.compute(5_000, AmountScale::default()).unwrap(), 30);
+        assert_eq!(fee.compute(20_000, AmountScale::default()).unwrap(), 200);
+    }
+
+    #[test]
+    fn test_schedule_from_toml() {
+        let schedule: FeeSchedule = toml::from_str(
+            r#"
+            house_account = "acme-fees"
+
+            [withdrawal]
+            kind = "percentage"
+            rate = 0.02
+            min = 1
+
+            [chargeback]
+            kind = "tiered"
+            tiers = [
+                { from = 0, kind = "flat", amount = 15 },
+                { from = 1000, kind = "flat", amount = 25 },
+            ]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(schedule.house_account, ClientId::Name("acme-fees".into()));
+        assert_eq!(
+            schedule
+                .withdrawal_fee(1_000, AmountScale::default())
+                .unwrap(),
+            100
+        );
+        assert_eq!(
+            schedule
+                .chargeback_fee(50_000, AmountScale::default())
+                .unwrap(),
+            1_500
+        );
+        assert_eq!(
+            schedule
+                .chargeback_fee(100_000, AmountScale::default())
+                .unwrap(),
+            2_500
+        );
+    }
+
+    #[test]
+    fn test_schedule_validation() {
+        let validate = |toml: &str| toml::from_str::<FeeSchedule>(toml).unwrap().validate();
+
+        assert!(validate("[withdrawal]\nkind = \"percentage\"\nrate = 0.02").is_ok());
+        assert!(validate("[withdrawal]\nkind = \"percentage\"\nrate = -0.02").is_err());
+        assert!(validate("[withdrawal]\nkind = \"percentage\"\nrate = 1.5").is_err());
+        assert!(validate("[chargeback]\nkind = \"flat\"\namount = 5\nmin = 2\nmax = 1").is_err());
+        assert!(validate(
+            "[chargeback]\nkind = \"tiered\"\ntiers = [{ from = 0, kind = \"flat\", amount = -1 }]"
+        )
+        .is_err());
+
+        let schedule = FeeSchedule::default();
+        assert_eq!(schedule.house_account, ClientId::Name("house".into()));
+    }
+}
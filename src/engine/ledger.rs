@@ -0,0 +1,322 @@
+use crate::{
+    engine::observer::LockReason,
+    models::{
+        ids::{ClientId, TxId},
+        timestamp::Timestamp,
+        transaction_kind::TransactionKind,
+    },
+};
+use anyhow::{anyhow, Context, Result};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    iter,
+    path::{Path, PathBuf},
+};
+
+/// A change to the accounts. The handlers never mutate an account themselves:
+/// they record these events, and the storage state is their fold, so that the
+/// same events rebuild an engine or feed projections.
+///
+/// Events carry everything needed to apply them, amounts included, and do not
+/// depend on the engine configuration but for the amount scale.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LedgerEvent {
+    /// An empty account opened by a reference, see `AccountPolicy::OnReference`.
+    /// Deposits open accounts on their own.
+    AccountOpened { client: ClientId },
+    Deposited {
+        client: ClientId,
+        tx: TxId,
+        amount: Decimal,
+        timestamp: Option<Timestamp>,
+    },
+    Withdrawn {
+        client: ClientId,
+        tx: TxId,
+        amount: Decimal,
+        timestamp: Option<Timestamp>,
+    },
+    /// Moved from the client's available funds to the house account.
+    FeeCharged {
+        client: ClientId,
+        house: ClientId,
+        amount: Decimal,
+    },
+    DisputeOpened {
+        client: ClientId,
+        tx: TxId,
+        amount: Decimal,
+    },
+    DisputeResolved {
+        client: ClientId,
+        tx: TxId,
+        amount: Decimal,
+    },
+    ChargedBack {
+        client: ClientId,
+        tx: TxId,
+        amount: Decimal,
+    },
+    AccountLocked {
+        client: ClientId,
+        reason: LockReason,
+    },
+}
+
+impl LedgerEvent {
+    // The accounts the event changes, the paying client first for fees.
+    pub(crate) fn accounts(&self) -> impl Iterator<Item = &ClientId> {
+        let (client, house) = match self {
+            LedgerEvent::FeeCharged { client, house, .. } => (client, Some(house)),
+            LedgerEvent::AccountOpened { client }
+            | LedgerEvent::Deposited { client, .. }
+            | LedgerEvent::Withdrawn { client, .. }
+            | LedgerEvent::DisputeOpened { client, .. }
+            | LedgerEvent::DisputeResolved { client, .. }
+            | LedgerEvent::ChargedBack { client, .. }
+            | LedgerEvent::AccountLocked { client, .. } => (client, None),
+        };
+        iter::once(client).chain(house)
+    }
+}
+
+/// The events recorded while dispatching one transaction. Dispatches changing
+/// nothing leave no record.
+///
+/// Accounts opened by the engine configuration, the house account and the
+/// registered clients, are recorded without a transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerRecord {
+    /// Starts at 1 and grows by one with every record.
+    pub seq: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx: Option<TxId>,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<TransactionKind>,
+    pub events: Vec<LedgerEvent>,
+}
+
+/// Where the engine appends its records, in order.
+pub trait LedgerBackend: Send {
+    fn append(&mut self, record: &LedgerRecord) -> Result<()>;
+
+    /// Takes back `record`, the last one appended, when another backend of
+    /// the engine could not append it. Keeps it by default.
+    fn revert(&mut self, _record: &LedgerRecord) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A point of the ledger to rebuild the state at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointInTime {
+    /// Up to this record included.
+    Seq(u64),
+    /// Up to the deposit or withdrawal of this transaction included, before
+    /// any dispute of it.
+    Tx(TxId),
+}
+
+/// The records up to `point`, all of them when it is never reached.
+pub fn as_of(records: &[LedgerRecord], point: PointInTime) -> &[LedgerRecord] {
+    let end = match point {
+        PointInTime::Seq(seq) => records.partition_point(|record| record.seq <= seq),
+        PointInTime::Tx(tx) => records
+            .iter()
+            .position(|record| {
+                record.tx == Some(tx)
+                    && matches!(
+                        record.kind,
+                        Some(TransactionKind::Deposit | TransactionKind::Withdrawal)
+                    )
+            })
+            .map_or(records.len(), |position| position + 1),
+    };
+    &records[..end]
+}
+
+// Reads the complete records of `file`, returning them and the position
+// after the last one. A line left incomplete by a crash has no newline and
+// is not read.
+fn read_records(file: &mut File, path: &Path) -> Result<(Vec<LedgerRecord>, u64)> {
+    let mut reader = BufReader::new(file);
+    let mut records = Vec::new();
+    let mut end = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 || !line.ends_with('\n') {
+            break;
+        }
+        let record = serde_json::from_str(&line).with_context(|| {
+            format!(
+                "Invalid ledger record at {}:{}",
+                path.display(),
+                records.len() + 1
+            )
+        })?;
+        records.push(record);
+        end += read as u64;
+    }
+    Ok((records, end))
+}
+
+/// JSON lines file of the ledger records, one per line.
+///
+/// The file is appended to: the records must go on from its last one, which
+/// an engine rebuilt from the same file does.
+#[derive(Debug)]
+pub struct LedgerFile {
+    file: File,
+    path: PathBuf,
+    // The size of the file up to the last complete record, and before it.
+    end: u64,
+    previous_end: u64,
+    last_seq: u64,
+}
+
+impl LedgerFile {
+    /// Creates the file when missing, dropping a record left incomplete by a
+    /// crash otherwise.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .with_context(|| format!("Cannot open ledger {}", path.display()))?;
+        let (records, end) = read_records(&mut file, path)?;
+        file.set_len(end)?;
+
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+            end,
+            previous_end: end,
+            last_seq: records.last().map_or(0, |record| record.seq),
+        })
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Vec<LedgerRecord>> {
+        let path = path.as_ref();
+        let mut file =
+            File::open(path).with_context(|| format!("Cannot open ledger {}", path.display()))?;
+        Ok(read_records(&mut file, path)?.0)
+    }
+
+    /// The sequence number of the last record, 0 when there is none.
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+}
+
+impl LedgerBackend for LedgerFile {
+    fn append(&mut self, record: &LedgerRecord) -> Result<()> {
+        if record.seq != self.last_seq + 1 {
+            return Err(anyhow!(
+                "Ledger {} ends at record {}, cannot append record {}",
+                self.path.display(),
+                self.last_seq,
+                record.seq
+            ));
+        }
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        if let Err(e) = self.file.write_all(&line).and_then(|()| self.file.flush()) {
+            // A partial line would be glued to the next record.
+            let _ = self.file.set_len(self.end);
+            return Err(e).with_context(|| format!("Cannot write ledger {}", self.path.display()));
+        }
+        self.previous_end = self.end;
+        self.end += line.len() as u64;
+        self.last_seq = record.seq;
+        Ok(())
+    }
+
+    fn revert(&mut self, record: &LedgerRecord) -> Result<()> {
+        if record.seq != self.last_seq || self.end == self.previous_end {
+            return Err(anyhow!(
+                "Record {} is not the last one appended",
+                record.seq
+            ));
+        }
+        self.file.set_len(self.previous_end)?;
+        self.end = self.previous_end;
+        self.last_seq -= 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(seq: u64, tx: u32, kind: TransactionKind) -> LedgerRecord {
+        LedgerRecord {
+            seq,
+            tx: Some(tx.into()),
+            kind: Some(kind),
+            events: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_as_of() {
+        let records = [
+            record(1, 1, TransactionKind::Deposit),
+            record(2, 2, TransactionKind::Deposit),
+            record(3, 1, TransactionKind::Dispute),
+            record(4, 3, TransactionKind::Withdrawal),
+        ];
+        let seqs = |point| {
+            as_of(&records, point)
+                .iter()
+                .map(|record| record.seq)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(seqs(PointInTime::Seq(2)), [1, 2]);
+        assert_eq!(seqs(PointInTime::Seq(9)), [1, 2, 3, 4]);
+        assert_eq!(seqs(PointInTime::Tx(1u32.into())), [1]);
+        assert_eq!(seqs(PointInTime::Tx(3u32.into())), [1, 2, 3, 4]);
+        assert_eq!(seqs(PointInTime::Tx(7u32.into())), [1, 2, 3, 4]);
+        assert_eq!(seqs(PointInTime::Seq(0)), [] as [u64; 0]);
+    }
+
+    #[test]
+    fn test_ledger_file_goes_on_from_its_last_record() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("ledger.jsonl");
+
+        let mut ledger = LedgerFile::open(&path)?;
+        ledger.append(&record(1, 1, TransactionKind::Deposit))?;
+        ledger.append(&record(2, 2, TransactionKind::Deposit))?;
+
+        // A line left incomplete is not read, then dropped on reopening.
+        OpenOptions::new()
+            .append(true)
+            .open(&path)?
+            .write_all(b"{\"seq\":3")?;
+        assert_eq!(LedgerFile::read(&path)?.len(), 2);
+        let mut ledger = LedgerFile::open(&path)?;
+        assert_eq!(ledger.last_seq(), 2);
+        assert!(ledger
+            .append(&record(1, 3, TransactionKind::Withdrawal))
+            .is_err());
+        ledger.append(&record(3, 3, TransactionKind::Withdrawal))?;
+
+        let records = LedgerFile::read(&path)?;
+        assert_eq!(
+            records.iter().map(|record| record.seq).collect::<Vec<_>>(),
+            [1, 2, 3]
+        );
+        assert_eq!(records[2], record(3, 3, TransactionKind::Withdrawal));
+        Ok(())
+    }
+}
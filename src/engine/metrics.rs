@@ -0,0 +1,387 @@
+use std::{collections::BTreeMap, fmt::Write, time::Duration};
+
+/// Upper bounds of the latency histogram buckets, in microseconds.
+pub const LATENCY_BUCKETS_US: [u64; 8] = [10, 25, 50, 100, 250, 500, 1_000, 10_000];
+
+/// Counts per bucket are not cumulative, the last one counting everything
+/// above the highest bound.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Histogram {
+    counts: [u64; LATENCY_BUCKETS_US.len() + 1],
+    sum_us: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, latency: Duration) {
+        let us = latency.as_micros() as u64;
+        let bucket = LATENCY_BUCKETS_US
+            .iter()
+            .position(|&bound| us <= bound)
+            .unwrap_or(LATENCY_BUCKETS_US.len());
+        self.counts[bucket] += 1;
+        self.sum_us = self.sum_us.saturating_add(us);
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        for (count, other) in self.counts.iter_mut().zip(other.counts) {
+            *count += other;
+        }
+        self.sum_us = self.sum_us.saturating_add(other.sum_us);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn sum_us(&self) -> u64 {
+        self.sum_us
+    }
+
+    /// Upper bound of the bucket holding the `q` quantile, `None` when it is
+    /// above the highest bound or nothing was observed.
+    pub fn quantile_us(&self, q: f64) -> Option<u64> {
+        let rank = (self.count() as f64 * q).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return LATENCY_BUCKETS_US.get(bucket).copied();
+            }
+        }
+        None
+    }
+}
+
+// What the engine measures while processing, the rest of the metrics being
+// read from its state when a snapshot is taken.
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    transactions: BTreeMap<(&'static str, &'static str), u64>,
+    latency: Histogram,
+}
+
+impl Metrics {
+    pub(crate) fn record(&mut self, kind: &'static str, outcome: &'static str, latency: Duration) {
+        *self.transactions.entry((kind, outcome)).or_default() += 1;
+        self.latency.observe(latency);
+    }
+
+    pub(crate) fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            transactions: self.transactions.clone(),
+            latency: self.latency.clone(),
+            ..MetricsSnapshot::default()
+        }
+    }
+}
+
+/// The transaction cache of a shard, and its queue.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShardMetrics {
+    pub cached_transactions: usize,
+    pub evictions: u64,
+    /// Transactions waiting to be applied by the shard, for the modes
+    /// queueing them.
+    pub queue_depth: Option<usize>,
+}
+
+/// The counters of an engine at some point.
+#[derive(Debug, Clone, Default)]
+pub struct MetricsSnapshot {
+    /// Keyed by transaction type and outcome (`applied`, `duplicate`,
+    /// `ignored` or `rejected`). Rows with an unexpected type are counted as
+    /// `unknown`.
+    pub transactions: BTreeMap<(&'static str, &'static str), u64>,
+    pub latency: Histogram,
+    pub accounts: usize,
+    pub locked_accounts: usize,
+    pub shards: Vec<ShardMetrics>,
+}
+
+impl MetricsSnapshot {
+    pub fn count(&self, kind: &str, outcome: &str) -> u64 {
+        self.transactions
+            .get(&(kind, outcome))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn total(&self) -> u64 {
+        self.transactions.values().sum()
+    }
+
+    /// Applied disputes per applied deposit.
+    pub fn dispute_rate(&self) -> Option<f64> {
+        self.ratio("dispute", "deposit")
+    }
+
+    /// Applied resolves per applied dispute.
+    pub fn resolve_rate(&self) -> Option<f64> {
+        self.ratio("resolve", "dispute")
+    }
+
+    /// Applied chargebacks per applied dispute.
+    pub fn chargeback_rate(&self) -> Option<f64> {
+        self.ratio("chargeback", "dispute")
+    }
+
+    fn ratio(&self, kind: &str, of: &str) -> Option<f64> {
+        match self.count(of, "applied") {
+            0 => None,
+            of => Some(self.count(kind, "applied") as f64 / of as f64),
+        }
+    }
+
+    /// Adds up the metrics of several engines, shard by shard.
+    pub fn merge(&mut self, other: &MetricsSnapshot) {
+        for (key, count) in &other.transactions {
+            *self.transactions.entry(*key).or_default() += count;
+        }
+        self.latency.merge(&other.latency);
+        self.accounts += other.accounts;
+        self.locked_accounts += other.locked_accounts;
+        if self.shards.len() < other.shards.len() {
+            self.shards
+                .resize(other.shards.len(), ShardMetrics::default());
+        }
+        for (shard, other) in self.shards.iter_mut().zip(&other.shards) {
+            shard.cached_transactions += other.cached_transactions;
+            shard.evictions += other.evictions;
+            shard.queue_depth = match (shard.queue_depth, other.queue_depth) {
+                (None, None) => None,
+                (depth, other) => Some(depth.unwrap_or_default() + other.unwrap_or_default()),
+            };
+        }
+    }
+
+    /// Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "toypay_transactions_total",
+            "counter",
+            "Processed transactions.",
+        );
+        for ((kind, outcome), count) in &self.transactions {
+            let _ = writeln!(
+                out,
+                "toypay_transactions_total{{type=\"{}\",outcome=\"{}\"}} {}",
+                kind, outcome, count
+            );
+        }
+
+        let rates = [
+            (
+                "toypay_dispute_rate",
+                "Applied disputes per applied deposit.",
+                self.dispute_rate(),
+            ),
+            (
+                "toypay_resolve_rate",
+                "Applied resolves per applied dispute.",
+                self.resolve_rate(),
+            ),
+            (
+                "toypay_chargeback_rate",
+                "Applied chargebacks per applied dispute.",
+                self.chargeback_rate(),
+            ),
+        ];
+        for (name, help, rate) in rates {
+            header(&mut out, name, "gauge", help);
+            let _ = writeln!(out, "{} {}", name, rate.unwrap_or_default());
+        }
+
+        header(&mut out, "toypay_accounts", "gauge", "Open accounts.");
+        let _ = writeln!(out, "toypay_accounts {}", self.accounts);
+        header(
+            &mut out,
+            "toypay_locked_accounts",
+            "gauge",
+            "Locked accounts.",
+        );
+        let _ = writeln!(out, "toypay_locked_accounts {}", self.locked_accounts);
+
+        header(
+            &mut out,
+            "toypay_shard_cached_transactions",
+            "gauge",
+            "Transactions in the LRU cache of each shard.",
+        );
+        for (shard, metrics) in self.shards.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "toypay_shard_cached_transactions{{shard=\"{}\"}} {}",
+                shard, metrics.cached_transactions
+            );
+        }
+        header(
+            &mut out,
+            "toypay_shard_evictions_total",
+            "counter",
+            "Transactions evicted from the LRU cache of each shard.",
+        );
+        for (shard, metrics) in self.shards.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "toypay_shard_evictions_total{{shard=\"{}\"}} {}",
+                shard, metrics.evictions
+            );
+        }
+
+        if self.shards.iter().any(|shard| shard.queue_depth.is_some()) {
+            header(
+                &mut out,
+                "toypay_queue_depth",
+                "gauge",
+                "Transactions waiting to be applied by each shard.",
+            );
+            for (shard, metrics) in self.shards.iter().enumerate() {
+                let _ = writeln!(
+                    out,
+                    "toypay_queue_depth{{shard=\"{}\"}} {}",
+                    shard,
+                    metrics.queue_depth.unwrap_or_default()
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "toypay_dispatch_latency_seconds",
+            "histogram",
+            "Time spent applying a transaction.",
+        );
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS_US.iter().zip(self.latency.counts) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "toypay_dispatch_latency_seconds_bucket{{le=\"{}\"}} {}",
+                *bound as f64 / 1e6,
+                cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "toypay_dispatch_latency_seconds_bucket{{le=\"+Inf\"}} {}",
+            self.latency.count()
+        );
+        let _ = writeln!(
+            out,
+            "toypay_dispatch_latency_seconds_sum {}",
+            self.latency.sum_us() as f64 / 1e6
+        );
+        let _ = writeln!(
+            out,
+            "toypay_dispatch_latency_seconds_count {}",
+            self.latency.count()
+        );
+
+        out
+    }
+
+    /// Human readable summary of a run.
+    pub fn summary(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "transactions: {}", self.total());
+        for ((kind, outcome), count) in &self.transactions {
+            let _ = writeln!(out, "  {} {}: {}", kind, outcome, count);
+        }
+
+        let percent = |rate: Option<f64>| match rate {
+            Some(rate) => format!("{:.2}%", rate * 100.0),
+            None => "n/a".to_string(),
+        };
+        let _ = writeln!(
+            out,
+            "dispute rate: {}, resolve rate: {}, chargeback rate: {}",
+            percent(self.dispute_rate()),
+            percent(self.resolve_rate()),
+            percent(self.chargeback_rate())
+        );
+        let _ = writeln!(
+            out,
+            "accounts: {} ({} locked)",
+            self.accounts, self.locked_accounts
+        );
+        let evictions: u64 = self.shards.iter().map(|shard| shard.evictions).sum();
+        let _ = writeln!(
+            out,
+            "shards: {}, LRU evictions: {}",
+            self.shards.len(),
+            evictions
+        );
+
+        let quantile = |q| match self.latency.quantile_us(q) {
+            Some(us) => format!("<= {}us", us),
+            None if self.latency.count() == 0 => "n/a".to_string(),
+            None => format!("> {}us", LATENCY_BUCKETS_US[LATENCY_BUCKETS_US.len() - 1]),
+        };
+        let _ = writeln!(
+            out,
+            "latency: p50 {}, p99 {}",
+            quantile(0.5),
+            quantile(0.99)
+        );
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets() {
+        let mut histogram = Histogram::default();
+        for us in [5, 10, 11, 400, 20_000] {
+            histogram.observe(Duration::from_micros(us));
+        }
+
+        assert_eq!(histogram.count(), 5);
+        assert_eq!(histogram.sum_us(), 20_426);
+        assert_eq!(histogram.quantile_us(0.4), Some(10));
+        assert_eq!(histogram.quantile_us(0.6), Some(25));
+        assert_eq!(histogram.quantile_us(1.0), None);
+    }
+
+    #[test]
+    fn test_prometheus_text() {
+        let mut metrics = Metrics::default();
+        metrics.record("deposit", "applied", Duration::from_micros(20));
+        metrics.record("deposit", "applied", Duration::from_micros(20));
+        metrics.record("dispute", "applied", Duration::from_micros(300));
+        let mut snapshot = metrics.snapshot();
+        snapshot.shards = vec![ShardMetrics {
+            cached_transactions: 2,
+            evictions: 1,
+            queue_depth: None,
+        }];
+
+        let text = snapshot.to_prometheus();
+        assert!(
+            text.contains("toypay_transactions_total{type=\"deposit\",outcome=\"applied\"} 2\n")
+        );
+        assert!(text.contains("toypay_dispute_rate 0.5\n"));
+        assert!(text.contains("toypay_shard_evictions_total{shard=\"0\"} 1\n"));
+        assert!(text.contains("toypay_dispatch_latency_seconds_bucket{le=\"0.000025\"} 2\n"));
+        assert!(text.contains("toypay_dispatch_latency_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(!text.contains("toypay_queue_depth"));
+
+        snapshot.shards.push(ShardMetrics {
+            queue_depth: Some(3),
+            ..ShardMetrics::default()
+        });
+        let text = snapshot.to_prometheus();
+        assert!(text.contains("toypay_queue_depth{shard=\"0\"} 0\n"));
+        assert!(text.contains("toypay_queue_depth{shard=\"1\"} 3\n"));
+    }
+}
@@ -1,44 +1,574 @@
 use crate::{
     engine::{
+        archive::ArchiveBackend,
+        clock::Clock,
+        config::EngineConfig,
+        fees::FeeSchedule,
+        ledger::{LedgerBackend, LedgerEvent, LedgerRecord},
+        metrics::{Metrics, MetricsSnapshot, ShardMetrics},
+        observer::{applied_amount, notify, EngineObserver, LockReason},
+        policy::{AccountPolicy, DisputePolicy, LockedAccountPolicy},
+        registry::ClientRegistry,
+        risk::{RiskAction, RiskCheckpoint, RiskConfig, RiskFlag, RiskMonitor},
+        rules::{Activity, LimitsConfig, RuleEngine, RulesCheckpoint},
         storage::Storage,
-        transactions::{chargeback, deposit, dispute, resolve, withdrawal},
+        transactions::{chargeback, deposit, dispute, replayed, resolve, withdrawal},
+    },
+    models::{
+        client::{ClientInfo, ClientStatus},
+        ids::{ClientId, TxId},
+        input_transaction::InputTransaction,
+        output_record::{DetailedOutputRecord, OutputRecord},
+        transaction::DisputeState,
+        transaction_kind::TransactionKind,
+        views::{AccountView, TransactionView},
     },
-    models::{input_transaction::InputTransaction, output_record::OutputRecord},
 };
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use std::{num::NonZeroUsize, time::Instant};
+use tracing::{field, info, info_span, warn};
+
+pub use async_engine::AsyncEngine;
+pub use builder::EngineBuilder;
+pub use outcome::{IgnoreReason, Outcome, TransactionConflict};
+pub use sharding::DEFAULT_SHARD_COUNT;
+pub use storage::DEFAULT_CACHE_CAPACITY;
 
-mod sharding;
+pub mod archive;
+pub mod async_engine;
+mod builder;
+pub mod clock;
+pub mod config;
+pub mod fees;
+pub mod ledger;
+pub mod metrics;
+pub mod observer;
+pub mod outbox;
+pub mod outcome;
+pub mod policy;
+pub mod projections;
+pub mod registry;
+pub mod reorder;
+pub mod risk;
+pub mod rules;
+pub(crate) mod sharding;
 mod storage;
+pub mod tenants;
 mod transactions;
-mod utils;
+pub(crate) mod utils;
+
+/// Called after every dispatch with the transaction, as stamped by the clock,
+/// and its result.
+pub type DispatchHook = Box<dyn FnMut(&InputTransaction, &Result<Outcome>) + Send>;
 
+/// Applies transactions to the client accounts, one at a time. Built with
+/// [`ToyEngine::builder`], or [`ToyEngine::from_config`] and the `with_*`
+/// methods.
 pub struct ToyEngine {
     store: Storage,
+    fees: FeeSchedule,
+    disputes: DisputePolicy,
+    accounts: AccountPolicy,
+    locked: LockedAccountPolicy,
+    registry: ClientRegistry,
+    rules: RuleEngine,
+    risk: RiskMonitor,
+    metrics: Metrics,
+    clock: Option<Box<dyn Clock>>,
+    hooks: Vec<DispatchHook>,
+    observers: Vec<Box<dyn EngineObserver>>,
+    ledgers: Vec<Box<dyn LedgerBackend>>,
+    // The sequence number of the last ledger record.
+    ledger_seq: u64,
+    // Accounts opened by the configuration, written to the ledger in a
+    // record of their own before the next dispatch.
+    opened: Vec<LedgerEvent>,
+    // The limits and risk state of the client before the transaction being
+    // dispatched was recorded in them, restored if the ledger refuses it.
+    undo: Option<(RulesCheckpoint, RiskCheckpoint)>,
+    last_events: Vec<LedgerEvent>,
 }
 
 impl ToyEngine {
     pub fn new() -> Self {
         Self {
             store: Storage::new(),
+            fees: FeeSchedule::default(),
+            disputes: DisputePolicy::default(),
+            accounts: AccountPolicy::default(),
+            locked: LockedAccountPolicy::default(),
+            registry: ClientRegistry::default(),
+            rules: RuleEngine::new(LimitsConfig::default()),
+            risk: RiskMonitor::new(RiskConfig::default()),
+            metrics: Metrics::default(),
+            clock: None,
+            hooks: Vec::new(),
+            observers: Vec::new(),
+            ledgers: Vec::new(),
+            ledger_seq: 0,
+            opened: Vec::new(),
+            undo: None,
+            last_events: Vec::new(),
+        }
+    }
+
+    /// An engine with the tunables of `config`, to be completed with the
+    /// builder methods below.
+    pub fn from_config(config: &EngineConfig) -> Result<Self> {
+        config.validate()?;
+        let mut store = Storage::with_capacity(
+            config.storage.shards,
+            config.cache_capacity()?,
+            config.storage.account_capacity,
+        );
+        store.amount_scale = config.amount_scale()?;
+
+        Ok(Self {
+            store,
+            disputes: DisputePolicy {
+                max_age: config.dispute_max_age()?,
+            },
+            locked: config.locked_accounts,
+            ..Self::new()
+        }
+        .with_account_policy(config.accounts))
+    }
+
+    /// An engine in the state left by `records`, to be completed with the
+    /// builder methods below. Accounts, transactions and dispute states come
+    /// back; the history of the limits and of the risk monitor does not.
+    pub fn from_ledger(
+        config: &EngineConfig,
+        records: impl IntoIterator<Item = LedgerRecord>,
+    ) -> Result<Self> {
+        let mut engine = Self::from_config(config)?;
+        engine.replay(records)?;
+        Ok(engine)
+    }
+
+    // Applies ledger records without writing them anywhere.
+    fn replay(&mut self, records: impl IntoIterator<Item = LedgerRecord>) -> Result<()> {
+        for record in records {
+            for event in &record.events {
+                self.store
+                    .apply_event(event)
+                    .with_context(|| format!("Cannot apply ledger record {}", record.seq))?;
+            }
+            self.ledger_seq = record.seq;
         }
+        Ok(())
+    }
+
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::new()
+    }
+
+    pub fn with_shard_count(mut self, shard_count: usize) -> Self {
+        self.rebalance(shard_count);
+        self
+    }
+
+    /// Number of transactions kept in memory per shard for disputes.
+    pub fn with_cache_capacity(mut self, capacity: NonZeroUsize) -> Self {
+        self.store.set_cache_capacity(capacity);
+        self
+    }
+
+    /// Transactions evicted from the caches are spilled to `archive`, where
+    /// disputes, resolves and chargebacks still find them.
+    pub fn with_archive(mut self, archive: impl ArchiveBackend + 'static) -> Self {
+        self.store.archive = Some(Box::new(archive));
+        self
+    }
+
+    /// Logs a warning for every transaction evicted from the caches.
+    pub fn with_eviction_logging(mut self, enabled: bool) -> Self {
+        self.store.log_evictions = enabled;
+        self
+    }
+
+    pub fn with_fees(mut self, fees: FeeSchedule) -> Self {
+        self.fees = fees;
+        self.open_accounts();
+        self
+    }
+
+    pub fn with_account_policy(mut self, accounts: AccountPolicy) -> Self {
+        self.accounts = accounts;
+        self.open_accounts();
+        self
+    }
+
+    pub fn with_registry(mut self, registry: ClientRegistry) -> Self {
+        self.registry = registry;
+        self.open_accounts();
+        self
+    }
+
+    pub fn with_limits(mut self, limits: LimitsConfig) -> Self {
+        self.rules = RuleEngine::new(limits);
+        self
+    }
+
+    pub fn with_dispute_policy(mut self, disputes: DisputePolicy) -> Self {
+        self.disputes = disputes;
+        self
+    }
+
+    pub fn with_locked_account_policy(mut self, locked: LockedAccountPolicy) -> Self {
+        self.locked = locked;
+        self
+    }
+
+    pub fn with_risk(mut self, risk: RiskConfig) -> Self {
+        self.risk = RiskMonitor::new(risk);
+        self
+    }
+
+    /// Where the events of every dispatch go, numbered after the last record
+    /// the engine was rebuilt from, if any. Records go to every backend in the
+    /// order they were added; when one fails, those before it revert theirs.
+    pub fn with_ledger(mut self, ledger: impl LedgerBackend + 'static) -> Self {
+        self.ledgers.push(Box::new(ledger));
+        self
+    }
+
+    pub fn with_observer(mut self, observer: impl EngineObserver + 'static) -> Self {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    // Accounts that exist before any transaction: the house account when
+    // fees are collected and the registered clients.
+    fn open_accounts(&mut self) {
+        let mut clients = Vec::new();
+        if !self.fees.is_empty() {
+            clients.push(self.fees.house_account.clone());
+        }
+        if self.accounts == AccountPolicy::Registered {
+            clients.extend(self.registry.clients().cloned());
+        }
+        for client in clients {
+            if self.store.get_account(&client).is_none() {
+                let event = LedgerEvent::AccountOpened { client };
+                // Opening an account cannot fail.
+                let _ = self.store.apply_event(&event);
+                self.opened.push(event);
+            }
+        }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.store.shard_count()
+    }
+
+    /// Migrates accounts and transactions to `shard_count` shards. Can be
+    /// called at any time between two transactions.
+    pub fn rebalance(&mut self, shard_count: usize) {
+        self.store.rebalance(shard_count.max(1));
     }
 
     pub fn get_all_accounts(&self) -> Vec<OutputRecord> {
         self.store.collect_accounts()
     }
 
-    pub fn dispatch(&mut self, tx: InputTransaction) -> Result<()> {
+    /// The account of `client`, if any. This and the query methods below
+    /// never create accounts nor touch the LRU order of the transaction cache.
+    pub fn account(&self, client: &ClientId) -> Option<AccountView> {
+        self.store
+            .get_account(client)
+            .map(|account| AccountView::new(client.clone(), account, self.store.amount_scale))
+    }
+
+    pub fn transaction(&self, tx: TxId) -> Option<TransactionView> {
+        self.store
+            .find_transaction(tx)
+            .map(|transaction| TransactionView::new(tx, &transaction, self.store.amount_scale))
+    }
+
+    pub fn disputed_transactions(&self, client: &ClientId) -> Vec<TransactionView> {
+        let mut disputed: Vec<TransactionView> = self
+            .store
+            .client_transactions(client)
+            .filter(|(_, tx)| tx.state == DisputeState::Disputed)
+            .map(|(&tx_id, tx)| TransactionView::new(tx_id, tx, self.store.amount_scale))
+            .collect();
+        disputed.sort_by_key(|view| view.tx);
+        disputed
+    }
+
+    pub fn locked_accounts(&self) -> Vec<AccountView> {
+        let mut locked: Vec<AccountView> = self
+            .store
+            .accounts
+            .shards_slices()
+            .iter()
+            .flatten()
+            .filter(|(_, account)| account.locked)
+            .map(|(client, account)| {
+                AccountView::new(client.clone(), account, self.store.amount_scale)
+            })
+            .collect();
+        locked.sort_by(|a, b| a.client.cmp(&b.client));
+        locked
+    }
+
+    pub fn client(&self, client: &ClientId) -> Option<&ClientInfo> {
+        self.registry.get(client)
+    }
+
+    /// Accounts joined with the registry metadata of their client.
+    pub fn get_detailed_accounts(&self) -> Vec<DetailedOutputRecord> {
+        self.store
+            .collect_accounts()
+            .into_iter()
+            .map(|record| {
+                let info = self.registry.get(&record.client);
+                DetailedOutputRecord::new(record, info)
+            })
+            .collect()
+    }
+
+    pub fn metrics(&self) -> MetricsSnapshot {
+        let accounts = self.store.accounts.shards_slices().iter().flatten();
+        let (count, locked) = accounts.fold((0, 0), |(count, locked), (_, account)| {
+            (count + 1, locked + account.locked as usize)
+        });
+        let shards = self
+            .store
+            .transactions
+            .shards_slices()
+            .iter()
+            .zip(&self.store.evictions)
+            .map(|(cache, &evictions)| ShardMetrics {
+                cached_transactions: cache.len(),
+                evictions,
+                queue_depth: None,
+            })
+            .collect();
+
+        MetricsSnapshot {
+            accounts: count,
+            locked_accounts: locked,
+            shards,
+            ..self.metrics.snapshot()
+        }
+    }
+
+    pub fn risk_flags(&self) -> Vec<RiskFlag> {
+        self.risk.flags()
+    }
+
+    pub fn dispatch(&mut self, tx: impl Into<InputTransaction>) -> Result<Outcome> {
+        let mut tx = tx.into();
+        let stamped = match &self.clock {
+            Some(clock) if tx.timestamp.is_none() => {
+                tx.timestamp = Some(clock.now());
+                true
+            }
+            _ => false,
+        };
+
+        let span = info_span!(
+            "dispatch",
+            client = %tx.client,
+            tx = %tx.tx,
+            "type" = %tx.transaction_type,
+            shard = self.store.shard_id(&tx.client),
+            outcome = field::Empty,
+            latency_us = field::Empty,
+        );
+        let _entered = span.enter();
+        let started = Instant::now();
+        let parsed = tx.transaction_type.parse::<TransactionKind>();
+        let kind = parsed.as_ref().map_or("unknown", |kind| kind.as_str());
+
+        let tx_id = tx.tx;
+        let hooked = (!self.hooks.is_empty() || !self.observers.is_empty()).then(|| tx.clone());
+        let result = self.apply(tx, stamped);
+        let undo = self.undo.take();
+        let committed = self.commit(tx_id, parsed.as_ref().ok().copied(), result.is_ok());
+        let (result, events) = match committed {
+            Ok(events) => (result, events),
+            Err(e) => (Err(e), Vec::new()),
+        };
+        if let (Err(_), Some((rules, risk))) = (&result, undo) {
+            self.rules.restore(rules);
+            self.risk.restore(risk);
+        }
+
+        let latency = started.elapsed();
+        span.record("latency_us", latency.as_micros() as u64);
+        let outcome = result.as_ref().map_or("rejected", Outcome::label);
+        self.metrics.record(kind, outcome, latency);
+        match &result {
+            Ok(outcome) => {
+                span.record("outcome", outcome.label());
+                if let Outcome::Ignored(reason) = outcome {
+                    info!(%reason, "transaction ignored");
+                }
+            }
+            Err(e) => {
+                span.record("outcome", "rejected");
+                warn!(error = %e, "transaction rejected");
+            }
+        }
+        if let Some(tx) = hooked {
+            // Observers are told the amount applied, truncated to the amount
+            // scale like in the ledger.
+            let applied = matches!(result, Ok(Outcome::Applied)).then(|| InputTransaction {
+                amount: applied_amount(&events).or(tx.amount),
+                ..tx.clone()
+            });
+            for observer in &mut self.observers {
+                match &result {
+                    Ok(Outcome::Applied) => observer.on_applied(applied.as_ref().unwrap_or(&tx)),
+                    Ok(Outcome::Ignored(reason)) => observer.on_ignored(&tx, *reason),
+                    Ok(Outcome::Duplicate) => continue,
+                    Err(e) => observer.on_rejected(&tx, e),
+                }
+                for event in &events {
+                    notify(observer.as_mut(), event);
+                }
+            }
+            for hook in &mut self.hooks {
+                hook(&tx, &result);
+            }
+        }
+        self.last_events = events;
+        result
+    }
+
+    /// The events the last dispatch applied, as written to the ledger, with
+    /// the amounts actually moved: truncated to the amount scale, fees
+    /// apart. Empty when it changed nothing.
+    pub fn last_events(&self) -> &[LedgerEvent] {
+        &self.last_events
+    }
+
+    // Appends the events recorded while dispatching `tx` to the ledger, then
+    // applies them. When the ledger cannot take them, nothing is applied and
+    // the dispatch fails. Of a rejected transaction, only the account it
+    // opened is kept.
+    fn commit(
+        &mut self,
+        tx: TxId,
+        kind: Option<TransactionKind>,
+        accepted: bool,
+    ) -> Result<Vec<LedgerEvent>> {
+        let mut events = std::mem::take(&mut self.store.events);
+        if !accepted {
+            events.retain(|event| matches!(event, LedgerEvent::AccountOpened { .. }));
+        }
+        if !self.opened.is_empty() {
+            // Already applied when the configuration opened the accounts.
+            let opened = self.opened.clone();
+            self.append_to_ledger(None, None, opened)?;
+            self.opened.clear();
+        }
+        let Some(kind) = kind.filter(|_| !events.is_empty()) else {
+            return Ok(Vec::new());
+        };
+        let events = self.append_to_ledger(Some(tx), Some(kind), events)?;
+        for event in &events {
+            self.store.apply_event(event)?;
+        }
+        Ok(events)
+    }
+
+    fn append_to_ledger(
+        &mut self,
+        tx: Option<TxId>,
+        kind: Option<TransactionKind>,
+        events: Vec<LedgerEvent>,
+    ) -> Result<Vec<LedgerEvent>> {
+        let record = LedgerRecord {
+            seq: self.ledger_seq + 1,
+            tx,
+            kind,
+            events,
+        };
+        for index in 0..self.ledgers.len() {
+            if let Err(e) = self.ledgers[index].append(&record) {
+                for ledger in &mut self.ledgers[..index] {
+                    if let Err(e) = ledger.revert(&record) {
+                        warn!(seq = record.seq, error = %e, "cannot revert a ledger record");
+                    }
+                }
+                let context = format!("Cannot append record {} to the ledger", record.seq);
+                return Err(e.context(context));
+            }
+        }
+        self.ledger_seq = record.seq;
+        Ok(record.events)
+    }
+
+    fn apply(&mut self, tx: InputTransaction, stamped: bool) -> Result<Outcome> {
+        let kind: TransactionKind = tx.transaction_type.parse()?;
+        // The fee account only moves by fees, which it would not pay itself.
+        if tx.client == self.fees.house_account {
+            return Err(anyhow!("The house account cannot transact"));
+        }
+        match self.accounts {
+            AccountPolicy::OnReference if self.store.get_account(&tx.client).is_none() => {
+                let client = tx.client.clone();
+                self.store.record(LedgerEvent::AccountOpened { client });
+            }
+            AccountPolicy::Registered if !self.registry.contains(&tx.client) => {
+                return Ok(Outcome::Ignored(IgnoreReason::UnknownAccount));
+            }
+            _ => {}
+        }
+        if let Some(outcome) = replayed(&self.store, kind, &tx, stamped)? {
+            return Ok(outcome);
+        }
+        // Disputes come from the card networks and keep flowing whatever the
+        // client status, but inactive clients cannot move money anymore.
+        let info = self.registry.get(&tx.client);
+        if info.is_some_and(|info| info.status != ClientStatus::Active)
+            && matches!(kind, TransactionKind::Deposit | TransactionKind::Withdrawal)
+        {
+            return Ok(Outcome::Ignored(IgnoreReason::ClientInactive));
+        }
+        self.rules
+            .check(&self.store, kind, &tx, self.registry.tier(&tx.client))?;
+
+        let client = tx.client.clone();
+        let activity = Activity::of(kind, &tx, self.store.amount_scale);
         let store = &mut self.store;
-        match tx.transaction_type.as_str() {
-            "deposit" => deposit(store, tx),
-            "withdrawal" => withdrawal(store, tx),
-            "dispute" => dispute(store, tx),
-            "resolve" => resolve(store, tx),
-            "chargeback" => chargeback(store, tx),
-            _ => Err(anyhow!(
-                "Unexpected transaction type: {}",
-                tx.transaction_type
-            )),
+        let outcome = match kind {
+            TransactionKind::Deposit => deposit(store, &self.locked, tx),
+            TransactionKind::Withdrawal => withdrawal(store, &self.fees, tx),
+            TransactionKind::Dispute => dispute(store, &self.disputes, tx),
+            TransactionKind::Resolve => resolve(store, tx),
+            TransactionKind::Chargeback => chargeback(store, &self.fees, tx),
+        }?;
+
+        if outcome == Outcome::Applied {
+            self.undo = Some((
+                self.rules.checkpoint(&client),
+                self.risk.checkpoint(&client),
+            ));
+            self.rules.record(&client, activity);
+            let freeze = self.risk.observe(&client, kind) == Some(RiskAction::Freeze);
+            let locked = self
+                .store
+                .pending_account(&client)?
+                .is_some_and(|a| a.locked);
+            if freeze && !locked {
+                warn!("account locked by the risk monitor");
+                self.store.record(LedgerEvent::AccountLocked {
+                    client,
+                    reason: LockReason::RiskMonitor,
+                });
+            }
         }
+        Ok(outcome)
+    }
+}
+
+impl Default for ToyEngine {
+    fn default() -> Self {
+        Self::new()
     }
 }
@@ -0,0 +1,212 @@
+use crate::{
+    engine::{ledger::LedgerEvent, IgnoreReason},
+    models::{
+        ids::{ClientId, TxId},
+        input_transaction::InputTransaction,
+    },
+};
+use anyhow::{Context, Error, Result};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+use tracing::warn;
+
+/// Why an account was locked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LockReason {
+    Chargeback,
+    RiskMonitor,
+}
+
+/// What happened to a transaction, and the consequences the handlers report
+/// while applying it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EngineEvent {
+    Applied {
+        client: ClientId,
+        tx: TxId,
+        #[serde(rename = "type")]
+        kind: String,
+        amount: Option<Decimal>,
+    },
+    Ignored {
+        client: ClientId,
+        tx: TxId,
+        #[serde(rename = "type")]
+        kind: String,
+        reason: String,
+    },
+    Rejected {
+        client: ClientId,
+        tx: TxId,
+        #[serde(rename = "type")]
+        kind: String,
+        error: String,
+    },
+    DisputeOpened {
+        client: ClientId,
+        tx: TxId,
+        amount: Decimal,
+    },
+    DisputeResolved {
+        client: ClientId,
+        tx: TxId,
+        amount: Decimal,
+    },
+    Chargeback {
+        client: ClientId,
+        tx: TxId,
+        amount: Decimal,
+    },
+    AccountLocked {
+        client: ClientId,
+        reason: LockReason,
+    },
+}
+
+/// Notified after every dispatch: first of its outcome, then of the disputes
+/// opened or resolved, chargebacks and account locks it caused, in order. Replayed
+/// duplicates are not notified. Applied transactions carry the amount moved,
+/// truncated to the amount scale. Every callback does nothing by default.
+pub trait EngineObserver: Send {
+    fn on_applied(&mut self, _tx: &InputTransaction) {}
+    fn on_ignored(&mut self, _tx: &InputTransaction, _reason: IgnoreReason) {}
+    fn on_rejected(&mut self, _tx: &InputTransaction, _error: &Error) {}
+    fn on_dispute_opened(&mut self, _client: &ClientId, _tx: TxId, _amount: Decimal) {}
+    fn on_dispute_resolved(&mut self, _client: &ClientId, _tx: TxId, _amount: Decimal) {}
+    fn on_chargeback(&mut self, _client: &ClientId, _tx: TxId, _amount: Decimal) {}
+    fn on_account_locked(&mut self, _client: &ClientId, _reason: LockReason) {}
+}
+
+// Calls the callback of `observer` matching a ledger event, if any.
+pub(crate) fn notify(observer: &mut dyn EngineObserver, event: &LedgerEvent) {
+    match event {
+        LedgerEvent::DisputeOpened { client, tx, amount } => {
+            observer.on_dispute_opened(client, *tx, *amount)
+        }
+        LedgerEvent::DisputeResolved { client, tx, amount } => {
+            observer.on_dispute_resolved(client, *tx, *amount)
+        }
+        LedgerEvent::ChargedBack { client, tx, amount } => {
+            observer.on_chargeback(client, *tx, *amount)
+        }
+        LedgerEvent::AccountLocked { client, reason } => {
+            observer.on_account_locked(client, *reason)
+        }
+        // Deposits and withdrawals are notified from the transaction itself.
+        LedgerEvent::AccountOpened { .. }
+        | LedgerEvent::Deposited { .. }
+        | LedgerEvent::Withdrawn { .. }
+        | LedgerEvent::FeeCharged { .. } => {}
+    }
+}
+
+// The amount a deposit or withdrawal moved, from the events it applied.
+pub(crate) fn applied_amount(events: &[LedgerEvent]) -> Option<Decimal> {
+    events.iter().find_map(|event| match event {
+        LedgerEvent::Deposited { amount, .. } | LedgerEvent::Withdrawn { amount, .. } => {
+            Some(*amount)
+        }
+        _ => None,
+    })
+}
+
+/// Writes every event as a JSON line, flushed right away so that a tailing
+/// reader sees it. Write errors are logged, not fatal.
+pub struct JsonLinesObserver<W: Write + Send> {
+    writer: W,
+}
+
+impl JsonLinesObserver<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .with_context(|| format!("Cannot create events file {}", path.display()))?;
+        Ok(Self::new(BufWriter::new(file)))
+    }
+}
+
+impl<W: Write + Send> JsonLinesObserver<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    fn write(&mut self, event: EngineEvent) {
+        let written = serde_json::to_writer(&mut self.writer, &event)
+            .map_err(Error::from)
+            .and_then(|()| {
+                self.writer.write_all(b"\n")?;
+                self.writer.flush()?;
+                Ok(())
+            });
+        if let Err(e) = written {
+            warn!(error = %e, "cannot write event");
+        }
+    }
+}
+
+impl<W: Write + Send> EngineObserver for JsonLinesObserver<W> {
+    fn on_applied(&mut self, tx: &InputTransaction) {
+        self.write(EngineEvent::Applied {
+            client: tx.client.clone(),
+            tx: tx.tx,
+            kind: tx.transaction_type.clone(),
+            amount: tx.amount,
+        });
+    }
+
+    fn on_ignored(&mut self, tx: &InputTransaction, reason: IgnoreReason) {
+        self.write(EngineEvent::Ignored {
+            client: tx.client.clone(),
+            tx: tx.tx,
+            kind: tx.transaction_type.clone(),
+            reason: reason.to_string(),
+        });
+    }
+
+    fn on_rejected(&mut self, tx: &InputTransaction, error: &Error) {
+        self.write(EngineEvent::Rejected {
+            client: tx.client.clone(),
+            tx: tx.tx,
+            kind: tx.transaction_type.clone(),
+            error: error.to_string(),
+        });
+    }
+
+    fn on_dispute_opened(&mut self, client: &ClientId, tx: TxId, amount: Decimal) {
+        self.write(EngineEvent::DisputeOpened {
+            client: client.clone(),
+            tx,
+            amount,
+        });
+    }
+
+    fn on_dispute_resolved(&mut self, client: &ClientId, tx: TxId, amount: Decimal) {
+        self.write(EngineEvent::DisputeResolved {
+            client: client.clone(),
+            tx,
+            amount,
+        });
+    }
+
+    fn on_chargeback(&mut self, client: &ClientId, tx: TxId, amount: Decimal) {
+        self.write(EngineEvent::Chargeback {
+            client: client.clone(),
+            tx,
+            amount,
+        });
+    }
+
+    fn on_account_locked(&mut self, client: &ClientId, reason: LockReason) {
+        self.write(EngineEvent::AccountLocked {
+            client: client.clone(),
+            reason,
+        });
+    }
+}
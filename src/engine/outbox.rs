@@ -0,0 +1,367 @@
+use crate::{
+    engine::{
+        ledger::{LedgerBackend, LedgerEvent, LedgerRecord},
+        observer::LockReason,
+    },
+    models::ids::{ClientId, TxId},
+};
+use anyhow::{anyhow, Context, Result};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+/// What changed in the accounts, for downstream consumers: the ledger events
+/// but for the accounts opened and the fees of nothing. Unlike the events of
+/// `JsonLinesObserver`, ignored and rejected transactions are left out, and
+/// amounts are the ones applied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum DomainEvent {
+    Deposited {
+        client: ClientId,
+        tx: TxId,
+        amount: Decimal,
+    },
+    Withdrawn {
+        client: ClientId,
+        tx: TxId,
+        amount: Decimal,
+    },
+    FeeCharged {
+        client: ClientId,
+        house: ClientId,
+        amount: Decimal,
+    },
+    DisputeOpened {
+        client: ClientId,
+        tx: TxId,
+        amount: Decimal,
+    },
+    DisputeResolved {
+        client: ClientId,
+        tx: TxId,
+        amount: Decimal,
+    },
+    ChargedBack {
+        client: ClientId,
+        tx: TxId,
+        amount: Decimal,
+    },
+    AccountLocked {
+        client: ClientId,
+        reason: LockReason,
+    },
+}
+
+impl DomainEvent {
+    fn of(event: &LedgerEvent) -> Option<Self> {
+        let event = match event.clone() {
+            LedgerEvent::AccountOpened { .. } => return None,
+            LedgerEvent::FeeCharged { amount, .. } if amount.is_zero() => return None,
+            LedgerEvent::Deposited {
+                client, tx, amount, ..
+            } => DomainEvent::Deposited { client, tx, amount },
+            LedgerEvent::Withdrawn {
+                client, tx, amount, ..
+            } => DomainEvent::Withdrawn { client, tx, amount },
+            LedgerEvent::FeeCharged {
+                client,
+                house,
+                amount,
+            } => DomainEvent::FeeCharged {
+                client,
+                house,
+                amount,
+            },
+            LedgerEvent::DisputeOpened { client, tx, amount } => {
+                DomainEvent::DisputeOpened { client, tx, amount }
+            }
+            LedgerEvent::DisputeResolved { client, tx, amount } => {
+                DomainEvent::DisputeResolved { client, tx, amount }
+            }
+            LedgerEvent::ChargedBack { client, tx, amount } => {
+                DomainEvent::ChargedBack { client, tx, amount }
+            }
+            LedgerEvent::AccountLocked { client, reason } => {
+                DomainEvent::AccountLocked { client, reason }
+            }
+        };
+        Some(event)
+    }
+}
+
+/// A line of the outbox.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    /// Starts at 1 and grows by one with every event, across restarts.
+    pub seq: u64,
+    #[serde(flatten)]
+    pub event: DomainEvent,
+}
+
+// Reads the complete lines of `file` from `position` on, returning the
+// entries and the position after the last complete line. A line being
+// written has no newline yet and is left for the next read.
+fn read_entries(file: &mut File, position: u64) -> Result<(Vec<OutboxEntry>, u64)> {
+    file.seek(SeekFrom::Start(position))?;
+    let mut reader = BufReader::new(file);
+    let mut entries = Vec::new();
+    let mut position = position;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 || !line.ends_with('\n') {
+            break;
+        }
+        let entry = serde_json::from_str(&line)
+            .with_context(|| format!("Invalid outbox entry at byte {}", position))?;
+        entries.push(entry);
+        position += read as u64;
+    }
+    Ok((entries, position))
+}
+
+/// Append-only JSON lines file of the domain events, each numbered with its
+/// sequence number and flushed right away. Fed with the ledger records, see
+/// `ToyEngine::with_ledger`.
+///
+/// Unlike the archive, the file is kept when opened: sequence numbers go on
+/// from the last entry, and a line left incomplete by a crash is dropped.
+#[derive(Debug)]
+pub struct Outbox {
+    file: File,
+    next_seq: u64,
+    // The size of the file up to the last complete entry.
+    end: u64,
+    // Where the entries of the last record start and how many there are.
+    last_append: (u64, u64),
+}
+
+impl Outbox {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .with_context(|| format!("Cannot open outbox {}", path.display()))?;
+        let (entries, end) = read_entries(&mut file, 0)
+            .with_context(|| format!("Cannot read outbox {}", path.display()))?;
+        file.set_len(end)?;
+
+        Ok(Self {
+            file,
+            next_seq: entries.last().map_or(1, |entry| entry.seq + 1),
+            end,
+            last_append: (end, 0),
+        })
+    }
+
+    /// The sequence number of the last event appended, 0 when there is none.
+    pub fn last_seq(&self) -> u64 {
+        self.next_seq - 1
+    }
+}
+
+impl LedgerBackend for Outbox {
+    // The entries of a record are written at once, or not at all.
+    fn append(&mut self, record: &LedgerRecord) -> Result<()> {
+        let mut lines = Vec::new();
+        let mut seq = self.next_seq;
+        for event in record.events.iter().filter_map(DomainEvent::of) {
+            serde_json::to_writer(&mut lines, &OutboxEntry { seq, event })?;
+            lines.push(b'\n');
+            seq += 1;
+        }
+        if lines.is_empty() {
+            self.last_append = (self.end, 0);
+            return Ok(());
+        }
+        if let Err(e) = self.file.write_all(&lines).and_then(|()| self.file.flush()) {
+            // A partial line would be glued to the next entry, and the
+            // consumers would stop there.
+            let _ = self.file.set_len(self.end);
+            return Err(e).context("Cannot write to the outbox");
+        }
+        self.last_append = (self.end, seq - self.next_seq);
+        self.end += lines.len() as u64;
+        self.next_seq = seq;
+        Ok(())
+    }
+
+    fn revert(&mut self, _record: &LedgerRecord) -> Result<()> {
+        let (start, count) = self.last_append;
+        self.file.set_len(start)?;
+        self.end = start;
+        self.next_seq -= count;
+        self.last_append = (start, 0);
+        Ok(())
+    }
+}
+
+/// Reads an outbox as it grows, from the first event not acknowledged yet.
+/// Acknowledgements are kept next to the outbox, in `<outbox>.ack`, so that
+/// a restarted consumer picks up where it left off.
+#[derive(Debug)]
+pub struct OutboxConsumer {
+    file: File,
+    ack_path: PathBuf,
+    position: u64,
+    acked: u64,
+    last_read: u64,
+}
+
+impl OutboxConsumer {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).with_context(|| format!("Cannot open outbox {}", path.display()))?;
+        let mut ack_path = path.as_os_str().to_owned();
+        ack_path.push(".ack");
+        let ack_path = PathBuf::from(ack_path);
+
+        let acked = match fs::read_to_string(&ack_path) {
+            Ok(content) => content
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid acknowledgement file {}", ack_path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self {
+            file,
+            ack_path,
+            position: 0,
+            acked,
+            last_read: acked,
+        })
+    }
+
+    /// The sequence number of the last event acknowledged, 0 when there is
+    /// none.
+    pub fn acked(&self) -> u64 {
+        self.acked
+    }
+
+    /// The events appended since the previous poll, skipping those already
+    /// acknowledged.
+    pub fn poll(&mut self) -> Result<Vec<OutboxEntry>> {
+        let (entries, position) = read_entries(&mut self.file, self.position)?;
+        self.position = position;
+        let entries: Vec<_> = entries
+            .into_iter()
+            .filter(|entry| entry.seq > self.acked)
+            .collect();
+        if let Some(last) = entries.last() {
+            self.last_read = last.seq;
+        }
+        Ok(entries)
+    }
+
+    /// Acknowledges every event up to `seq`, which must have been polled.
+    pub fn ack(&mut self, seq: u64) -> Result<()> {
+        if seq < self.acked {
+            return Err(anyhow!(
+                "Events up to {} are already acknowledged",
+                self.acked
+            ));
+        }
+        if seq > self.last_read {
+            return Err(anyhow!("Cannot acknowledge event {}, not read yet", seq));
+        }
+
+        // Written aside then renamed, so that a crash never leaves a partial
+        // acknowledgement.
+        let mut tmp_path = self.ack_path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, format!("{}\n", seq))?;
+        fs::rename(&tmp_path, &self.ack_path).with_context(|| {
+            format!(
+                "Cannot write acknowledgement file {}",
+                self.ack_path.display()
+            )
+        })?;
+        self.acked = seq;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::transaction_kind::TransactionKind;
+
+    fn deposited(tx: u32) -> DomainEvent {
+        DomainEvent::Deposited {
+            client: ClientId::Num(1),
+            tx: tx.into(),
+            amount: Decimal::from(10),
+        }
+    }
+
+    fn record(tx: u32) -> LedgerRecord {
+        let client = ClientId::Num(1);
+        LedgerRecord {
+            seq: tx.into(),
+            tx: Some(tx.into()),
+            kind: Some(TransactionKind::Deposit),
+            events: vec![
+                LedgerEvent::AccountOpened {
+                    client: client.clone(),
+                },
+                LedgerEvent::Deposited {
+                    client,
+                    tx: tx.into(),
+                    amount: Decimal::from(10),
+                    timestamp: None,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_outbox_resumes_and_acknowledges() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("outbox.jsonl");
+
+        let mut outbox = Outbox::open(&path)?;
+        outbox.append(&record(1))?;
+        outbox.append(&record(2))?;
+        let mut consumer = OutboxConsumer::open(&path)?;
+        assert_eq!(
+            consumer.poll()?.iter().map(|e| e.seq).collect::<Vec<_>>(),
+            [1, 2]
+        );
+        assert!(consumer.ack(3).is_err());
+        consumer.ack(1)?;
+
+        // A line left incomplete is not read, then dropped on reopening.
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)?
+            .write_all(b"{\"seq\":3")?;
+        assert!(consumer.poll()?.is_empty());
+        let mut outbox = Outbox::open(&path)?;
+        assert_eq!(outbox.last_seq(), 2);
+        outbox.append(&record(3))?;
+        // Taken back, as when another backend of the engine fails.
+        outbox.append(&record(4))?;
+        outbox.revert(&record(4))?;
+        assert_eq!(outbox.last_seq(), 3);
+
+        let mut consumer = OutboxConsumer::open(&path)?;
+        assert_eq!(consumer.acked(), 1);
+        let entries = consumer.poll()?;
+        assert_eq!(entries.iter().map(|e| e.seq).collect::<Vec<_>>(), [2, 3]);
+        assert_eq!(entries[1].event, deposited(3));
+        assert!(consumer.ack(0).is_err());
+        Ok(())
+    }
+}
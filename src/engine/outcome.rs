@@ -0,0 +1,80 @@
+use crate::models::ids::TxId;
+use std::fmt;
+
+/// What a dispatch did with a valid transaction, errors being rejections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Applied,
+    Duplicate,
+    Ignored(IgnoreReason),
+}
+
+/// Why a valid transaction changed nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IgnoreReason {
+    UnknownAccount,
+    ClientInactive,
+    AccountLocked,
+    InsufficientFunds,
+    UnknownTransaction,
+    AlreadyDisputed,
+    NotDisputed,
+    NotDisputable,
+    DisputeWindowExpired,
+}
+
+impl Outcome {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Outcome::Applied => "applied",
+            Outcome::Duplicate => "duplicate",
+            Outcome::Ignored(_) => "ignored",
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Applied => write!(f, "applied"),
+            Outcome::Duplicate => write!(f, "duplicate"),
+            Outcome::Ignored(reason) => write!(f, "ignored: {}", reason),
+        }
+    }
+}
+
+impl fmt::Display for IgnoreReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            IgnoreReason::UnknownAccount => "unknown account",
+            IgnoreReason::ClientInactive => "client is not active",
+            IgnoreReason::AccountLocked => "account locked",
+            IgnoreReason::InsufficientFunds => "insufficient funds",
+            IgnoreReason::UnknownTransaction => "unknown transaction",
+            IgnoreReason::AlreadyDisputed => "transaction already disputed",
+            IgnoreReason::NotDisputed => "transaction not disputed",
+            IgnoreReason::NotDisputable => "transaction cannot be disputed",
+            IgnoreReason::DisputeWindowExpired => "dispute window expired",
+        };
+        f.write_str(reason)
+    }
+}
+
+/// A row reusing the id of an already applied transaction with different
+/// content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransactionConflict {
+    pub tx: TxId,
+}
+
+impl fmt::Display for TransactionConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Transaction {} conflicts with an already applied transaction",
+            self.tx
+        )
+    }
+}
+
+impl std::error::Error for TransactionConflict {}
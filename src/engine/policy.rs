@@ -0,0 +1,80 @@
+use crate::models::{timestamp::Timestamp, transaction::Transaction};
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::{str::FromStr, time::Duration};
+
+/// Which deposits can still be disputed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DisputePolicy {
+    pub max_age: Option<Duration>,
+}
+
+impl DisputePolicy {
+    // Only enforced when both the disputed transaction and the dispute carry a
+    // timestamp.
+    pub(crate) fn is_expired(&self, original: &Transaction, at: Option<Timestamp>) -> bool {
+        match (self.max_age, original.timestamp, at) {
+            (Some(max_age), Some(made_at), Some(at)) => at.elapsed_since(made_at) > max_age,
+            _ => false,
+        }
+    }
+}
+
+/// When an account comes into existence. Transactions for a client without an
+/// account are ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AccountPolicy {
+    /// Only an applied deposit opens an account.
+    #[default]
+    OnFirstDeposit,
+    /// Any transaction mentioning the client opens an account, even if it ends
+    /// up ignored or rejected.
+    OnReference,
+    /// Accounts are opened upfront for the clients of the engine registry, and
+    /// only for them.
+    Registered,
+}
+
+impl FromStr for AccountPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "on-first-deposit" => Ok(AccountPolicy::OnFirstDeposit),
+            "on-reference" => Ok(AccountPolicy::OnReference),
+            "registered" => Ok(AccountPolicy::Registered),
+            _ => Err(anyhow!("Unexpected account policy: {}", s)),
+        }
+    }
+}
+
+/// What a locked account still accepts. Withdrawals and disputes are always
+/// ignored, resolves and chargebacks of open disputes always go through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LockedAccountPolicy {
+    #[default]
+    Frozen,
+    /// Deposits are still credited, so that a client can pay back what a
+    /// chargeback left owing.
+    AcceptDeposits,
+}
+
+impl LockedAccountPolicy {
+    pub(crate) fn accepts_deposits(&self) -> bool {
+        *self == LockedAccountPolicy::AcceptDeposits
+    }
+}
+
+impl FromStr for LockedAccountPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "frozen" => Ok(LockedAccountPolicy::Frozen),
+            "accept-deposits" => Ok(LockedAccountPolicy::AcceptDeposits),
+            _ => Err(anyhow!("Unexpected locked account policy: {}", s)),
+        }
+    }
+}
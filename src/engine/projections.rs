@@ -0,0 +1,131 @@
+use crate::{
+    engine::ledger::{LedgerEvent, LedgerRecord},
+    models::{
+        ids::{ClientId, TxId},
+        output_record::OutputRecord,
+    },
+};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// A read model folded from the ledger events, without an engine.
+pub trait Projection {
+    fn apply(&mut self, event: &LedgerEvent);
+}
+
+/// Folds the events of `records` into a new `P`.
+pub fn project<P: Projection + Default>(records: &[LedgerRecord]) -> P {
+    let mut projection = P::default();
+    for event in records.iter().flat_map(|record| &record.events) {
+        projection.apply(event);
+    }
+    projection
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Balance {
+    available: Decimal,
+    held: Decimal,
+    locked: bool,
+}
+
+/// The balances of every account, as the engine output has them.
+#[derive(Debug, Clone, Default)]
+pub struct Balances(BTreeMap<ClientId, Balance>);
+
+impl Balances {
+    pub fn records(&self) -> Vec<OutputRecord> {
+        self.0
+            .iter()
+            .map(|(client, balance)| OutputRecord {
+                client: client.clone(),
+                available: balance.available,
+                held: balance.held,
+                total: balance.available + balance.held,
+                locked: balance.locked,
+            })
+            .collect()
+    }
+
+    fn account(&mut self, client: &ClientId) -> &mut Balance {
+        self.0.entry(client.clone()).or_default()
+    }
+}
+
+impl Projection for Balances {
+    fn apply(&mut self, event: &LedgerEvent) {
+        match event {
+            LedgerEvent::AccountOpened { client } => {
+                self.account(client);
+            }
+            LedgerEvent::Deposited { client, amount, .. } => {
+                self.account(client).available += amount;
+            }
+            LedgerEvent::Withdrawn { client, amount, .. } => {
+                self.account(client).available -= amount;
+            }
+            LedgerEvent::FeeCharged {
+                client,
+                house,
+                amount,
+            } => {
+                self.account(client).available -= amount;
+                self.account(house).available += amount;
+            }
+            LedgerEvent::DisputeOpened { client, amount, .. } => {
+                let account = self.account(client);
+                account.available -= amount;
+                account.held += amount;
+            }
+            LedgerEvent::DisputeResolved { client, amount, .. } => {
+                let account = self.account(client);
+                account.held -= amount;
+                account.available += amount;
+            }
+            LedgerEvent::ChargedBack { client, amount, .. } => {
+                self.account(client).held -= amount;
+            }
+            LedgerEvent::AccountLocked { client, .. } => {
+                self.account(client).locked = true;
+            }
+        }
+    }
+}
+
+/// A disputed transaction, with the amount held.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OpenDispute {
+    pub client: ClientId,
+    pub tx: TxId,
+    pub amount: Decimal,
+}
+
+/// The transactions under dispute, neither resolved nor charged back yet.
+#[derive(Debug, Clone, Default)]
+pub struct OpenDisputes(BTreeMap<TxId, OpenDispute>);
+
+impl OpenDisputes {
+    pub fn disputes(&self) -> Vec<OpenDispute> {
+        self.0.values().cloned().collect()
+    }
+}
+
+impl Projection for OpenDisputes {
+    fn apply(&mut self, event: &LedgerEvent) {
+        match event {
+            LedgerEvent::DisputeOpened { client, tx, amount } => {
+                let dispute = OpenDispute {
+                    client: client.clone(),
+                    tx: *tx,
+                    amount: *amount,
+                };
+                self.0.insert(*tx, dispute);
+            }
+            LedgerEvent::DisputeResolved { tx, .. } | LedgerEvent::ChargedBack { tx, .. } => {
+                self.0.remove(tx);
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use crate::models::{client::ClientInfo, ids::ClientId};
+use anyhow::{Context, Result};
+use std::{collections::HashMap, path::Path};
+
+/// Known clients and their metadata, loaded from a CSV file with a `client`
+/// column and optional `name`, `kyc_tier`, `country`, `opened` (YYYY-MM-DD)
+/// and `status` columns.
+#[derive(Debug, Clone, Default)]
+pub struct ClientRegistry {
+    clients: HashMap<ClientId, ClientInfo>,
+}
+
+impl ClientRegistry {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_path(path)
+            .with_context(|| format!("Cannot read client registry {}", path.display()))?;
+        Self::from_reader(reader)
+            .with_context(|| format!("Invalid client registry {}", path.display()))
+    }
+
+    fn from_reader<R: std::io::Read>(mut reader: csv::Reader<R>) -> Result<Self> {
+        let mut clients = HashMap::new();
+        for (line, record) in reader.deserialize().enumerate() {
+            let info: ClientInfo = record.with_context(|| format!("record {}", line + 1))?;
+            clients.insert(info.client.clone(), info);
+        }
+        Ok(Self { clients })
+    }
+
+    pub fn get(&self, client: &ClientId) -> Option<&ClientInfo> {
+        self.clients.get(client)
+    }
+
+    pub fn contains(&self, client: &ClientId) -> bool {
+        self.clients.contains_key(client)
+    }
+
+    pub fn tier(&self, client: &ClientId) -> Option<&str> {
+        self.get(client).and_then(|info| info.kyc_tier.as_deref())
+    }
+
+    pub fn clients(&self) -> impl Iterator<Item = &ClientId> {
+        self.clients.keys()
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+}
+
+impl FromIterator<ClientInfo> for ClientRegistry {
+    fn from_iter<I: IntoIterator<Item = ClientInfo>>(clients: I) -> Self {
+        Self {
+            clients: clients
+                .into_iter()
+                .map(|info| (info.client.clone(), info))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::client::ClientStatus;
+    use chrono::NaiveDate;
+
+    fn registry(csv: &str) -> Result<ClientRegistry> {
+        let reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(csv.as_bytes());
+        ClientRegistry::from_reader(reader)
+    }
+
+    #[test]
+    fn test_registry_from_csv() {
+        let registry = registry("client, name\n1, Alice\n 7 ,Bob\n1,Alice again\n").unwrap();
+
+        assert_eq!(registry.len(), 2);
+        assert!(registry.contains(&ClientId::Num(1)));
+        assert!(registry.contains(&ClientId::Num(7)));
+        assert!(!registry.contains(&ClientId::Num(2)));
+        assert_eq!(
+            registry.get(&ClientId::Num(1)).unwrap().name.as_deref(),
+            Some("Alice again")
+        );
+    }
+
+    #[test]
+    fn test_registry_metadata() {
+        let registry = registry(
+            "client,name,kyc_tier,country,opened,status\n\
+             1,Alice,verified,FR,2021-04-01,active\n\
+             2,Bob,,DE,,suspended\n\
+             3,,,,,closed\n",
+        )
+        .unwrap();
+
+        let alice = registry.get(&ClientId::Num(1)).unwrap();
+        assert_eq!(alice.country.as_deref(), Some("FR"));
+        assert_eq!(alice.opened, NaiveDate::from_ymd_opt(2021, 4, 1));
+        assert_eq!(registry.tier(&ClientId::Num(1)), Some("verified"));
+
+        let bob = registry.get(&ClientId::Num(2)).unwrap();
+        assert_eq!(bob.status, ClientStatus::Suspended);
+        assert_eq!((bob.kyc_tier.as_deref(), bob.opened), (None, None));
+
+        assert_eq!(
+            registry.get(&ClientId::Num(3)).unwrap().status,
+            ClientStatus::Closed
+        );
+        assert_eq!(registry.get(&ClientId::Num(3)).unwrap().name, None);
+    }
+
+    #[test]
+    fn test_registry_rejects_invalid_rows() {
+        assert!(registry("client\n-1\n").is_err());
+        assert!(registry("client,status\n1,frozen\n").is_err());
+        assert!(registry("client,opened\n1,01/04/2021\n").is_err());
+    }
+}
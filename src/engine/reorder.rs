@@ -0,0 +1,182 @@
+use crate::models::{input_transaction::InputTransaction, timestamp::Timestamp};
+use anyhow::{anyhow, Result};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    time::Duration,
+};
+
+// Buffers transactions so that rows arriving up to `tolerance` late (in event
+// time) are still applied in timestamp order. Rows without timestamp are
+// ordered right after everything seen so far, which keeps plain file order for
+// files without a timestamp column.
+pub struct ReorderBuffer {
+    tolerance: i64,
+    pending: BinaryHeap<Reverse<Pending>>,
+    sequence: u64,
+    max_seen: Option<Timestamp>,
+    released: Option<Timestamp>,
+}
+
+struct Pending {
+    at: Option<Timestamp>,
+    sequence: u64,
+    tx: InputTransaction,
+}
+
+impl ReorderBuffer {
+    pub fn new(tolerance: Duration) -> Self {
+        Self {
+            tolerance: i64::try_from(tolerance.as_millis()).unwrap_or(i64::MAX),
+            pending: BinaryHeap::new(),
+            sequence: 0,
+            max_seen: None,
+            released: None,
+        }
+    }
+
+    pub fn push(&mut self, tx: InputTransaction) -> Result<()> {
+        if let (Some(at), Some(released)) = (tx.timestamp, self.released) {
+            if at < released {
+                return Err(anyhow!(
+                    "Transaction {} at {} arrived after {} was applied",
+                    tx.tx,
+                    at,
+                    released
+                ));
+            }
+        }
+
+        let at = tx.timestamp.or(self.max_seen);
+        if at > self.max_seen {
+            self.max_seen = at;
+        }
+
+        self.sequence += 1;
+        self.pending.push(Reverse(Pending {
+            at,
+            sequence: self.sequence,
+            tx,
+        }));
+        Ok(())
+    }
+
+    // Next transaction that can no longer be overtaken by a late one.
+    pub fn pop_ready(&mut self) -> Option<InputTransaction> {
+        let watermark = self
+            .max_seen
+            .map(|max| Timestamp::from_millis(max.as_millis().saturating_sub(self.tolerance)));
+        let Reverse(next) = self.pending.peek()?;
+        if next.at > watermark {
+            return None;
+        }
+        self.pop()
+    }
+
+    pub fn pop(&mut self) -> Option<InputTransaction> {
+        let Reverse(next) = self.pending.pop()?;
+        if next.at > self.released {
+            self.released = next.at;
+        }
+        Some(next.tx)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+impl Pending {
+    fn key(&self) -> (Option<Timestamp>, u64) {
+        (self.at, self.sequence)
+    }
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ids::{ClientId, TxId};
+
+    fn input_transaction(tx: u32, at: Option<i64>) -> InputTransaction {
+        InputTransaction {
+            transaction_type: "deposit".to_string(),
+            client: ClientId::Num(1),
+            tx: tx.into(),
+            amount: None,
+            timestamp: at.map(Timestamp::from_millis),
+            tenant: None,
+        }
+    }
+
+    fn ready(buffer: &mut ReorderBuffer) -> Vec<TxId> {
+        std::iter::from_fn(|| buffer.pop_ready())
+            .map(|tx| tx.tx)
+            .collect()
+    }
+
+    #[test]
+    fn test_reorders_within_tolerance() {
+        let mut buffer = ReorderBuffer::new(Duration::from_millis(100));
+
+        buffer.push(input_transaction(1, Some(1_000))).unwrap();
+        buffer.push(input_transaction(2, Some(1_050))).unwrap();
+        buffer.push(input_transaction(3, Some(1_020))).unwrap();
+        assert!(ready(&mut buffer).is_empty());
+
+        buffer.push(input_transaction(4, Some(1_130))).unwrap();
+        assert_eq!(ready(&mut buffer), [1, 3].map(TxId::Num));
+
+        assert_eq!(
+            std::iter::from_fn(|| buffer.pop())
+                .map(|tx| tx.tx)
+                .collect::<Vec<_>>(),
+            [2, 4].map(TxId::Num)
+        );
+    }
+
+    #[test]
+    fn test_rejects_rows_beyond_tolerance() {
+        let mut buffer = ReorderBuffer::new(Duration::from_millis(10));
+
+        buffer.push(input_transaction(1, Some(1_000))).unwrap();
+        buffer.push(input_transaction(2, Some(2_000))).unwrap();
+        assert_eq!(ready(&mut buffer), [TxId::Num(1)]);
+
+        assert!(buffer.push(input_transaction(3, Some(500))).is_err());
+        assert!(buffer.push(input_transaction(4, Some(1_500))).is_ok());
+    }
+
+    #[test]
+    fn test_rows_without_timestamp_keep_file_order() {
+        let mut buffer = ReorderBuffer::new(Duration::ZERO);
+
+        for tx in 1..=3 {
+            buffer.push(input_transaction(tx, None)).unwrap();
+        }
+        assert_eq!(ready(&mut buffer), [1, 2, 3].map(TxId::Num));
+    }
+}
@@ -0,0 +1,329 @@
+use crate::models::{ids::ClientId, transaction_kind::TransactionKind};
+use anyhow::{anyhow, Context, Result};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs,
+    path::Path,
+};
+
+/// The heuristics flagging or freezing suspicious clients, each one off when
+/// missing.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RiskConfig {
+    pub dispute_burst: Option<DisputeBurst>,
+    pub dispute_ratio: Option<DisputeRatio>,
+    pub chargebacks: Option<ChargebackCount>,
+}
+
+/// Fires when `disputes` disputes happen within the client's last `window`
+/// transactions.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct DisputeBurst {
+    pub disputes: usize,
+    pub window: usize,
+    pub action: RiskAction,
+}
+
+/// Fires when disputes / deposits goes above `ratio`, once the client made at
+/// least `min_deposits` deposits.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct DisputeRatio {
+    pub ratio: Decimal,
+    #[serde(default)]
+    pub min_deposits: usize,
+    pub action: RiskAction,
+}
+
+/// Fires when the client reaches `count` chargebacks.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ChargebackCount {
+    pub count: usize,
+    pub action: RiskAction,
+}
+
+/// What a heuristic does when it fires: freezing also locks the account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RiskAction {
+    Flag,
+    Freeze,
+}
+
+/// The heuristic that fired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RiskSignal {
+    DisputeBurst,
+    DisputeRatio,
+    Chargebacks,
+}
+
+/// A client a heuristic fired for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RiskFlag {
+    pub client: ClientId,
+    pub signal: RiskSignal,
+    pub action: RiskAction,
+}
+
+impl RiskConfig {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Cannot read risk config {}", path.display()))?;
+        let config: RiskConfig = toml::from_str(&content)
+            .with_context(|| format!("Invalid risk config {}", path.display()))?;
+        config
+            .validate()
+            .with_context(|| format!("Invalid risk config {}", path.display()))?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(burst) = self.dispute_burst {
+            if burst.window == 0 || burst.disputes == 0 || burst.disputes > burst.window {
+                return Err(anyhow!(
+                    "dispute_burst needs 0 < disputes <= window (got {} in {})",
+                    burst.disputes,
+                    burst.window
+                ));
+            }
+        }
+        if self.dispute_ratio.is_some_and(|r| r.ratio < Decimal::ZERO) {
+            return Err(anyhow!("dispute_ratio.ratio cannot be negative"));
+        }
+        if self.chargebacks.is_some_and(|c| c.count == 0) {
+            return Err(anyhow!("chargebacks.count must be positive"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct ClientStats {
+    deposits: usize,
+    disputes: usize,
+    chargebacks: usize,
+    recent_disputes: VecDeque<bool>,
+}
+
+// What `RiskMonitor::observe` may change for a client, to undo it.
+pub(crate) struct RiskCheckpoint {
+    client: ClientId,
+    stats: Option<ClientStats>,
+    flags: usize,
+}
+
+pub(crate) struct RiskMonitor {
+    config: RiskConfig,
+    stats: HashMap<ClientId, ClientStats>,
+    raised: HashSet<(ClientId, RiskSignal)>,
+    flags: Vec<RiskFlag>,
+}
+
+impl RiskMonitor {
+    pub fn new(config: RiskConfig) -> Self {
+        Self {
+            config,
+            stats: HashMap::new(),
+            raised: HashSet::new(),
+            flags: Vec::new(),
+        }
+    }
+
+    /// Records an applied transaction and returns the strongest action
+    /// triggered by it, if any. A given signal is only raised once per client.
+    pub fn observe(&mut self, client: &ClientId, kind: TransactionKind) -> Option<RiskAction> {
+        let config = &self.config;
+        if config.dispute_burst.is_none()
+            && config.dispute_ratio.is_none()
+            && config.chargebacks.is_none()
+        {
+            return None;
+        }
+
+        let stats = self.stats.entry(client.clone()).or_default();
+        match kind {
+            TransactionKind::Deposit => stats.deposits += 1,
+            TransactionKind::Dispute => stats.disputes += 1,
+            TransactionKind::Chargeback => stats.chargebacks += 1,
+            _ => {}
+        }
+
+        let mut fired = Vec::new();
+
+        if let Some(burst) = config.dispute_burst {
+            stats
+                .recent_disputes
+                .push_back(kind == TransactionKind::Dispute);
+            while stats.recent_disputes.len() > burst.window {
+                stats.recent_disputes.pop_front();
+            }
+            let disputes = stats.recent_disputes.iter().filter(|d| **d).count();
+            if disputes >= burst.disputes {
+                fired.push((RiskSignal::DisputeBurst, burst.action));
+            }
+        }
+
+        if let Some(ratio) = config.dispute_ratio {
+            if stats.deposits > 0 && stats.deposits >= ratio.min_deposits {
+                let observed = Decimal::from(stats.disputes) / Decimal::from(stats.deposits);
+                if observed > ratio.ratio {
+                    fired.push((RiskSignal::DisputeRatio, ratio.action));
+                }
+            }
+        }
+
+        if let Some(chargebacks) = config.chargebacks {
+            if stats.chargebacks >= chargebacks.count {
+                fired.push((RiskSignal::Chargebacks, chargebacks.action));
+            }
+        }
+
+        let mut strongest = None;
+        for (signal, action) in fired {
+            if self.raised.insert((client.clone(), signal)) {
+                self.flags.push(RiskFlag {
+                    client: client.clone(),
+                    signal,
+                    action,
+                });
+                if strongest != Some(RiskAction::Freeze) {
+                    strongest = Some(action);
+                }
+            }
+        }
+        strongest
+    }
+
+    pub fn checkpoint(&self, client: &ClientId) -> RiskCheckpoint {
+        RiskCheckpoint {
+            client: client.clone(),
+            stats: self.stats.get(client).cloned(),
+            flags: self.flags.len(),
+        }
+    }
+
+    pub fn restore(&mut self, checkpoint: RiskCheckpoint) {
+        // Every flag raised since the checkpoint is the client's.
+        for flag in self.flags.drain(checkpoint.flags..) {
+            self.raised.remove(&(flag.client, flag.signal));
+        }
+        match checkpoint.stats {
+            Some(stats) => self.stats.insert(checkpoint.client, stats),
+            None => self.stats.remove(&checkpoint.client),
+        };
+    }
+
+    pub fn flags(&self) -> Vec<RiskFlag> {
+        let mut flags = self.flags.clone();
+        flags.sort_by(|a, b| a.client.cmp(&b.client));
+        flags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(config: &str) -> RiskMonitor {
+        let config: RiskConfig = toml::from_str(config).unwrap();
+        config.validate().unwrap();
+        RiskMonitor::new(config)
+    }
+
+    #[test]
+    fn test_dispute_burst_freezes_once() {
+        let mut risk = monitor(
+            r#"
+            dispute_burst = { disputes = 2, window = 3, action = "freeze" }
+            "#,
+        );
+
+        assert_eq!(
+            risk.observe(&ClientId::Num(1), TransactionKind::Deposit),
+            None
+        );
+        assert_eq!(
+            risk.observe(&ClientId::Num(1), TransactionKind::Dispute),
+            None
+        );
+        assert_eq!(
+            risk.observe(&ClientId::Num(1), TransactionKind::Dispute),
+            Some(RiskAction::Freeze)
+        );
+        assert_eq!(
+            risk.observe(&ClientId::Num(1), TransactionKind::Dispute),
+            None
+        );
+        assert_eq!(risk.flags().len(), 1);
+    }
+
+    #[test]
+    fn test_dispute_burst_outside_window() {
+        let mut risk = monitor(
+            r#"
+            dispute_burst = { disputes = 2, window = 2, action = "flag" }
+            "#,
+        );
+
+        for kind in [
+            TransactionKind::Dispute,
+            TransactionKind::Deposit,
+            TransactionKind::Dispute,
+        ] {
+            assert_eq!(risk.observe(&ClientId::Num(1), kind), None);
+        }
+        assert!(risk.flags().is_empty());
+    }
+
+    #[test]
+    fn test_dispute_ratio_and_chargebacks() {
+        let mut risk = monitor(
+            r#"
+            dispute_ratio = { ratio = 0.5, min_deposits = 2, action = "flag" }
+            chargebacks = { count = 1, action = "flag" }
+            "#,
+        );
+
+        assert_eq!(
+            risk.observe(&ClientId::Num(2), TransactionKind::Deposit),
+            None
+        );
+        assert_eq!(
+            risk.observe(&ClientId::Num(2), TransactionKind::Dispute),
+            None
+        );
+        assert_eq!(
+            risk.observe(&ClientId::Num(2), TransactionKind::Deposit),
+            None
+        );
+        assert_eq!(
+            risk.observe(&ClientId::Num(2), TransactionKind::Dispute),
+            Some(RiskAction::Flag)
+        );
+        assert_eq!(
+            risk.observe(&ClientId::Num(2), TransactionKind::Chargeback),
+            Some(RiskAction::Flag)
+        );
+
+        let signals: Vec<RiskSignal> = risk.flags().iter().map(|f| f.signal).collect();
+        assert_eq!(
+            signals,
+            vec![RiskSignal::DisputeRatio, RiskSignal::Chargebacks]
+        );
+    }
+
+    #[test]
+    fn test_invalid_burst() {
+        let config: RiskConfig = toml::from_str(
+            r#"
+            dispute_burst = { disputes = 4, window = 3, action = "flag" }
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,571 @@
+use crate::{
+    engine::{
+        storage::Storage,
+        utils::{AmountScale, DecimalToU32},
+    },
+    models::{
+        ids::ClientId, input_transaction::InputTransaction, transaction_kind::TransactionKind,
+    },
+};
+use anyhow::{anyhow, Context, Result};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt, fs,
+    path::Path,
+};
+
+/// Limits on the transactions of a client, each one off when missing.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct Limits {
+    pub max_withdrawal: Option<Decimal>,
+    pub max_balance: Option<Decimal>,
+    pub rolling_withdrawals: Option<RollingWithdrawals>,
+    pub deposit_velocity: Option<DepositVelocity>,
+    pub daily_withdrawals: Option<Decimal>,
+}
+
+/// Windows are expressed in number of transactions of the client, the current
+/// one included.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct RollingWithdrawals {
+    pub window: usize,
+    pub max_total: Decimal,
+}
+
+/// At most `max_deposits` deposits within the last `window` transactions.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct DepositVelocity {
+    pub window: usize,
+    pub max_deposits: usize,
+}
+
+/// Limits for one client.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClientLimits {
+    pub client: ClientId,
+    #[serde(flatten)]
+    pub limits: Limits,
+}
+
+/// Limits for the clients of a KYC tier of the client registry.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TierLimits {
+    pub tier: String,
+    #[serde(flatten)]
+    pub limits: Limits,
+}
+
+/// Client overrides take precedence over the tier of the client, which takes
+/// precedence over the defaults.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LimitsConfig {
+    #[serde(flatten)]
+    pub defaults: Limits,
+    #[serde(default)]
+    pub tiers: Vec<TierLimits>,
+    #[serde(default)]
+    pub clients: Vec<ClientLimits>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleViolation {
+    MaxWithdrawal,
+    RollingWithdrawals,
+    DepositVelocity,
+    MaxBalance,
+    DailyWithdrawals,
+}
+
+impl fmt::Display for RuleViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            RuleViolation::MaxWithdrawal => "withdrawal exceeds the maximum single withdrawal",
+            RuleViolation::RollingWithdrawals => "withdrawal exceeds the rolling withdrawal limit",
+            RuleViolation::DepositVelocity => "too many deposits in the velocity window",
+            RuleViolation::MaxBalance => "deposit would exceed the maximum balance",
+            RuleViolation::DailyWithdrawals => "withdrawal exceeds the daily withdrawal limit",
+        };
+        f.write_str(reason)
+    }
+}
+
+impl std::error::Error for RuleViolation {}
+
+impl Limits {
+    fn merge(self, overrides: Limits) -> Limits {
+        Limits {
+            max_withdrawal: overrides.max_withdrawal.or(self.max_withdrawal),
+            max_balance: overrides.max_balance.or(self.max_balance),
+            rolling_withdrawals: overrides.rolling_withdrawals.or(self.rolling_withdrawals),
+            deposit_velocity: overrides.deposit_velocity.or(self.deposit_velocity),
+            daily_withdrawals: overrides.daily_withdrawals.or(self.daily_withdrawals),
+        }
+    }
+
+    fn window(&self) -> usize {
+        let rolling = self.rolling_withdrawals.map_or(0, |r| r.window);
+        let velocity = self.deposit_velocity.map_or(0, |v| v.window);
+        rolling.max(velocity)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.rolling_withdrawals.is_some_and(|r| r.window == 0) {
+            return Err(anyhow!("rolling_withdrawals.window must be positive"));
+        }
+        if self.deposit_velocity.is_some_and(|v| v.window == 0) {
+            return Err(anyhow!("deposit_velocity.window must be positive"));
+        }
+        Ok(())
+    }
+}
+
+impl LimitsConfig {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Cannot read limits {}", path.display()))?;
+        let config: LimitsConfig = toml::from_str(&content)
+            .with_context(|| format!("Invalid limits {}", path.display()))?;
+        config
+            .validate()
+            .with_context(|| format!("Invalid limits {}", path.display()))?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.defaults.validate()?;
+        for tier in &self.tiers {
+            tier.limits
+                .validate()
+                .with_context(|| format!("tier {}", tier.tier))?;
+        }
+        for overrides in &self.clients {
+            overrides
+                .limits
+                .validate()
+                .with_context(|| format!("client {}", overrides.client))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Activity {
+    Deposit,
+    Withdrawal { amount: u32, day: Option<i64> },
+    Other,
+}
+
+impl Activity {
+    pub fn of(kind: TransactionKind, tx: &InputTransaction, scale: AmountScale) -> Self {
+        match kind {
+            TransactionKind::Deposit => Activity::Deposit,
+            TransactionKind::Withdrawal => Activity::Withdrawal {
+                amount: tx
+                    .amount
+                    .and_then(|amount| amount.decimal_to_u32(scale).ok())
+                    .unwrap_or(0),
+                day: tx.timestamp.map(|at| at.day()),
+            },
+            _ => Activity::Other,
+        }
+    }
+}
+
+// What `RuleEngine::record` may change for a client, to undo it.
+pub(crate) struct RulesCheckpoint {
+    client: ClientId,
+    history: Option<VecDeque<Activity>>,
+    daily: Option<(i64, u64)>,
+}
+
+pub(crate) struct RuleEngine {
+    defaults: Limits,
+    tiers: HashMap<String, Limits>,
+    overrides: HashMap<ClientId, Limits>,
+    window: usize,
+    history: HashMap<ClientId, VecDeque<Activity>>,
+    tracks_daily: bool,
+    daily: HashMap<ClientId, (i64, u64)>,
+}
+
+impl RuleEngine {
+    pub fn new(config: LimitsConfig) -> Self {
+        let tiers: HashMap<String, Limits> = config
+            .tiers
+            .into_iter()
+            .map(|t| (t.tier, config.defaults.merge(t.limits)))
+            .collect();
+        let overrides: HashMap<ClientId, Limits> = config
+            .clients
+            .into_iter()
+            .map(|c| (c.client, c.limits))
+            .collect();
+        let all = || {
+            std::iter::once(&config.defaults)
+                .chain(tiers.values())
+                .chain(overrides.values())
+        };
+        let window = all().map(Limits::window).max().unwrap_or(0);
+        let tracks_daily = all().any(|l| l.daily_withdrawals.is_some());
+
+        Self {
+            defaults: config.defaults,
+            tiers,
+            overrides,
+            window,
+            history: HashMap::new(),
+            tracks_daily,
+            daily: HashMap::new(),
+        }
+    }
+
+    fn limits_for(&self, client: &ClientId, tier: Option<&str>) -> Limits {
+        let base = tier
+            .and_then(|tier| self.tiers.get(tier))
+            .unwrap_or(&self.defaults);
+        match self.overrides.get(client) {
+            Some(overrides) => base.merge(*overrides),
+            None => *base,
+        }
+    }
+
+    pub fn check(
+        &self,
+        store: &Storage,
+        kind: TransactionKind,
+        tx: &InputTransaction,
+        tier: Option<&str>,
+    ) -> Result<()> {
+        let limits = self.limits_for(&tx.client, tier);
+        let amount = match tx.amount {
+            Some(amount) => amount.decimal_to_u32(store.amount_scale)?,
+            None => return Ok(()),
+        };
+        let history = self.history.get(&tx.client);
+
+        match kind {
+            TransactionKind::Withdrawal => {
+                if let Some(max) = limits.max_withdrawal {
+                    if amount > max.decimal_to_u32(store.amount_scale)? {
+                        return Err(RuleViolation::MaxWithdrawal.into());
+                    }
+                }
+                if let Some(rolling) = limits.rolling_withdrawals {
+                    let withdrawn: u64 = recent(history, rolling.window)
+                        .filter_map(|activity| match activity {
+                            Activity::Withdrawal { amount, .. } => Some(*amount as u64),
+                            _ => None,
+                        })
+                        .sum();
+                    if withdrawn + amount as u64
+                        > rolling.max_total.decimal_to_u32(store.amount_scale)? as u64
+                    {
+                        return Err(RuleViolation::RollingWithdrawals.into());
+                    }
+                }
+                // Daily totals follow the UTC day of the transaction timestamp,
+                // so they only apply to timestamped rows.
+                if let (Some(max), Some(at)) = (limits.daily_withdrawals, tx.timestamp) {
+                    let withdrawn = match self.daily.get(&tx.client) {
+                        Some(&(day, total)) if day == at.day() => total,
+                        _ => 0,
+                    };
+                    if withdrawn + amount as u64 > max.decimal_to_u32(store.amount_scale)? as u64 {
+                        return Err(RuleViolation::DailyWithdrawals.into());
+                    }
+                }
+            }
+            TransactionKind::Deposit => {
+                if let Some(velocity) = limits.deposit_velocity {
+                    let deposits = recent(history, velocity.window)
+                        .filter(|activity| matches!(activity, Activity::Deposit))
+                        .count();
+                    if deposits + 1 > velocity.max_deposits {
+                        return Err(RuleViolation::DepositVelocity.into());
+                    }
+                }
+                if let Some(max) = limits.max_balance {
+                    let total = store
+                        .get_account(&tx.client)
+                        .map_or(0, |account| account.available + account.held);
+                    if store.amount_scale.to_decimal(total + u64::from(amount)) > max {
+                        return Err(RuleViolation::MaxBalance.into());
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    pub fn record(&mut self, client: &ClientId, activity: Activity) {
+        if let (
+            true,
+            Activity::Withdrawal {
+                amount,
+                day: Some(day),
+            },
+        ) = (self.tracks_daily, activity)
+        {
+            let daily = self.daily.entry(client.clone()).or_insert((day, 0));
+            if daily.0 != day {
+                *daily = (day, 0);
+            }
+            daily.1 += amount as u64;
+        }
+
+        if self.window == 0 {
+            return;
+        }
+
+        // The current transaction is always part of the window, so only the
+        // `window - 1` previous ones need to be kept around.
+        let history = self.history.entry(client.clone()).or_default();
+        history.push_back(activity);
+        while history.len() >= self.window {
+            history.pop_front();
+        }
+    }
+
+    pub fn checkpoint(&self, client: &ClientId) -> RulesCheckpoint {
+        RulesCheckpoint {
+            client: client.clone(),
+            history: self.history.get(client).cloned(),
+            daily: self.daily.get(client).copied(),
+        }
+    }
+
+    pub fn restore(&mut self, checkpoint: RulesCheckpoint) {
+        let RulesCheckpoint {
+            client,
+            history,
+            daily,
+        } = checkpoint;
+        match history {
+            Some(history) => self.history.insert(client.clone(), history),
+            None => self.history.remove(&client),
+        };
+        match daily {
+            Some(daily) => self.daily.insert(client, daily),
+            None => self.daily.remove(&client),
+        };
+    }
+}
+
+fn recent(history: Option<&VecDeque<Activity>>, window: usize) -> impl Iterator<Item = &Activity> {
+    history
+        .into_iter()
+        .flat_map(move |h| h.iter().skip(h.len().saturating_sub(window - 1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ids::TxId;
+    use std::str::FromStr;
+
+    fn input_transaction(transaction_type: &str, client: u16, amount: &str) -> InputTransaction {
+        InputTransaction {
+            transaction_type: transaction_type.to_string(),
+            client: client.into(),
+            tx: TxId::Num(0),
+            amount: Some(Decimal::from_str(amount).unwrap()),
+            timestamp: None,
+            tenant: None,
+        }
+    }
+
+    fn rule_engine(config: &str) -> RuleEngine {
+        let config: LimitsConfig = toml::from_str(config).unwrap();
+        config.validate().unwrap();
+        RuleEngine::new(config)
+    }
+
+    fn check(rules: &RuleEngine, store: &Storage, tx: &InputTransaction) -> Result<()> {
+        rules.check(store, tx.transaction_type.parse()?, tx, None)
+    }
+
+    fn violation(result: Result<()>) -> RuleViolation {
+        *result.unwrap_err().downcast_ref::<RuleViolation>().unwrap()
+    }
+
+    #[test]
+    fn test_max_withdrawal_with_client_override() {
+        let rules = rule_engine(
+            r#"
+            max_withdrawal = 100
+
+            [[clients]]
+            client = 2
+            max_withdrawal = 1000
+            "#,
+        );
+        let store = Storage::new();
+
+        let tx = input_transaction("withdrawal", 1, "150");
+        assert_eq!(
+            violation(check(&rules, &store, &tx)),
+            RuleViolation::MaxWithdrawal
+        );
+
+        let tx = input_transaction("withdrawal", 2, "150");
+        assert!(check(&rules, &store, &tx).is_ok());
+    }
+
+    #[test]
+    fn test_tier_limits_precedence() {
+        let rules = rule_engine(
+            r#"
+            max_withdrawal = 100
+            max_balance = 1000
+
+            [[tiers]]
+            tier = "premium"
+            max_withdrawal = 500
+
+            [[clients]]
+            client = 2
+            max_balance = 200
+            "#,
+        );
+
+        let limits = rules.limits_for(&ClientId::Num(1), None);
+        assert_eq!(limits.max_withdrawal, Some(Decimal::from(100)));
+
+        let limits = rules.limits_for(&ClientId::Num(1), Some("premium"));
+        assert_eq!(limits.max_withdrawal, Some(Decimal::from(500)));
+        assert_eq!(limits.max_balance, Some(Decimal::from(1000)));
+
+        let limits = rules.limits_for(&ClientId::Num(2), Some("premium"));
+        assert_eq!(limits.max_withdrawal, Some(Decimal::from(500)));
+        assert_eq!(limits.max_balance, Some(Decimal::from(200)));
+
+        let limits = rules.limits_for(&ClientId::Num(1), Some("unknown"));
+        assert_eq!(limits.max_withdrawal, Some(Decimal::from(100)));
+    }
+
+    #[test]
+    fn test_rolling_withdrawals() {
+        let mut rules = rule_engine(
+            r#"
+            [rolling_withdrawals]
+            window = 3
+            max_total = 100
+            "#,
+        );
+        let store = Storage::new();
+
+        for amount in ["40", "40"] {
+            let tx = input_transaction("withdrawal", 1, amount);
+            assert!(check(&rules, &store, &tx).is_ok());
+            rules.record(
+                &ClientId::Num(1),
+                Activity::of(
+                    tx.transaction_type.parse().unwrap(),
+                    &tx,
+                    AmountScale::default(),
+                ),
+            );
+        }
+
+        let tx = input_transaction("withdrawal", 1, "30");
+        assert_eq!(
+            violation(check(&rules, &store, &tx)),
+            RuleViolation::RollingWithdrawals
+        );
+
+        // Once the first withdrawal leaves the window, there is room again.
+        rules.record(&ClientId::Num(1), Activity::Deposit);
+        assert!(check(&rules, &store, &tx).is_ok());
+    }
+
+    #[test]
+    fn test_deposit_velocity() {
+        let mut rules = rule_engine(
+            r#"
+            [deposit_velocity]
+            window = 4
+            max_deposits = 2
+            "#,
+        );
+        let store = Storage::new();
+
+        for _ in 0..2 {
+            let tx = input_transaction("deposit", 1, "10");
+            assert!(check(&rules, &store, &tx).is_ok());
+            rules.record(
+                &ClientId::Num(1),
+                Activity::of(
+                    tx.transaction_type.parse().unwrap(),
+                    &tx,
+                    AmountScale::default(),
+                ),
+            );
+        }
+
+        let tx = input_transaction("deposit", 1, "10");
+        assert_eq!(
+            violation(check(&rules, &store, &tx)),
+            RuleViolation::DepositVelocity
+        );
+        assert!(check(&rules, &store, &input_transaction("deposit", 2, "10")).is_ok());
+    }
+
+    #[test]
+    fn test_max_balance() {
+        let rules = rule_engine("max_balance = 50");
+        let mut store = Storage::new();
+        store.get_account_mut(&ClientId::Num(1)).available = 4000;
+
+        let tx = input_transaction("deposit", 1, "10.01");
+        assert_eq!(
+            violation(check(&rules, &store, &tx)),
+            RuleViolation::MaxBalance
+        );
+        assert!(check(&rules, &store, &input_transaction("deposit", 1, "10")).is_ok());
+    }
+
+    #[test]
+    fn test_daily_withdrawals() {
+        let mut rules = rule_engine("daily_withdrawals = 100");
+        let store = Storage::new();
+
+        let mut withdraw = |amount: &str, at: &str| {
+            let mut tx = input_transaction("withdrawal", 1, amount);
+            tx.timestamp = Some(at.parse().unwrap());
+            let result = check(&rules, &store, &tx);
+            if result.is_ok() {
+                rules.record(
+                    &ClientId::Num(1),
+                    Activity::of(TransactionKind::Withdrawal, &tx, AmountScale::default()),
+                );
+            }
+            result
+        };
+
+        assert!(withdraw("60", "2024-03-01T08:00:00Z").is_ok());
+        assert_eq!(
+            violation(withdraw("50", "2024-03-01T20:00:00Z")),
+            RuleViolation::DailyWithdrawals
+        );
+        assert!(withdraw("40", "2024-03-01T21:00:00Z").is_ok());
+        assert!(withdraw("90", "2024-03-02T00:30:00Z").is_ok());
+    }
+
+    #[test]
+    fn test_invalid_window() {
+        let config: LimitsConfig = toml::from_str(
+            r#"
+            [[clients]]
+            client = 3
+            deposit_velocity = { window = 0, max_deposits = 1 }
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+    }
+}
@@ -1,3 +1,9 @@
+use crate::models::ids::ClientId;
+
+// Fixed rather than derived from the number of CPUs, so that a client lands
+// in the same shard on every machine.
+pub const DEFAULT_SHARD_COUNT: usize = 8;
+
 #[derive(Debug)]
 pub struct Shards<T> {
     pub(crate) shards: Vec<T>,
@@ -13,11 +19,19 @@ impl<T> Shards<T> {
         }
     }
 
-    pub fn shard_id(&self, client_id: u16) -> usize {
-        (client_id as usize) % self.shard_count
+    pub fn shard_id(&self, client_id: &ClientId) -> usize {
+        shard_of(client_id, self.shard_count)
     }
 
-    pub fn get_shard(&mut self, client_id: u16) -> &mut T {
+    pub fn shard_count(&self) -> usize {
+        self.shard_count
+    }
+
+    pub fn get(&self, client_id: &ClientId) -> &T {
+        &self.shards[self.shard_id(client_id)]
+    }
+
+    pub fn get_shard(&mut self, client_id: &ClientId) -> &mut T {
         let shard_id = self.shard_id(client_id);
         &mut self.shards[shard_id]
     }
@@ -26,3 +40,49 @@ impl<T> Shards<T> {
         &self.shards
     }
 }
+
+// The shard of `client_id` among `shard_count`, for the callers routing
+// transactions ahead of the engine.
+pub(crate) fn shard_of(client_id: &ClientId, shard_count: usize) -> usize {
+    jump_hash(client_id.stable_hash(), shard_count)
+}
+
+// Jump consistent hash (Lamping & Veach): growing from n to n + 1 shards only
+// moves 1 / (n + 1) of the keys, all of them to the new shard.
+fn jump_hash(mut key: u64, buckets: usize) -> usize {
+    let (mut b, mut j) = (-1i64, 0i64);
+    while j < buckets as i64 {
+        b = j;
+        key = key.wrapping_mul(2_862_933_555_777_941_757).wrapping_add(1);
+        j = ((b + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    b.max(0) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jump_hash_is_stable() {
+        // Pinned values: changing them would move clients across shards.
+        let shards: Vec<usize> = (0..8).map(|key| jump_hash(key, 10)).collect();
+        assert_eq!(shards, vec![0, 6, 6, 8, 1, 4, 9, 0]);
+        assert_eq!(jump_hash(42, 1), 0);
+    }
+
+    #[test]
+    fn test_jump_hash_moves_keys_to_new_shard_only() {
+        let mut moved = 0;
+        for key in 0..10_000u64 {
+            let before = jump_hash(key, 7);
+            let after = jump_hash(key, 8);
+            if before != after {
+                assert_eq!(after, 7);
+                moved += 1;
+            }
+        }
+        // About 1/8 of the keys.
+        assert!((1_000..1_500).contains(&moved), "moved {}", moved);
+    }
+}
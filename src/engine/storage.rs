@@ -1,70 +1,425 @@
 use crate::{
-    engine::sharding::Shards,
-    models::{account::Account, output_record::OutputRecord, transaction::Transaction},
-    num_cpus,
+    engine::{
+        archive::{ArchiveBackend, ArchivedTransaction},
+        ledger::LedgerEvent,
+        sharding::{Shards, DEFAULT_SHARD_COUNT},
+        utils::{AmountScale, DecimalToU32},
+    },
+    models::{
+        account::Account,
+        ids::{ClientId, TxId},
+        output_record::OutputRecord,
+        timestamp::Timestamp,
+        transaction::{DisputeState, Transaction},
+        transaction_kind::TransactionKind,
+    },
 };
+use anyhow::Result;
 use lru::LruCache;
-use std::{collections::HashMap, num::NonZeroUsize};
+use std::{borrow::Cow, collections::HashMap, num::NonZeroUsize};
+use tracing::warn;
+
+// Transactions kept in the LRU cache of each shard.
+pub const DEFAULT_CACHE_CAPACITY: usize = 100_000;
+// Accounts each shard has room for upfront.
+pub const DEFAULT_ACCOUNT_CAPACITY: usize = 1_000;
 
 pub struct Storage {
-    pub accounts: Shards<HashMap<u16, Account>>,
-    pub transactions: Shards<LruCache<u32, Transaction>>,
+    pub accounts: Shards<HashMap<ClientId, Account>>,
+    pub transactions: Shards<LruCache<TxId, Transaction>>,
+    // Transactions pushed out of each shard's LRU cache, since the last
+    // rebalance.
+    pub evictions: Vec<u64>,
+    // Where evicted transactions go, when set. Lookups missing the cache
+    // fall back to it.
+    pub archive: Option<Box<dyn ArchiveBackend>>,
+    pub log_evictions: bool,
+    pub amount_scale: AmountScale,
+    // Recorded by the handlers while applying a transaction, not applied
+    // yet. Drained by the engine, which commits them.
+    pub(crate) events: Vec<LedgerEvent>,
+    // Ticks at every use of a cached transaction.
+    recency: u64,
+    cache_capacity: NonZeroUsize,
+    account_capacity: usize,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Storage {
     pub fn new() -> Self {
-        let num_shards = std::cmp::max(4, num_cpus::get_cpus());
+        Self::with_shards(DEFAULT_SHARD_COUNT)
+    }
 
+    pub fn with_shards(num_shards: usize) -> Self {
+        let capacity = NonZeroUsize::new(DEFAULT_CACHE_CAPACITY).unwrap();
+        Self::with_capacity(num_shards, capacity, DEFAULT_ACCOUNT_CAPACITY)
+    }
+
+    pub fn with_capacity(
+        num_shards: usize,
+        cache_capacity: NonZeroUsize,
+        account_capacity: usize,
+    ) -> Self {
         let accounts = (0..num_shards)
-            .map(|_| HashMap::with_capacity(1000))
+            .map(|_| HashMap::with_capacity(account_capacity))
             .collect();
         let transactions = (0..num_shards)
-            .map(|_| LruCache::new(NonZeroUsize::new(100000).unwrap()))
+            .map(|_| LruCache::new(cache_capacity))
             .collect();
 
         Self {
             accounts: Shards::new(accounts),
             transactions: Shards::new(transactions),
+            evictions: vec![0; num_shards],
+            archive: None,
+            log_evictions: false,
+            amount_scale: AmountScale::default(),
+            events: Vec::new(),
+            recency: 0,
+            cache_capacity,
+            account_capacity,
+        }
+    }
+
+    // Shrinking the caches evicts their least recently used transactions.
+    pub fn set_cache_capacity(&mut self, capacity: NonZeroUsize) {
+        self.cache_capacity = capacity;
+        for shard_id in 0..self.shard_count() {
+            while self.transactions.shards[shard_id].len() > capacity.get() {
+                if let Some((tx_id, tx)) = self.transactions.shards[shard_id].pop_lru() {
+                    self.evicted(shard_id, tx_id, tx);
+                }
+            }
+            self.transactions.shards[shard_id].resize(capacity);
+        }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.accounts.shard_count()
+    }
+
+    // Of all the shards together.
+    pub fn total_cache_capacity(&self) -> usize {
+        self.cache_capacity.get() * self.shard_count()
+    }
+
+    pub fn shard_id(&self, client_id: &ClientId) -> usize {
+        self.accounts.shard_id(client_id)
+    }
+
+    // Moves every account and cached transaction to its shard in a new set of
+    // `num_shards` shards. The caches keep the same capacity in total, so
+    // that fewer shards do not evict, and transactions keep their recency
+    // order across shards.
+    pub fn rebalance(&mut self, num_shards: usize) {
+        let total = self.cache_capacity.get() * self.shard_count();
+        let capacity = NonZeroUsize::new(total.div_ceil(num_shards)).unwrap_or(self.cache_capacity);
+        let new = Self::with_capacity(num_shards, capacity, self.account_capacity);
+        let mut old = std::mem::replace(self, new);
+        self.archive = old.archive.take();
+        self.log_evictions = old.log_evictions;
+        self.amount_scale = old.amount_scale;
+        self.recency = old.recency;
+
+        for shard in old.accounts.shards {
+            for (client_id, account) in shard {
+                self.accounts
+                    .get_shard(&client_id)
+                    .insert(client_id, account);
+            }
+        }
+        let mut transactions: Vec<_> = old.transactions.shards.into_iter().flatten().collect();
+        transactions.sort_by_key(|(_, tx)| tx.last_used);
+        for (tx_id, tx) in transactions {
+            self.store_transaction(tx_id, tx);
+        }
+    }
+
+    // The only way the handlers change the state. The event is only applied
+    // when the engine commits it, once it is in the ledger: until then,
+    // `pending_account` tells what it does to the account.
+    pub(crate) fn record(&mut self, event: LedgerEvent) {
+        self.events.push(event);
+    }
+
+    // The account of `client` as the events recorded so far leave it, `None`
+    // when it neither exists nor is opened by one of them.
+    pub(crate) fn pending_account(&self, client: &ClientId) -> Result<Option<Account>> {
+        let mut account = self.get_account(client).copied();
+        for event in &self.events {
+            if event.accounts().any(|c| c == client) {
+                let pending = account.get_or_insert_with(Account::new);
+                apply_to_account(pending, client, event, self.amount_scale)?;
+            }
+        }
+        Ok(account)
+    }
+
+    // Folds one event into the state. The handlers checked the event made
+    // sense when recording it, so amounts saturate rather than fail.
+    pub(crate) fn apply_event(&mut self, event: &LedgerEvent) -> Result<()> {
+        let scale = self.amount_scale;
+        for client in event.accounts() {
+            apply_to_account(self.get_account_mut(client), client, event, scale)?;
+        }
+        match event {
+            LedgerEvent::Deposited {
+                client,
+                tx,
+                amount,
+                timestamp,
+            } => {
+                let amount = amount.decimal_to_u32(scale)?;
+                self.store_new_transaction(
+                    *tx,
+                    client,
+                    TransactionKind::Deposit,
+                    amount,
+                    *timestamp,
+                );
+            }
+            LedgerEvent::Withdrawn {
+                client,
+                tx,
+                amount,
+                timestamp,
+            } => {
+                // Withdrawals are not disputable, they are only kept to
+                // recognize re-submitted rows.
+                let amount = amount.decimal_to_u32(scale)?;
+                self.store_new_transaction(
+                    *tx,
+                    client,
+                    TransactionKind::Withdrawal,
+                    amount,
+                    *timestamp,
+                );
+            }
+            LedgerEvent::DisputeOpened { client, tx, .. } => {
+                self.set_dispute_state(*tx, client, DisputeState::Disputed);
+            }
+            LedgerEvent::DisputeResolved { client, tx, .. } => {
+                self.set_dispute_state(*tx, client, DisputeState::Resolved);
+            }
+            LedgerEvent::ChargedBack { client, tx, .. } => {
+                self.set_dispute_state(*tx, client, DisputeState::ChargedBack);
+            }
+            LedgerEvent::AccountOpened { .. }
+            | LedgerEvent::FeeCharged { .. }
+            | LedgerEvent::AccountLocked { .. } => {}
         }
+        Ok(())
+    }
+
+    fn store_new_transaction(
+        &mut self,
+        tx_id: TxId,
+        client: &ClientId,
+        kind: TransactionKind,
+        amount: u32,
+        timestamp: Option<Timestamp>,
+    ) {
+        let tx = Transaction {
+            client: client.clone(),
+            kind,
+            amount,
+            state: DisputeState::Undisputed,
+            timestamp,
+            last_used: 0,
+        };
+        self.store_transaction(tx_id, tx);
+    }
+
+    // Brings an archived transaction back to the cache first, as a ledger
+    // being replayed may have evicted it since.
+    fn set_dispute_state(&mut self, tx_id: TxId, client_id: &ClientId, state: DisputeState) {
+        self.get_transaction(tx_id, client_id);
+        self.update_transaction_dispute(tx_id, client_id, state);
     }
 
     pub fn collect_accounts(&self) -> Vec<OutputRecord> {
         let mut all_accounts = Vec::new();
 
         for shard in self.accounts.shards_slices() {
-            for (&client_id, &account) in shard {
-                all_accounts.push(OutputRecord {
-                    client: client_id,
-                    available: account.available_as_decimal(),
-                    held: account.held_as_decimal(),
-                    total: account.total_as_decimal(),
-                    locked: account.locked,
-                });
+            for (client_id, account) in shard {
+                all_accounts.push(OutputRecord::new(
+                    client_id.clone(),
+                    account,
+                    self.amount_scale,
+                ));
             }
         }
 
-        all_accounts.sort_by_key(|r| r.client);
+        all_accounts.sort_by(|a, b| a.client.cmp(&b.client));
         all_accounts
     }
 
-    pub fn get_account_mut(&mut self, client_id: u16) -> &mut Account {
+    pub fn get_account(&self, client_id: &ClientId) -> Option<&Account> {
+        self.accounts.get(client_id).get(client_id)
+    }
+
+    pub fn get_account_mut(&mut self, client_id: &ClientId) -> &mut Account {
         self.accounts
             .get_shard(client_id)
-            .entry(client_id)
-            .or_insert_with(Account::new)
+            .entry(client_id.clone())
+            .or_default()
     }
 
-    pub fn store_transaction(&mut self, tx_id: u32, tx: Transaction) {
-        self.transactions.get_shard(tx.client).put(tx_id, tx);
+    pub fn store_transaction(&mut self, tx_id: TxId, mut tx: Transaction) {
+        tx.last_used = self.tick();
+        let shard_id = self.shard_id(&tx.client);
+        // `push` also returns the previous entry when the id is replaced.
+        if let Some((evicted, evicted_tx)) = self.transactions.shards[shard_id].push(tx_id, tx) {
+            if evicted != tx_id {
+                self.evicted(shard_id, evicted, evicted_tx);
+            }
+        }
     }
 
-    pub fn get_transaction(&mut self, tx_id: u32, client_id: u16) -> Option<Transaction> {
-        self.transactions.get_shard(client_id).get(&tx_id).copied()
+    fn tick(&mut self) -> u64 {
+        self.recency += 1;
+        self.recency
     }
 
-    pub fn update_transaction_dispute(&mut self, tx_id: u32, client_id: u16, disputed: bool) {
+    fn evicted(&mut self, shard_id: usize, tx_id: TxId, tx: Transaction) {
+        self.evictions[shard_id] += 1;
+        if self.log_evictions {
+            warn!(
+                tx = %tx_id,
+                client = %tx.client,
+                shard = shard_id,
+                archived = self.archive.is_some(),
+                "transaction evicted from the cache"
+            );
+        }
+        if let Some(archive) = &mut self.archive {
+            if let Err(e) = archive.append(tx_id, ArchivedTransaction(tx)) {
+                warn!(tx = %tx_id, error = %e, "cannot archive evicted transaction");
+            }
+        }
+    }
+
+    fn archived(&self, tx_id: TxId) -> Option<Transaction> {
+        let archive = self.archive.as_ref()?;
+        archive
+            .get(tx_id)
+            .inspect_err(|e| warn!(tx = %tx_id, error = %e, "cannot read archived transaction"))
+            .ok()
+            .flatten()
+            .map(|archived| archived.0)
+    }
+
+    // Archived transactions are moved back to the cache, where their dispute
+    // state can be updated.
+    pub fn get_transaction(&mut self, tx_id: TxId, client_id: &ClientId) -> Option<Transaction> {
+        let tick = self.tick();
         if let Some(tx) = self.transactions.get_shard(client_id).get_mut(&tx_id) {
-            tx.disputed = disputed;
+            tx.last_used = tick;
+            return Some(tx.clone());
+        }
+        let tx = self.archived(tx_id)?;
+        if tx.client != *client_id {
+            return Some(tx);
+        }
+        self.store_transaction(tx_id, tx.clone());
+        Some(tx)
+    }
+
+    pub fn peek_transaction(
+        &self,
+        tx_id: TxId,
+        client_id: &ClientId,
+    ) -> Option<Cow<'_, Transaction>> {
+        match self.transactions.get(client_id).peek(&tx_id) {
+            Some(tx) => Some(Cow::Borrowed(tx)),
+            None => self.archived(tx_id).map(Cow::Owned),
+        }
+    }
+
+    // Transactions are sharded by client, so finding one by id alone means
+    // looking into every shard.
+    pub fn find_transaction(&self, tx_id: TxId) -> Option<Cow<'_, Transaction>> {
+        let cached = self
+            .transactions
+            .shards_slices()
+            .iter()
+            .find_map(|shard| shard.peek(&tx_id));
+        match cached {
+            Some(tx) => Some(Cow::Borrowed(tx)),
+            None => self.archived(tx_id).map(Cow::Owned),
+        }
+    }
+
+    // Cached transactions only.
+    pub fn client_transactions<'a>(
+        &'a self,
+        client_id: &'a ClientId,
+    ) -> impl Iterator<Item = (&'a TxId, &'a Transaction)> + 'a {
+        self.transactions
+            .get(client_id)
+            .iter()
+            .filter(move |(_, tx)| tx.client == *client_id)
+    }
+
+    pub fn update_transaction_dispute(
+        &mut self,
+        tx_id: TxId,
+        client_id: &ClientId,
+        state: DisputeState,
+    ) {
+        let tick = self.tick();
+        if let Some(tx) = self.transactions.get_shard(client_id).get_mut(&tx_id) {
+            tx.state = state;
+            tx.last_used = tick;
+        }
+    }
+}
+
+// What `event` does to the balances of `client`, one of its accounts.
+fn apply_to_account(
+    account: &mut Account,
+    client: &ClientId,
+    event: &LedgerEvent,
+    scale: AmountScale,
+) -> Result<()> {
+    match event {
+        LedgerEvent::AccountOpened { .. } => {}
+        LedgerEvent::Deposited { amount, .. } => {
+            let amount = u64::from(amount.decimal_to_u32(scale)?);
+            account.available = account.available.saturating_add(amount);
+        }
+        LedgerEvent::Withdrawn { amount, .. } => {
+            let amount = u64::from(amount.decimal_to_u32(scale)?);
+            account.available = account.available.saturating_sub(amount);
+        }
+        LedgerEvent::FeeCharged { house, amount, .. } => {
+            let amount = u64::from(amount.decimal_to_u32(scale)?);
+            account.available = if client == house {
+                account.available.saturating_add(amount)
+            } else {
+                account.available.saturating_sub(amount)
+            };
+        }
+        LedgerEvent::DisputeOpened { amount, .. } => {
+            let amount = u64::from(amount.decimal_to_u32(scale)?);
+            account.available = account.available.saturating_sub(amount);
+            account.held = account.held.saturating_add(amount);
+        }
+        LedgerEvent::DisputeResolved { amount, .. } => {
+            let amount = u64::from(amount.decimal_to_u32(scale)?);
+            account.held = account.held.saturating_sub(amount);
+            account.available = account.available.saturating_add(amount);
+        }
+        LedgerEvent::ChargedBack { amount, .. } => {
+            let amount = u64::from(amount.decimal_to_u32(scale)?);
+            account.held = account.held.saturating_sub(amount);
         }
+        LedgerEvent::AccountLocked { .. } => account.locked = true,
     }
+    Ok(())
 }
@@ -0,0 +1,209 @@
+use crate::{
+    engine::{
+        config::EngineConfig, fees::FeeSchedule, metrics::MetricsSnapshot, Outcome, ToyEngine,
+    },
+    models::{input_transaction::InputTransaction, output_record::TenantOutputRecord},
+};
+use anyhow::{Context, Result};
+use serde::{de::Error, Deserialize, Deserializer};
+use std::{collections::BTreeMap, fs, path::Path};
+
+// Rows without a tenant column, or with an empty one, belong to this tenant.
+pub const DEFAULT_TENANT: &str = "default";
+
+// Settings a tenant can override over the engine defaults. The currency is
+// not used by the engine, it only labels the tenant's output.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TenantConfig {
+    pub currency: Option<String>,
+    // In days. Not set, the engine's applies. Set to `None`, written "none"
+    // in the file, disputes are accepted whatever their age.
+    #[serde(default, deserialize_with = "dispute_window")]
+    pub dispute_window: Option<Option<u64>>,
+    pub fees: Option<FeeSchedule>,
+}
+
+fn dispute_window<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Option<u64>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Window {
+        Days(u64),
+        Keyword(String),
+    }
+    match Window::deserialize(deserializer)? {
+        Window::Days(days) => Ok(Some(Some(days))),
+        Window::Keyword(keyword) if keyword == "none" => Ok(Some(None)),
+        Window::Keyword(keyword) => Err(D::Error::custom(format!(
+            "expected a number of days or \"none\", got \"{}\"",
+            keyword
+        ))),
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TenantsConfig {
+    #[serde(default)]
+    pub tenants: BTreeMap<String, TenantConfig>,
+}
+
+impl TenantsConfig {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Cannot read tenants {}", path.display()))?;
+        let config: Self = toml::from_str(&content)
+            .with_context(|| format!("Invalid tenants {}", path.display()))?;
+        for (name, tenant) in &config.tenants {
+            if let Some(Some(days)) = tenant.dispute_window {
+                let window = EngineConfig {
+                    dispute_window: Some(days),
+                    ..EngineConfig::default()
+                };
+                window
+                    .validate()
+                    .with_context(|| format!("Invalid tenant {} in {}", name, path.display()))?;
+            }
+            if let Some(fees) = &tenant.fees {
+                fees.validate().with_context(|| {
+                    format!("Invalid fees of tenant {} in {}", name, path.display())
+                })?;
+            }
+        }
+        Ok(config)
+    }
+}
+
+// Creates the engine of a tenant from its config.
+type EngineFactory = Box<dyn Fn(&TenantConfig) -> Result<ToyEngine>>;
+
+// Routes every transaction to the engine of its tenant. Tenants share
+// nothing: client and transaction ids only have to be unique within a tenant,
+// and each tenant has its own fees and house account.
+//
+// Engines are created on the first transaction of their tenant by
+// `make_engine`, from the tenant config or the default one for tenants
+// missing from the config.
+pub struct TenantRouter {
+    make_engine: EngineFactory,
+    configs: BTreeMap<String, TenantConfig>,
+    engines: BTreeMap<String, ToyEngine>,
+}
+
+impl TenantRouter {
+    pub fn new(make_engine: impl Fn(&TenantConfig) -> Result<ToyEngine> + 'static) -> Self {
+        Self {
+            make_engine: Box::new(make_engine),
+            configs: BTreeMap::new(),
+            engines: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_tenants(mut self, config: TenantsConfig) -> Self {
+        self.configs = config.tenants;
+        self
+    }
+
+    pub fn dispatch(&mut self, tx: InputTransaction) -> Result<Outcome> {
+        let tenant = match tx.tenant.as_deref() {
+            Some(tenant) if !tenant.is_empty() => tenant,
+            _ => DEFAULT_TENANT,
+        };
+        if !self.engines.contains_key(tenant) {
+            let engine = match self.configs.get(tenant) {
+                Some(config) => (self.make_engine)(config),
+                None => (self.make_engine)(&TenantConfig::default()),
+            }
+            .with_context(|| format!("Cannot create the engine of tenant {}", tenant))?;
+            self.engines.insert(tenant.to_string(), engine);
+        }
+        let engine = self.engines.get_mut(tenant).expect("engine just created");
+        engine.dispatch(tx)
+    }
+
+    pub fn engine(&self, tenant: &str) -> Option<&ToyEngine> {
+        self.engines.get(tenant)
+    }
+
+    // Tenants that received at least one transaction, sorted by name.
+    pub fn tenants(&self) -> impl Iterator<Item = (&str, &ToyEngine)> {
+        self.engines
+            .iter()
+            .map(|(tenant, engine)| (tenant.as_str(), engine))
+    }
+
+    // Metrics of all tenants added up.
+    pub fn metrics(&self) -> MetricsSnapshot {
+        let mut metrics = MetricsSnapshot::default();
+        for (_, engine) in self.tenants() {
+            metrics.merge(&engine.metrics());
+        }
+        metrics
+    }
+
+    // Accounts grouped by tenant, then sorted by client.
+    pub fn get_all_accounts(&self) -> Vec<TenantOutputRecord> {
+        self.tenants()
+            .flat_map(|(tenant, engine)| {
+                let currency = self
+                    .configs
+                    .get(tenant)
+                    .and_then(|config| config.currency.clone());
+                engine.get_all_accounts().into_iter().map(move |record| {
+                    TenantOutputRecord::new(tenant.to_string(), currency.clone(), record)
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tenants_from_toml() {
+        let config: TenantsConfig = toml::from_str(
+            r#"
+            [tenants.acme]
+            currency = "EUR"
+            dispute_window = 30
+
+            [tenants.acme.fees.withdrawal]
+            kind = "flat"
+            amount = 0.5
+
+            [tenants.globex]
+            currency = "USD"
+            "#,
+        )
+        .unwrap();
+
+        let acme = &config.tenants["acme"];
+        assert_eq!(acme.currency.as_deref(), Some("EUR"));
+        assert_eq!(acme.dispute_window, Some(Some(30)));
+        assert!(acme.fees.as_ref().is_some_and(|fees| !fees.is_empty()));
+        assert!(config.tenants["globex"].fees.is_none());
+        assert_eq!(config.tenants["globex"].dispute_window, None);
+    }
+
+    #[test]
+    fn test_tenant_dispute_window_can_be_cleared() {
+        let config: TenantsConfig = toml::from_str(
+            r#"
+            [tenants.acme]
+            dispute_window = "none"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.tenants["acme"].dispute_window, Some(None));
+
+        let invalid = |config: &str| toml::from_str::<TenantsConfig>(config).is_err();
+        assert!(invalid("[tenants.acme]\ndispute_window = \"never\""));
+        assert!(invalid("[tenants.acme]\ndispute_windows = 30"));
+        assert!(invalid("[tenant.acme]\ncurrency = \"EUR\""));
+    }
+}
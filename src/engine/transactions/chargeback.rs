@@ -1,17 +1,44 @@
+use crate::engine::fees::{post_fee, FeeSchedule};
+use crate::engine::ledger::LedgerEvent;
+use crate::engine::observer::LockReason;
+use crate::engine::outcome::{IgnoreReason, Outcome};
 use crate::engine::storage::Storage;
-use crate::models::input_transaction::InputTransaction;
+use crate::models::{input_transaction::InputTransaction, transaction::DisputeState};
 use anyhow::Result;
 
-pub fn chargeback(store: &mut Storage, tx: InputTransaction) -> Result<()> {
-    if let Some(original_tx) = store.get_transaction(tx.tx, tx.client) {
-        if original_tx.client == tx.client && original_tx.disputed {
-            let account = store.get_account_mut(tx.client);
+#[tracing::instrument(level = "debug", skip_all)]
+pub fn chargeback(
+    store: &mut Storage,
+    fees: &FeeSchedule,
+    tx: InputTransaction,
+) -> Result<Outcome> {
+    let original_tx = match store.get_transaction(tx.tx, &tx.client) {
+        Some(original_tx) if original_tx.client == tx.client => original_tx,
+        _ => return Ok(Outcome::Ignored(IgnoreReason::UnknownTransaction)),
+    };
 
-            account.held = account.held.saturating_sub(original_tx.amount);
-            account.locked = true;
+    if original_tx.state != DisputeState::Disputed {
+        return Ok(Outcome::Ignored(IgnoreReason::NotDisputed));
+    }
+
+    let fee = fees.chargeback_fee(original_tx.amount, store.amount_scale)?;
+    let Some(account) = store.pending_account(&tx.client)? else {
+        return Ok(Outcome::Ignored(IgnoreReason::UnknownAccount));
+    };
+    let already_locked = account.locked;
 
-            store.update_transaction_dispute(tx.tx, tx.client, false);
-        }
+    store.record(LedgerEvent::ChargedBack {
+        client: tx.client.clone(),
+        tx: tx.tx,
+        amount: store.amount_scale.to_decimal(original_tx.amount as u64),
+    });
+    if !already_locked {
+        store.record(LedgerEvent::AccountLocked {
+            client: tx.client.clone(),
+            reason: LockReason::Chargeback,
+        });
     }
-    Ok(())
+    post_fee(store, fees, &tx.client, fee)?;
+
+    Ok(Outcome::Applied)
 }
@@ -1,34 +1,39 @@
+use crate::engine::ledger::LedgerEvent;
+use crate::engine::outcome::{IgnoreReason, Outcome};
+use crate::engine::policy::LockedAccountPolicy;
 use crate::engine::storage::Storage;
 use crate::engine::utils::DecimalToU32;
 use crate::models::input_transaction::InputTransaction;
-use crate::models::transaction::Transaction;
 use anyhow::{anyhow, Result};
 
-pub fn deposit(store: &mut Storage, tx: InputTransaction) -> Result<()> {
+#[tracing::instrument(level = "debug", skip_all)]
+pub fn deposit(
+    store: &mut Storage,
+    locked: &LockedAccountPolicy,
+    tx: InputTransaction,
+) -> Result<Outcome> {
     let amount = tx
         .amount
         .ok_or_else(|| anyhow!("Deposit requires amount"))?;
-    let amount_centimes = amount.decimal_to_u32()?;
+    let amount_centimes = amount.decimal_to_u32(store.amount_scale)?;
 
     if amount_centimes == 0 {
         return Err(anyhow!("Deposit amount must be positive -> tx ignored"));
     }
 
-    let account = store.get_account_mut(tx.client);
-
-    if account.locked {
-        return Ok(());
+    let account_locked = store.pending_account(&tx.client)?.is_some_and(|a| a.locked);
+    if account_locked && !locked.accepts_deposits() {
+        return Ok(Outcome::Ignored(IgnoreReason::AccountLocked));
     }
 
-    account.available = account.available.saturating_add(amount_centimes);
-
-    let stored_tx = Transaction {
+    // Opens the account when missing, the engine already made sure the
+    // account policy allows it.
+    store.record(LedgerEvent::Deposited {
         client: tx.client,
-        amount: amount_centimes,
-        disputed: false,
-    };
-
-    store.store_transaction(tx.tx, stored_tx);
+        tx: tx.tx,
+        amount: store.amount_scale.to_decimal(amount_centimes as u64),
+        timestamp: tx.timestamp,
+    });
 
-    Ok(())
+    Ok(Outcome::Applied)
 }
@@ -1,19 +1,55 @@
+use crate::engine::ledger::LedgerEvent;
+use crate::engine::outcome::{IgnoreReason, Outcome};
+use crate::engine::policy::DisputePolicy;
 use crate::engine::storage::Storage;
-use crate::models::input_transaction::InputTransaction;
+use crate::models::{
+    input_transaction::InputTransaction, transaction::DisputeState,
+    transaction_kind::TransactionKind,
+};
 use anyhow::Result;
 
-pub fn dispute(store: &mut Storage, tx: InputTransaction) -> Result<()> {
-    if let Some(original_tx) = store.get_transaction(tx.tx, tx.client) {
-        if original_tx.client == tx.client && !original_tx.disputed {
-            let account = store.get_account_mut(tx.client);
+#[tracing::instrument(level = "debug", skip_all)]
+pub fn dispute(
+    store: &mut Storage,
+    policy: &DisputePolicy,
+    tx: InputTransaction,
+) -> Result<Outcome> {
+    let original_tx = match store.get_transaction(tx.tx, &tx.client) {
+        Some(original_tx) if original_tx.client == tx.client => original_tx,
+        _ => return Ok(Outcome::Ignored(IgnoreReason::UnknownTransaction)),
+    };
 
-            if !account.locked && account.available >= original_tx.amount {
-                account.available -= original_tx.amount;
-                account.held += original_tx.amount;
+    if original_tx.kind != TransactionKind::Deposit {
+        return Ok(Outcome::Ignored(IgnoreReason::NotDisputable));
+    }
+
+    match original_tx.state {
+        DisputeState::Disputed => return Ok(Outcome::Ignored(IgnoreReason::AlreadyDisputed)),
+        DisputeState::ChargedBack => return Ok(Outcome::Ignored(IgnoreReason::NotDisputable)),
+        DisputeState::Undisputed | DisputeState::Resolved => {}
+    }
+
+    if policy.is_expired(&original_tx, tx.timestamp) {
+        return Ok(Outcome::Ignored(IgnoreReason::DisputeWindowExpired));
+    }
 
-                store.update_transaction_dispute(tx.tx, tx.client, true);
-            }
-        }
+    let Some(account) = store.pending_account(&tx.client)? else {
+        return Ok(Outcome::Ignored(IgnoreReason::UnknownAccount));
+    };
+
+    if account.locked {
+        return Ok(Outcome::Ignored(IgnoreReason::AccountLocked));
+    }
+
+    if account.available < u64::from(original_tx.amount) {
+        return Ok(Outcome::Ignored(IgnoreReason::InsufficientFunds));
     }
-    Ok(())
+
+    store.record(LedgerEvent::DisputeOpened {
+        client: tx.client,
+        tx: tx.tx,
+        amount: store.amount_scale.to_decimal(original_tx.amount as u64),
+    });
+
+    Ok(Outcome::Applied)
 }
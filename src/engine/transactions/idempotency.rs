@@ -0,0 +1,55 @@
+use crate::engine::outcome::{Outcome, TransactionConflict};
+use crate::engine::storage::Storage;
+use crate::engine::utils::DecimalToU32;
+use crate::models::{
+    input_transaction::InputTransaction, transaction::DisputeState,
+    transaction_kind::TransactionKind,
+};
+use anyhow::Result;
+
+// Detects rows re-submitted by a retrying upstream. Returns
+// `Some(Outcome::Duplicate)` when the row is identical to one already applied,
+// and a `TransactionConflict` error when its id is already taken by a
+// different transaction. The timestamp of a row `stamped` by the engine clock
+// is not compared, a re-submission being stamped anew.
+pub fn replayed(
+    store: &Storage,
+    kind: TransactionKind,
+    tx: &InputTransaction,
+    stamped: bool,
+) -> Result<Option<Outcome>> {
+    match kind {
+        TransactionKind::Deposit | TransactionKind::Withdrawal => {
+            let Some(existing) = store.find_transaction(tx.tx) else {
+                return Ok(None);
+            };
+            let amount = tx
+                .amount
+                .and_then(|amount| amount.decimal_to_u32(store.amount_scale).ok());
+            let identical = existing.kind == kind
+                && existing.client == tx.client
+                && Some(existing.amount) == amount
+                && (stamped || existing.timestamp == tx.timestamp);
+
+            if identical {
+                Ok(Some(Outcome::Duplicate))
+            } else {
+                Err(TransactionConflict { tx: tx.tx }.into())
+            }
+        }
+        TransactionKind::Dispute | TransactionKind::Resolve | TransactionKind::Chargeback => {
+            let applied_state = match kind {
+                TransactionKind::Dispute => DisputeState::Disputed,
+                TransactionKind::Resolve => DisputeState::Resolved,
+                _ => DisputeState::ChargedBack,
+            };
+            let duplicate = store
+                .peek_transaction(tx.tx, &tx.client)
+                .is_some_and(|existing| {
+                    existing.client == tx.client && existing.state == applied_state
+                });
+
+            Ok(duplicate.then_some(Outcome::Duplicate))
+        }
+    }
+}
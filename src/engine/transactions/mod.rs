@@ -1,27 +1,100 @@
 mod chargeback;
 mod deposit;
 mod dispute;
+mod idempotency;
 mod resolve;
 mod withdrawal;
 
 pub use chargeback::chargeback;
 pub use deposit::deposit;
 pub use dispute::dispute;
+pub use idempotency::replayed;
 pub use resolve::resolve;
 pub use withdrawal::withdrawal;
 
 #[cfg(test)]
 mod tests {
-    use super::*;
+    use crate::engine::fees::{Fee, FeeRule, FeeSchedule};
+    use crate::engine::ledger::LedgerEvent;
+    use crate::engine::observer::LockReason;
+    use crate::engine::outcome::{IgnoreReason, Outcome};
+    use crate::engine::policy::{DisputePolicy, LockedAccountPolicy};
     use crate::engine::storage::Storage;
-    use crate::models::input_transaction::InputTransaction;
+    use crate::models::{ids::ClientId, input_transaction::InputTransaction};
+    use anyhow::Result;
     use rust_decimal::Decimal;
     use std::str::FromStr;
+    use std::time::Duration;
 
     fn test_storage() -> Storage {
         Storage::new()
     }
 
+    // The handlers only record their events, which the engine applies once
+    // they are in the ledger. These wrappers apply them right away.
+    fn committed(store: &mut Storage, result: Result<Outcome>) -> Result<Outcome> {
+        for event in std::mem::take(&mut store.events) {
+            store.apply_event(&event).unwrap();
+        }
+        result
+    }
+
+    fn deposit(
+        store: &mut Storage,
+        locked: &LockedAccountPolicy,
+        tx: InputTransaction,
+    ) -> Result<Outcome> {
+        let result = super::deposit(store, locked, tx);
+        committed(store, result)
+    }
+
+    fn withdrawal(
+        store: &mut Storage,
+        fees: &FeeSchedule,
+        tx: InputTransaction,
+    ) -> Result<Outcome> {
+        let result = super::withdrawal(store, fees, tx);
+        committed(store, result)
+    }
+
+    fn dispute(
+        store: &mut Storage,
+        policy: &DisputePolicy,
+        tx: InputTransaction,
+    ) -> Result<Outcome> {
+        let result = super::dispute(store, policy, tx);
+        committed(store, result)
+    }
+
+    fn resolve(store: &mut Storage, tx: InputTransaction) -> Result<Outcome> {
+        let result = super::resolve(store, tx);
+        committed(store, result)
+    }
+
+    fn chargeback(
+        store: &mut Storage,
+        fees: &FeeSchedule,
+        tx: InputTransaction,
+    ) -> Result<Outcome> {
+        let result = super::chargeback(store, fees, tx);
+        committed(store, result)
+    }
+
+    fn flat_fees(withdrawal: &str, chargeback: &str) -> FeeSchedule {
+        let flat = |amount: &str| Fee {
+            rule: FeeRule::Flat {
+                amount: Decimal::from_str(amount).unwrap(),
+            },
+            min: None,
+            max: None,
+        };
+        FeeSchedule {
+            house_account: ClientId::Num(0),
+            withdrawal: Some(flat(withdrawal)),
+            chargeback: Some(flat(chargeback)),
+        }
+    }
+
     fn input_transaction(
         transaction_type: &str,
         client: u16,
@@ -30,9 +103,11 @@ mod tests {
     ) -> InputTransaction {
         InputTransaction {
             transaction_type: transaction_type.to_string(),
-            client,
-            tx,
+            client: client.into(),
+            tx: tx.into(),
             amount: amount.map(|a| Decimal::from_str(a).unwrap()),
+            timestamp: None,
+            tenant: None,
         }
     }
 
@@ -44,10 +119,10 @@ mod tests {
             let mut storage = test_storage();
             let tx = input_transaction("deposit", 1, 1, Some("10.50"));
 
-            let result = deposit(&mut storage, tx);
+            let result = deposit(&mut storage, &LockedAccountPolicy::default(), tx);
             assert!(result.is_ok());
 
-            let account = storage.get_account_mut(1);
+            let account = storage.get_account_mut(&ClientId::Num(1));
             assert_eq!(account.available, 1050);
             assert_eq!(account.held, 0);
             assert!(!account.locked);
@@ -58,7 +133,7 @@ mod tests {
             let mut storage = test_storage();
             let tx = input_transaction("deposit", 1, 1, None);
 
-            let result = deposit(&mut storage, tx);
+            let result = deposit(&mut storage, &LockedAccountPolicy::default(), tx);
             assert!(result.is_err());
             assert!(result
                 .unwrap_err()
@@ -71,7 +146,7 @@ mod tests {
             let mut storage = test_storage();
             let tx = input_transaction("deposit", 1, 1, Some("0"));
 
-            let result = deposit(&mut storage, tx);
+            let result = deposit(&mut storage, &LockedAccountPolicy::default(), tx);
             assert!(result.is_err());
             assert!(result
                 .unwrap_err()
@@ -83,16 +158,24 @@ mod tests {
         fn test_deposit_on_locked_account() {
             let mut storage = test_storage();
 
-            let account = storage.get_account_mut(1);
+            let account = storage.get_account_mut(&ClientId::Num(1));
             account.locked = true;
 
             let tx = input_transaction("deposit", 1, 1, Some("10.00"));
-            let result = deposit(&mut storage, tx);
+            let result = deposit(&mut storage, &LockedAccountPolicy::default(), tx);
 
             assert!(result.is_ok());
-            let account = storage.get_account_mut(1);
+            let account = storage.get_account_mut(&ClientId::Num(1));
             assert_eq!(account.available, 0);
             assert!(account.locked);
+
+            let tx = input_transaction("deposit", 1, 2, Some("10.00"));
+            let result = deposit(&mut storage, &LockedAccountPolicy::AcceptDeposits, tx);
+
+            assert_eq!(result.unwrap(), Outcome::Applied);
+            let account = storage.get_account_mut(&ClientId::Num(1));
+            assert_eq!(account.available, 1000);
+            assert!(account.locked);
         }
     }
 
@@ -104,13 +187,13 @@ mod tests {
             let mut storage = test_storage();
 
             let deposit_tx = input_transaction("deposit", 1, 1, Some("20.00"));
-            deposit(&mut storage, deposit_tx).unwrap();
+            deposit(&mut storage, &LockedAccountPolicy::default(), deposit_tx).unwrap();
 
             let withdrawal_tx = input_transaction("withdrawal", 1, 2, Some("5.00"));
-            let result = withdrawal(&mut storage, withdrawal_tx);
+            let result = withdrawal(&mut storage, &FeeSchedule::default(), withdrawal_tx);
 
             assert!(result.is_ok());
-            let account = storage.get_account_mut(1);
+            let account = storage.get_account_mut(&ClientId::Num(1));
             assert_eq!(account.available, 1500);
         }
 
@@ -119,22 +202,56 @@ mod tests {
             let mut storage = test_storage();
 
             let deposit_tx = input_transaction("deposit", 1, 1, Some("5.00"));
-            deposit(&mut storage, deposit_tx).unwrap();
+            deposit(&mut storage, &LockedAccountPolicy::default(), deposit_tx).unwrap();
 
             let withdrawal_tx = input_transaction("withdrawal", 1, 2, Some("10.00"));
-            let result = withdrawal(&mut storage, withdrawal_tx);
+            let result = withdrawal(&mut storage, &FeeSchedule::default(), withdrawal_tx);
 
             assert!(result.is_ok());
-            let account = storage.get_account_mut(1);
+            let account = storage.get_account_mut(&ClientId::Num(1));
             assert_eq!(account.available, 500);
         }
 
+        #[test]
+        fn test_withdrawal_reports_outcome() {
+            let mut storage = test_storage();
+
+            let deposit_tx = input_transaction("deposit", 1, 1, Some("5.00"));
+            deposit(&mut storage, &LockedAccountPolicy::default(), deposit_tx).unwrap();
+
+            let fees = FeeSchedule::default();
+            let withdrawal_tx = input_transaction("withdrawal", 1, 2, Some("10.00"));
+            assert_eq!(
+                withdrawal(&mut storage, &fees, withdrawal_tx).unwrap(),
+                Outcome::Ignored(IgnoreReason::InsufficientFunds)
+            );
+
+            let withdrawal_tx = input_transaction("withdrawal", 1, 3, Some("5.00"));
+            assert_eq!(
+                withdrawal(&mut storage, &fees, withdrawal_tx).unwrap(),
+                Outcome::Applied
+            );
+        }
+
+        #[test]
+        fn test_withdrawal_unknown_account() {
+            let mut storage = test_storage();
+
+            let fees = FeeSchedule::default();
+            let withdrawal_tx = input_transaction("withdrawal", 1, 1, Some("10.00"));
+            assert_eq!(
+                withdrawal(&mut storage, &fees, withdrawal_tx).unwrap(),
+                Outcome::Ignored(IgnoreReason::UnknownAccount)
+            );
+            assert!(storage.get_account(&ClientId::Num(1)).is_none());
+        }
+
         #[test]
         fn test_withdrawal_no_amount() {
             let mut storage = test_storage();
             let tx = input_transaction("withdrawal", 1, 1, None);
 
-            let result = withdrawal(&mut storage, tx);
+            let result = withdrawal(&mut storage, &FeeSchedule::default(), tx);
             assert!(result.is_err());
             assert!(result
                 .unwrap_err()
@@ -147,19 +264,51 @@ mod tests {
             let mut storage = test_storage();
 
             let deposit_tx = input_transaction("deposit", 1, 1, Some("20.00"));
-            deposit(&mut storage, deposit_tx).unwrap();
+            deposit(&mut storage, &LockedAccountPolicy::default(), deposit_tx).unwrap();
 
-            let account = storage.get_account_mut(1);
+            let account = storage.get_account_mut(&ClientId::Num(1));
             account.locked = true;
 
             let withdrawal_tx = input_transaction("withdrawal", 1, 2, Some("5.00"));
-            let result = withdrawal(&mut storage, withdrawal_tx);
+            let result = withdrawal(&mut storage, &FeeSchedule::default(), withdrawal_tx);
 
             assert!(result.is_ok());
-            let account = storage.get_account_mut(1);
+            let account = storage.get_account_mut(&ClientId::Num(1));
             assert_eq!(account.available, 2000);
             assert!(account.locked);
         }
+
+        #[test]
+        fn test_withdrawal_with_fee() {
+            let mut storage = test_storage();
+            let fees = flat_fees("1.00", "0");
+
+            let deposit_tx = input_transaction("deposit", 1, 1, Some("20.00"));
+            deposit(&mut storage, &LockedAccountPolicy::default(), deposit_tx).unwrap();
+
+            let withdrawal_tx = input_transaction("withdrawal", 1, 2, Some("5.00"));
+            let result = withdrawal(&mut storage, &fees, withdrawal_tx);
+
+            assert!(result.is_ok());
+            assert_eq!(storage.get_account_mut(&ClientId::Num(1)).available, 1400);
+            assert_eq!(storage.get_account_mut(&ClientId::Num(0)).available, 100);
+        }
+
+        #[test]
+        fn test_withdrawal_insufficient_funds_for_fee() {
+            let mut storage = test_storage();
+            let fees = flat_fees("1.00", "0");
+
+            let deposit_tx = input_transaction("deposit", 1, 1, Some("5.50"));
+            deposit(&mut storage, &LockedAccountPolicy::default(), deposit_tx).unwrap();
+
+            let withdrawal_tx = input_transaction("withdrawal", 1, 2, Some("5.00"));
+            let result = withdrawal(&mut storage, &fees, withdrawal_tx);
+
+            assert!(result.is_ok());
+            assert_eq!(storage.get_account_mut(&ClientId::Num(1)).available, 550);
+            assert_eq!(storage.get_account_mut(&ClientId::Num(0)).available, 0);
+        }
     }
 
     mod dispute_tests {
@@ -172,7 +321,7 @@ mod tests {
             amount: &str,
         ) {
             let deposit_tx = input_transaction("deposit", client, tx_id, Some(amount));
-            deposit(storage, deposit_tx).unwrap();
+            deposit(storage, &LockedAccountPolicy::default(), deposit_tx).unwrap();
         }
 
         #[test]
@@ -181,10 +330,10 @@ mod tests {
             setup_account_with_deposit(&mut storage, 1, 1, "10.00");
 
             let dispute_tx = input_transaction("dispute", 1, 1, None);
-            let result = dispute(&mut storage, dispute_tx);
+            let result = dispute(&mut storage, &DisputePolicy::default(), dispute_tx);
 
             assert!(result.is_ok());
-            let account = storage.get_account_mut(1);
+            let account = storage.get_account_mut(&ClientId::Num(1));
             assert_eq!(account.available, 0);
             assert_eq!(account.held, 1000);
             assert!(!account.locked);
@@ -196,10 +345,10 @@ mod tests {
             setup_account_with_deposit(&mut storage, 1, 1, "10.00");
 
             let dispute_tx = input_transaction("dispute", 1, 999, None); // Non-existent tx
-            let result = dispute(&mut storage, dispute_tx);
+            let result = dispute(&mut storage, &DisputePolicy::default(), dispute_tx);
 
             assert!(result.is_ok());
-            let account = storage.get_account_mut(1);
+            let account = storage.get_account_mut(&ClientId::Num(1));
             assert_eq!(account.available, 1000);
             assert_eq!(account.held, 0);
         }
@@ -210,10 +359,10 @@ mod tests {
             setup_account_with_deposit(&mut storage, 1, 1, "10.00");
 
             let dispute_tx = input_transaction("dispute", 2, 1, None); // Wrong client
-            let result = dispute(&mut storage, dispute_tx);
+            let result = dispute(&mut storage, &DisputePolicy::default(), dispute_tx);
 
             assert!(result.is_ok());
-            let account1 = storage.get_account_mut(1);
+            let account1 = storage.get_account_mut(&ClientId::Num(1));
             assert_eq!(account1.available, 1000);
             assert_eq!(account1.held, 0);
         }
@@ -224,16 +373,43 @@ mod tests {
             setup_account_with_deposit(&mut storage, 1, 1, "10.00");
 
             let withdrawal_tx = input_transaction("withdrawal", 1, 2, Some("8.00"));
-            withdrawal(&mut storage, withdrawal_tx).unwrap();
+            withdrawal(&mut storage, &FeeSchedule::default(), withdrawal_tx).unwrap();
 
             let dispute_tx = input_transaction("dispute", 1, 1, None);
-            let result = dispute(&mut storage, dispute_tx);
+            let result = dispute(&mut storage, &DisputePolicy::default(), dispute_tx);
 
             assert!(result.is_ok());
-            let account = storage.get_account_mut(1);
+            let account = storage.get_account_mut(&ClientId::Num(1));
             assert_eq!(account.available, 200);
             assert_eq!(account.held, 0);
         }
+        #[test]
+        fn test_dispute_after_window_expired() {
+            let mut storage = test_storage();
+            let policy = DisputePolicy {
+                max_age: Some(Duration::from_secs(90 * 86_400)),
+            };
+
+            let mut deposit_tx = input_transaction("deposit", 1, 1, Some("10.00"));
+            deposit_tx.timestamp = Some("2024-01-01T00:00:00Z".parse().unwrap());
+            deposit(&mut storage, &LockedAccountPolicy::default(), deposit_tx).unwrap();
+
+            let mut dispute_tx = input_transaction("dispute", 1, 1, None);
+            dispute_tx.timestamp = Some("2024-04-01T00:00:00Z".parse().unwrap());
+            let result = dispute(&mut storage, &policy, dispute_tx.clone());
+
+            assert_eq!(
+                result.unwrap(),
+                Outcome::Ignored(IgnoreReason::DisputeWindowExpired)
+            );
+            assert_eq!(storage.get_account_mut(&ClientId::Num(1)).held, 0);
+
+            dispute_tx.timestamp = Some("2024-03-30T00:00:00Z".parse().unwrap());
+            let result = dispute(&mut storage, &policy, dispute_tx);
+
+            assert_eq!(result.unwrap(), Outcome::Applied);
+            assert_eq!(storage.get_account_mut(&ClientId::Num(1)).held, 1000);
+        }
     }
 
     mod resolve_tests {
@@ -246,10 +422,10 @@ mod tests {
             amount: &str,
         ) {
             let deposit_tx = input_transaction("deposit", client, tx_id, Some(amount));
-            deposit(storage, deposit_tx).unwrap();
+            deposit(storage, &LockedAccountPolicy::default(), deposit_tx).unwrap();
 
             let dispute_tx = input_transaction("dispute", client, tx_id, None);
-            dispute(storage, dispute_tx).unwrap();
+            dispute(storage, &DisputePolicy::default(), dispute_tx).unwrap();
         }
 
         #[test]
@@ -261,7 +437,7 @@ mod tests {
             let result = resolve(&mut storage, resolve_tx);
 
             assert!(result.is_ok());
-            let account = storage.get_account_mut(1);
+            let account = storage.get_account_mut(&ClientId::Num(1));
             assert_eq!(account.available, 1000);
             assert_eq!(account.held, 0);
             assert!(!account.locked);
@@ -276,7 +452,7 @@ mod tests {
             let result = resolve(&mut storage, resolve_tx);
 
             assert!(result.is_ok());
-            let account = storage.get_account_mut(1);
+            let account = storage.get_account_mut(&ClientId::Num(1));
             assert_eq!(account.available, 0);
             assert_eq!(account.held, 1000);
         }
@@ -285,13 +461,13 @@ mod tests {
         fn test_resolve_not_disputed() {
             let mut storage = test_storage();
             let deposit_tx = input_transaction("deposit", 1, 1, Some("10.00"));
-            deposit(&mut storage, deposit_tx).unwrap();
+            deposit(&mut storage, &LockedAccountPolicy::default(), deposit_tx).unwrap();
 
             let resolve_tx = input_transaction("resolve", 1, 1, None);
             let result = resolve(&mut storage, resolve_tx);
 
             assert!(result.is_ok());
-            let account = storage.get_account_mut(1);
+            let account = storage.get_account_mut(&ClientId::Num(1));
             assert_eq!(account.available, 1000);
             assert_eq!(account.held, 0);
         }
@@ -305,7 +481,7 @@ mod tests {
             let result = resolve(&mut storage, resolve_tx);
 
             assert!(result.is_ok());
-            let account1 = storage.get_account_mut(1);
+            let account1 = storage.get_account_mut(&ClientId::Num(1));
             assert_eq!(account1.available, 0);
             assert_eq!(account1.held, 1000);
         }
@@ -321,10 +497,10 @@ mod tests {
             amount: &str,
         ) {
             let deposit_tx = input_transaction("deposit", client, tx_id, Some(amount));
-            deposit(storage, deposit_tx).unwrap();
+            deposit(storage, &LockedAccountPolicy::default(), deposit_tx).unwrap();
 
             let dispute_tx = input_transaction("dispute", client, tx_id, None);
-            dispute(storage, dispute_tx).unwrap();
+            dispute(storage, &DisputePolicy::default(), dispute_tx).unwrap();
         }
 
         #[test]
@@ -333,10 +509,30 @@ mod tests {
             setup_disputed_transaction(&mut storage, 1, 1, "10.00");
 
             let chargeback_tx = input_transaction("chargeback", 1, 1, None);
-            let result = chargeback(&mut storage, chargeback_tx);
+            let result =
+                super::super::chargeback(&mut storage, &FeeSchedule::default(), chargeback_tx);
 
+            // Nothing is applied before the engine commits the events.
             assert!(result.is_ok());
-            let account = storage.get_account_mut(1);
+            assert_eq!(storage.get_account_mut(&ClientId::Num(1)).held, 1000);
+            let amount = Decimal::from(10);
+            assert_eq!(
+                storage.events,
+                [
+                    LedgerEvent::ChargedBack {
+                        client: ClientId::Num(1),
+                        tx: 1u32.into(),
+                        amount,
+                    },
+                    LedgerEvent::AccountLocked {
+                        client: ClientId::Num(1),
+                        reason: LockReason::Chargeback,
+                    },
+                ]
+            );
+
+            committed(&mut storage, result).unwrap();
+            let account = storage.get_account_mut(&ClientId::Num(1));
             assert_eq!(account.available, 0);
             assert_eq!(account.held, 0);
             assert!(account.locked);
@@ -348,10 +544,10 @@ mod tests {
             setup_disputed_transaction(&mut storage, 1, 1, "10.00");
 
             let chargeback_tx = input_transaction("chargeback", 1, 999, None); // Non-existent
-            let result = chargeback(&mut storage, chargeback_tx);
+            let result = chargeback(&mut storage, &FeeSchedule::default(), chargeback_tx);
 
             assert!(result.is_ok());
-            let account = storage.get_account_mut(1);
+            let account = storage.get_account_mut(&ClientId::Num(1));
             assert_eq!(account.available, 0);
             assert_eq!(account.held, 1000);
             assert!(!account.locked);
@@ -363,13 +559,33 @@ mod tests {
             setup_disputed_transaction(&mut storage, 1, 1, "10.00");
 
             let chargeback_tx = input_transaction("chargeback", 2, 1, None); // Wrong client
-            let result = chargeback(&mut storage, chargeback_tx);
+            let result = chargeback(&mut storage, &FeeSchedule::default(), chargeback_tx);
 
             assert!(result.is_ok());
-            let account1 = storage.get_account_mut(1);
+            let account1 = storage.get_account_mut(&ClientId::Num(1));
             assert_eq!(account1.available, 0);
             assert_eq!(account1.held, 1000);
             assert!(!account1.locked);
         }
+
+        #[test]
+        fn test_chargeback_fee_capped_by_available_funds() {
+            let mut storage = test_storage();
+            let fees = flat_fees("0", "15.00");
+            setup_disputed_transaction(&mut storage, 1, 1, "10.00");
+
+            let deposit_tx = input_transaction("deposit", 1, 2, Some("4.00"));
+            deposit(&mut storage, &LockedAccountPolicy::default(), deposit_tx).unwrap();
+
+            let chargeback_tx = input_transaction("chargeback", 1, 1, None);
+            let result = chargeback(&mut storage, &fees, chargeback_tx);
+
+            assert!(result.is_ok());
+            let account = storage.get_account_mut(&ClientId::Num(1));
+            assert_eq!(account.available, 0);
+            assert_eq!(account.held, 0);
+            assert!(account.locked);
+            assert_eq!(storage.get_account_mut(&ClientId::Num(0)).available, 400);
+        }
     }
 }
@@ -1,16 +1,31 @@
-use crate::{engine::storage::Storage, models::input_transaction::InputTransaction};
+use crate::engine::ledger::LedgerEvent;
+use crate::engine::outcome::{IgnoreReason, Outcome};
+use crate::{
+    engine::storage::Storage,
+    models::{input_transaction::InputTransaction, transaction::DisputeState},
+};
 use anyhow::Result;
 
-pub fn resolve(store: &mut Storage, tx: InputTransaction) -> Result<()> {
-    if let Some(original_tx) = store.get_transaction(tx.tx, tx.client) {
-        if original_tx.client == tx.client && original_tx.disputed {
-            let account = store.get_account_mut(tx.client);
+#[tracing::instrument(level = "debug", skip_all)]
+pub fn resolve(store: &mut Storage, tx: InputTransaction) -> Result<Outcome> {
+    let original_tx = match store.get_transaction(tx.tx, &tx.client) {
+        Some(original_tx) if original_tx.client == tx.client => original_tx,
+        _ => return Ok(Outcome::Ignored(IgnoreReason::UnknownTransaction)),
+    };
 
-            account.held = account.held.saturating_sub(original_tx.amount);
-            account.available += original_tx.amount;
+    if original_tx.state != DisputeState::Disputed {
+        return Ok(Outcome::Ignored(IgnoreReason::NotDisputed));
+    }
 
-            store.update_transaction_dispute(tx.tx, tx.client, false);
-        }
+    if store.pending_account(&tx.client)?.is_none() {
+        return Ok(Outcome::Ignored(IgnoreReason::UnknownAccount));
     }
-    Ok(())
+
+    store.record(LedgerEvent::DisputeResolved {
+        client: tx.client,
+        tx: tx.tx,
+        amount: store.amount_scale.to_decimal(original_tx.amount as u64),
+    });
+
+    Ok(Outcome::Applied)
 }
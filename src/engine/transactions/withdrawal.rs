@@ -1,25 +1,50 @@
-use crate::engine::{storage::Storage, utils::DecimalToU32};
+use crate::engine::{
+    fees::{post_fee, FeeSchedule},
+    ledger::LedgerEvent,
+    outcome::{IgnoreReason, Outcome},
+    storage::Storage,
+    utils::DecimalToU32,
+};
 use crate::models::input_transaction::InputTransaction;
 use anyhow::{anyhow, Result};
 
-pub fn withdrawal(store: &mut Storage, tx: InputTransaction) -> Result<()> {
+#[tracing::instrument(level = "debug", skip_all)]
+pub fn withdrawal(
+    store: &mut Storage,
+    fees: &FeeSchedule,
+    tx: InputTransaction,
+) -> Result<Outcome> {
     let amount = tx
         .amount
         .ok_or_else(|| anyhow!("Withdrawal requires amount"))?;
-    let amount_centimes = amount.decimal_to_u32()?;
+    let amount_centimes = amount.decimal_to_u32(store.amount_scale)?;
 
     if amount_centimes == 0 {
         return Err(anyhow!("Withdrawal amount must be positive -> tx ignored"));
     }
 
-    let account = store.get_account_mut(tx.client);
+    let fee = fees.withdrawal_fee(amount_centimes, store.amount_scale)?;
+    let required = u64::from(amount_centimes) + u64::from(fee);
+
+    let Some(account) = store.pending_account(&tx.client)? else {
+        return Ok(Outcome::Ignored(IgnoreReason::UnknownAccount));
+    };
 
     if account.locked {
-        return Ok(());
+        return Ok(Outcome::Ignored(IgnoreReason::AccountLocked));
     }
 
-    if account.available >= amount_centimes {
-        account.available -= amount_centimes;
+    if account.available < required {
+        return Ok(Outcome::Ignored(IgnoreReason::InsufficientFunds));
     }
-    Ok(())
+
+    store.record(LedgerEvent::Withdrawn {
+        client: tx.client.clone(),
+        tx: tx.tx,
+        amount: store.amount_scale.to_decimal(amount_centimes as u64),
+        timestamp: tx.timestamp,
+    });
+    post_fee(store, fees, &tx.client, fee)?;
+
+    Ok(Outcome::Applied)
 }
@@ -2,22 +2,57 @@ use anyhow::{anyhow, Result};
 use rust_decimal::prelude::ToPrimitive;
 use rust_decimal::Decimal;
 
+pub const DEFAULT_AMOUNT_SCALE: u32 = 2;
+pub const MAX_AMOUNT_SCALE: u32 = 4;
+
+// Number of decimal places kept on amounts, which are stored as integers of
+// the smallest unit: centimes with the default scale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AmountScale(u32);
+
+impl AmountScale {
+    pub fn new(places: u32) -> Result<Self> {
+        if places > MAX_AMOUNT_SCALE {
+            return Err(anyhow!(
+                "amount_scale must be at most {}, got {}",
+                MAX_AMOUNT_SCALE,
+                places
+            ));
+        }
+        Ok(AmountScale(places))
+    }
+
+    fn factor(&self) -> Decimal {
+        Decimal::from(10u32.pow(self.0))
+    }
+
+    pub(crate) fn to_decimal(self, units: u64) -> Decimal {
+        Decimal::from(units) / self.factor()
+    }
+}
+
+impl Default for AmountScale {
+    fn default() -> Self {
+        AmountScale(DEFAULT_AMOUNT_SCALE)
+    }
+}
+
 pub trait DecimalToU32 {
-    fn decimal_to_u32(self) -> Result<u32>;
+    fn decimal_to_u32(self, scale: AmountScale) -> Result<u32>;
 }
 
 impl DecimalToU32 for Decimal {
-    fn decimal_to_u32(self) -> Result<u32> {
+    fn decimal_to_u32(self, scale: AmountScale) -> Result<u32> {
         if self < Decimal::ZERO {
             return Err(anyhow!("Amount cannot be negative"));
         }
 
-        const MAX_AMOUNT: Decimal = Decimal::from_parts(4294967295, 0, 0, false, 2);
-        if self > MAX_AMOUNT {
+        let max_amount = Decimal::from_parts(u32::MAX, 0, 0, false, scale.0);
+        if self > max_amount {
             return Err(anyhow!("Amount too large"));
         }
 
-        let result = (self * Decimal::from(100))
+        let result = (self * scale.factor())
             .to_u32()
             .ok_or_else(|| anyhow!("Invalid amount precision"))?;
 
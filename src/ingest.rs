@@ -0,0 +1,289 @@
+use crate::{
+    engine::sharding::shard_of,
+    models::{ids::ClientId, input_transaction::InputTransaction},
+    server::outcome_json,
+    ToyEngine,
+};
+use anyhow::{anyhow, Result};
+use csv::{ReaderBuilder, StringRecord};
+use serde_json::{json, Value};
+use std::{
+    io::{BufRead, BufReader, BufWriter, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+};
+use tiny_http::{Header, Method, Response, Server};
+use tracing::{error, warn};
+
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+const CSV_HEADERS: [&str; 5] = ["type", "client", "tx", "amount", "timestamp"];
+
+enum Job {
+    Apply(InputTransaction),
+    Reject(String),
+}
+
+// Where the engine thread sends the acknowledgements of a connection.
+#[derive(Clone)]
+struct Connection {
+    acks: SyncSender<Value>,
+    stream: Arc<TcpStream>,
+}
+
+// The transactions in the queue, counted by the engine shard of their client.
+struct QueueDepths(Vec<AtomicUsize>);
+
+impl QueueDepths {
+    fn new(shard_count: usize) -> Self {
+        Self((0..shard_count).map(|_| AtomicUsize::new(0)).collect())
+    }
+
+    fn of(&self, client: &ClientId) -> &AtomicUsize {
+        &self.0[shard_of(client, self.0.len())]
+    }
+}
+
+// Accepts newline-delimited CSV or JSON transactions on every connection and
+// writes back one JSON acknowledgement line per transaction, in order.
+//
+// All connections feed a single engine thread through a bounded queue: when
+// it is full, connections stop reading from their socket until the engine
+// catches up, which pushes the backpressure down to the senders. Each
+// connection also stops reading once it has as many transactions waiting for
+// their acknowledgement as the capacity, so that a sender not reading them
+// only stalls itself. The engine thread never waits on a connection: one
+// whose acknowledgement queue is full anyway is closed.
+pub fn serve(listener: TcpListener, engine: ToyEngine, queue_capacity: usize) -> Result<()> {
+    serve_with_metrics(listener, engine, queue_capacity, None)
+}
+
+// Same as `serve`, also serving the engine metrics and the queue depth of
+// each shard over HTTP on `GET /metrics` of the `metrics` listener.
+pub fn serve_with_metrics(
+    listener: TcpListener,
+    engine: ToyEngine,
+    queue_capacity: usize,
+    metrics: Option<TcpListener>,
+) -> Result<()> {
+    let depth = Arc::new(QueueDepths::new(engine.shard_count()));
+    let engine = Arc::new(Mutex::new(engine));
+
+    if let Some(metrics) = metrics {
+        let server = Server::from_listener(metrics, None).map_err(|e| anyhow!(e))?;
+        let (engine, depth) = (engine.clone(), depth.clone());
+        thread::spawn(move || serve_metrics(server, engine, depth));
+    }
+
+    let (jobs, queue) = mpsc::sync_channel(queue_capacity);
+    let queued = depth.clone();
+    thread::spawn(move || run_engine(engine, queue, queued));
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let jobs = jobs.clone();
+        let depth = depth.clone();
+        thread::spawn(move || handle_connection(stream, jobs, depth, queue_capacity));
+    }
+
+    Ok(())
+}
+
+fn serve_metrics(server: Server, engine: Arc<Mutex<ToyEngine>>, depth: Arc<QueueDepths>) {
+    let text =
+        Header::from_bytes("Content-Type", "text/plain; version=0.0.4").expect("valid header");
+    for request in server.incoming_requests() {
+        let response = if *request.method() == Method::Get && request.url() == "/metrics" {
+            let mut metrics = match engine.lock() {
+                Ok(engine) => engine.metrics(),
+                Err(_) => {
+                    error!("engine poisoned by a panic, metrics stopped");
+                    return;
+                }
+            };
+            for (shard, depth) in metrics.shards.iter_mut().zip(&depth.0) {
+                shard.queue_depth = Some(depth.load(Ordering::Relaxed));
+            }
+            Response::from_string(metrics.to_prometheus()).with_header(text.clone())
+        } else {
+            Response::from_string("not found").with_status_code(404)
+        };
+        let _ = request.respond(response);
+    }
+}
+
+fn run_engine(
+    engine: Arc<Mutex<ToyEngine>>,
+    queue: Receiver<(Job, Connection)>,
+    depth: Arc<QueueDepths>,
+) {
+    for (job, connection) in queue {
+        let reply = match job {
+            Job::Apply(tx) => {
+                depth.of(&tx.client).fetch_sub(1, Ordering::Relaxed);
+                let tx_id = tx.tx;
+                let result = match engine.lock() {
+                    Ok(mut engine) => engine.dispatch(tx),
+                    Err(_) => {
+                        error!("engine poisoned by a panic, ingest stopped");
+                        return;
+                    }
+                };
+                let mut reply = outcome_json(&result);
+                reply["tx"] = json!(tx_id);
+                reply
+            }
+            Job::Reject(error) => json!({ "outcome": "rejected", "error": error }),
+        };
+        match connection.acks.try_send(reply) {
+            // The connection may be gone already, there is nobody left to tell.
+            Ok(()) | Err(TrySendError::Disconnected(_)) => {}
+            Err(TrySendError::Full(_)) => {
+                warn!("acknowledgement queue full, closing the connection");
+                let _ = connection.stream.shutdown(Shutdown::Both);
+            }
+        }
+    }
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    jobs: SyncSender<(Job, Connection)>,
+    depth: Arc<QueueDepths>,
+    ack_capacity: usize,
+) -> Result<()> {
+    // At least one transaction has to be in flight to make progress.
+    let ack_capacity = ack_capacity.max(1);
+    let (ack, acks) = mpsc::sync_channel(ack_capacity);
+    let (written, credits) = mpsc::channel();
+    let writer = stream.try_clone()?;
+    let writer = thread::spawn(move || write_acks(writer, acks, written));
+    let connection = Connection {
+        acks: ack,
+        stream: Arc::new(stream.try_clone()?),
+    };
+
+    let headers = StringRecord::from(CSV_HEADERS.to_vec());
+    let mut unacknowledged = 0;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("type,") {
+            continue;
+        }
+
+        // Wait for the sender to read its acknowledgements rather than let
+        // the engine thread find their queue full.
+        unacknowledged -= credits.try_iter().count();
+        if unacknowledged == ack_capacity {
+            if credits.recv().is_err() {
+                break;
+            }
+            unacknowledged -= 1;
+        }
+
+        let job = match parse_line(line, &headers) {
+            Ok(tx) => {
+                // Counted before sending, so that connections blocked on a
+                // full queue show up in the depth. Rejected lines are not
+                // transactions of any shard.
+                depth.of(&tx.client).fetch_add(1, Ordering::Relaxed);
+                Job::Apply(tx)
+            }
+            Err(e) => Job::Reject(e.to_string()),
+        };
+        if jobs.send((job, connection.clone())).is_err() {
+            return Err(anyhow!("Engine stopped"));
+        }
+        unacknowledged += 1;
+    }
+
+    drop(connection);
+    writer.join().map_err(|_| anyhow!("Ack writer panicked"))?
+}
+
+// Gives a credit back to the reader of the connection for every
+// acknowledgement taken off its queue.
+fn write_acks(stream: TcpStream, acks: Receiver<Value>, written: Sender<()>) -> Result<()> {
+    let mut writer = BufWriter::new(stream);
+    while let Ok(ack) = acks.recv() {
+        writeln!(writer, "{}", ack)?;
+        let _ = written.send(());
+        // Batch whatever is already available before flushing.
+        for ack in acks.try_iter() {
+            writeln!(writer, "{}", ack)?;
+            let _ = written.send(());
+        }
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+fn parse_line(line: &str, headers: &StringRecord) -> Result<InputTransaction> {
+    if line.starts_with('{') {
+        return Ok(serde_json::from_str(line)?);
+    }
+
+    let mut reader = ReaderBuilder::new()
+        .has_headers(false)
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(line.as_bytes());
+    let record = reader
+        .records()
+        .next()
+        .ok_or_else(|| anyhow!("Empty line"))??;
+    Ok(record.deserialize(Some(headers))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Read, time::Duration};
+
+    fn connection(capacity: usize) -> (Connection, Receiver<Value>, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let (acks, received) = mpsc::sync_channel(capacity);
+        let stream = Arc::new(stream);
+        (Connection { acks, stream }, received, client)
+    }
+
+    fn deposit(client: u16, tx: u32) -> Job {
+        Job::Apply(InputTransaction {
+            transaction_type: "deposit".to_string(),
+            client: client.into(),
+            tx: tx.into(),
+            amount: Some(1.into()),
+            timestamp: None,
+            tenant: None,
+        })
+    }
+
+    #[test]
+    fn test_full_ack_queue_closes_its_connection_only() {
+        let depth = Arc::new(QueueDepths::new(1));
+        let engine = Arc::new(Mutex::new(ToyEngine::new()));
+        let (jobs, queue) = mpsc::sync_channel(4);
+
+        // Its acknowledgements are never read.
+        let (slow, _unread, mut slow_client) = connection(1);
+        slow.acks.send(json!({})).unwrap();
+        let (fast, acks, _fast_client) = connection(1);
+
+        jobs.send((deposit(1, 1), slow)).unwrap();
+        jobs.send((deposit(2, 2), fast)).unwrap();
+        drop(jobs);
+        thread::spawn(move || run_engine(engine, queue, depth));
+
+        let ack = acks.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(ack, json!({ "tx": 2, "outcome": "applied" }));
+        assert_eq!(slow_client.read(&mut [0; 1]).unwrap(), 0);
+    }
+}
@@ -1,6 +1,51 @@
+// The stable API for embedding ToyPay is the re-exports below, checked for
+// semver breakage. The modules stay public for the binaries, but their layout
+// may change: they are hidden from the documentation, and so from the check.
+#[doc(hidden)]
 pub mod cli;
+#[doc(hidden)]
 pub mod engine;
+#[doc(hidden)]
+pub mod ingest;
+#[doc(hidden)]
+pub mod logging;
+#[doc(hidden)]
 pub mod models;
-pub mod num_cpus;
+#[doc(hidden)]
+pub mod output;
+#[doc(hidden)]
+pub mod replay;
+#[doc(hidden)]
+pub mod report;
+#[doc(hidden)]
+pub mod server;
 
-pub use engine::ToyEngine;
+pub use engine::{
+    archive::{ArchiveBackend, ArchivedTransaction, TransactionArchive},
+    clock::{Clock, SystemClock},
+    config::{EngineConfig, ErrorMode, StorageConfig},
+    fees::{Fee, FeeRule, FeeSchedule, FeeTier, DEFAULT_HOUSE_ACCOUNT},
+    ledger::{as_of, LedgerBackend, LedgerEvent, LedgerFile, LedgerRecord, PointInTime},
+    metrics::{Histogram, MetricsSnapshot, ShardMetrics},
+    observer::{EngineEvent, EngineObserver, JsonLinesObserver, LockReason},
+    outbox::{DomainEvent, Outbox, OutboxConsumer, OutboxEntry},
+    policy::{AccountPolicy, DisputePolicy, LockedAccountPolicy},
+    projections::{project, Balances, OpenDispute, OpenDisputes, Projection},
+    registry::ClientRegistry,
+    risk::{
+        ChargebackCount, DisputeBurst, DisputeRatio, RiskAction, RiskConfig, RiskFlag, RiskSignal,
+    },
+    rules::{ClientLimits, DepositVelocity, Limits, LimitsConfig, RollingWithdrawals, TierLimits},
+    AsyncEngine, DispatchHook, EngineBuilder, IgnoreReason, Outcome, ToyEngine,
+    TransactionConflict,
+};
+pub use models::{
+    client::{ClientInfo, ClientStatus},
+    ids::{ClientId, TxId},
+    input_transaction::{InputTransaction, Transaction},
+    output_record::{DetailedOutputRecord, OutputRecord},
+    timestamp::Timestamp,
+    transaction::DisputeState,
+    transaction_kind::TransactionKind,
+    views::{AccountView, TransactionView},
+};
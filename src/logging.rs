@@ -0,0 +1,30 @@
+use anyhow::{anyhow, Context, Result};
+use std::{fs::File, io, sync::Mutex};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::fmt::format::FmtSpan;
+
+pub const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::WARN;
+
+// Installs the global subscriber: one JSON object per line, written to `file`
+// or stderr, so stdout stays free for the output.
+//
+// Every transaction runs in an info `dispatch` span, and every handler in a
+// debug span named after it. Spans are logged when they close, with their
+// fields and their busy time.
+pub fn init(level: LevelFilter, file: Option<&str>) -> Result<()> {
+    let builder = tracing_subscriber::fmt()
+        .json()
+        .with_max_level(level)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_ansi(false);
+
+    let installed = match file {
+        Some(path) => {
+            let file =
+                File::create(path).with_context(|| format!("Cannot create log file {}", path))?;
+            builder.with_writer(Mutex::new(file)).try_init()
+        }
+        None => builder.with_writer(io::stderr).try_init(),
+    };
+    installed.map_err(|e| anyhow!("Cannot install logger: {}", e))
+}
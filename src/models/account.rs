@@ -1,12 +1,21 @@
+use crate::engine::utils::AmountScale;
 use rust_decimal::Decimal;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Account {
-    pub(crate) available: u32,
-    pub(crate) held: u32,
+    // Wider than the transaction amounts, so that balances summing many of
+    // them are not capped.
+    pub(crate) available: u64,
+    pub(crate) held: u64,
     pub(crate) locked: bool,
 }
 
+impl Default for Account {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Account {
     pub fn new() -> Self {
         Account {
@@ -16,15 +25,15 @@ impl Account {
         }
     }
 
-    pub(crate) fn available_as_decimal(&self) -> Decimal {
-        Decimal::from(self.available) / Decimal::from(100)
+    pub(crate) fn available_as_decimal(&self, scale: AmountScale) -> Decimal {
+        scale.to_decimal(self.available)
     }
 
-    pub(crate) fn held_as_decimal(&self) -> Decimal {
-        Decimal::from(self.held) / Decimal::from(100)
+    pub(crate) fn held_as_decimal(&self, scale: AmountScale) -> Decimal {
+        scale.to_decimal(self.held)
     }
 
-    pub(crate) fn total_as_decimal(&self) -> Decimal {
-        Decimal::from(self.available + self.held) / Decimal::from(100)
+    pub(crate) fn total_as_decimal(&self, scale: AmountScale) -> Decimal {
+        scale.to_decimal(self.available.saturating_add(self.held))
     }
 }
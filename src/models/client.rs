@@ -0,0 +1,50 @@
+use crate::models::ids::ClientId;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Only active clients can transact.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientStatus {
+    #[default]
+    Active,
+    Suspended,
+    Closed,
+}
+
+/// A row of the client registry. Only `client` is mandatory.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ClientInfo {
+    pub client: ClientId,
+    pub name: Option<String>,
+    pub kyc_tier: Option<String>,
+    pub country: Option<String>,
+    pub opened: Option<NaiveDate>,
+    #[serde(default)]
+    pub status: ClientStatus,
+}
+
+impl ClientInfo {
+    pub fn new(client: ClientId) -> Self {
+        Self {
+            client,
+            name: None,
+            kyc_tier: None,
+            country: None,
+            opened: None,
+            status: ClientStatus::Active,
+        }
+    }
+}
+
+impl fmt::Display for ClientStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            ClientStatus::Active => "active",
+            ClientStatus::Suspended => "suspended",
+            ClientStatus::Closed => "closed",
+        };
+        f.write_str(status)
+    }
+}
@@ -0,0 +1,226 @@
+use anyhow::{anyhow, Error, Result};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr, sync::Arc};
+use uuid::Uuid;
+
+/// A client is identified either by an unsigned integer (up to 64 bits) or by
+/// an opaque name. Purely numeric names are read as integers, so `"42"` and
+/// `42` are the same client.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ClientId {
+    Num(u64),
+    Name(Arc<str>),
+}
+
+/// A transaction is identified either by an unsigned 64-bit integer or by a
+/// UUID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TxId {
+    Num(u64),
+    Uuid(Uuid),
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+impl ClientId {
+    /// FNV-1a, which unlike the std hasher does not change between Rust
+    /// releases or processes.
+    pub fn stable_hash(&self) -> u64 {
+        let fnv = |bytes: &[u8]| {
+            bytes.iter().fold(FNV_OFFSET, |hash, &byte| {
+                (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+            })
+        };
+        match self {
+            ClientId::Num(id) => fnv(&id.to_le_bytes()),
+            ClientId::Name(name) => fnv(name.as_bytes()),
+        }
+    }
+}
+
+impl From<u16> for ClientId {
+    fn from(id: u16) -> Self {
+        ClientId::Num(id as u64)
+    }
+}
+
+impl From<u32> for ClientId {
+    fn from(id: u32) -> Self {
+        ClientId::Num(id as u64)
+    }
+}
+
+impl From<u64> for ClientId {
+    fn from(id: u64) -> Self {
+        ClientId::Num(id)
+    }
+}
+
+impl From<u32> for TxId {
+    fn from(id: u32) -> Self {
+        TxId::Num(id as u64)
+    }
+}
+
+impl From<u64> for TxId {
+    fn from(id: u64) -> Self {
+        TxId::Num(id)
+    }
+}
+
+impl From<Uuid> for TxId {
+    fn from(id: Uuid) -> Self {
+        TxId::Uuid(id)
+    }
+}
+
+impl FromStr for ClientId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(anyhow!("Empty client id"));
+        }
+        Ok(match s.parse::<u64>() {
+            Ok(id) => ClientId::Num(id),
+            Err(_) => ClientId::Name(s.into()),
+        })
+    }
+}
+
+impl FromStr for TxId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(id) = s.parse::<u64>() {
+            return Ok(TxId::Num(id));
+        }
+        Uuid::parse_str(s)
+            .map(TxId::Uuid)
+            .map_err(|_| anyhow!("Invalid transaction id: {}", s))
+    }
+}
+
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientId::Num(id) => write!(f, "{}", id),
+            ClientId::Name(name) => f.write_str(name),
+        }
+    }
+}
+
+impl fmt::Display for TxId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxId::Num(id) => write!(f, "{}", id),
+            TxId::Uuid(id) => write!(f, "{}", id.hyphenated()),
+        }
+    }
+}
+
+impl Serialize for ClientId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ClientId::Num(id) => serializer.serialize_u64(*id),
+            ClientId::Name(name) => serializer.serialize_str(name),
+        }
+    }
+}
+
+impl Serialize for TxId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            TxId::Num(id) => serializer.serialize_u64(*id),
+            TxId::Uuid(_) => serializer.collect_str(self),
+        }
+    }
+}
+
+// Both ids accept integers as well as strings, so that they can be read from
+// CSV, JSON and TOML alike.
+struct IdVisitor<T>(std::marker::PhantomData<T>);
+
+impl<T> de::Visitor<'_> for IdVisitor<T>
+where
+    T: From<u64> + FromStr<Err = Error>,
+{
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an unsigned integer or a string id")
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<T, E> {
+        Ok(T::from(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<T, E> {
+        u64::try_from(value)
+            .map(T::from)
+            .map_err(|_| E::custom("id cannot be negative"))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<T, E> {
+        value.parse().map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for ClientId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(IdVisitor(std::marker::PhantomData))
+    }
+}
+
+impl<'de> Deserialize<'de> for TxId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(IdVisitor(std::marker::PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_client_ids() {
+        assert_eq!("42".parse::<ClientId>().unwrap(), ClientId::Num(42));
+        assert_eq!(
+            "acme-42".parse::<ClientId>().unwrap(),
+            ClientId::Name("acme-42".into())
+        );
+        assert_eq!(ClientId::from(70_000u32).to_string(), "70000");
+        assert!("".parse::<ClientId>().is_err());
+    }
+
+    #[test]
+    fn test_parse_tx_ids() {
+        let uuid = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+        assert_eq!("42".parse::<TxId>().unwrap(), TxId::Num(42));
+        assert_eq!(uuid.parse::<TxId>().unwrap().to_string(), uuid);
+        assert!("tx-1".parse::<TxId>().is_err());
+    }
+
+    #[test]
+    fn test_deserialize_ids() {
+        let ids: Vec<ClientId> = serde_json::from_str(r#"[1, "2", "acme"]"#).unwrap();
+        assert_eq!(
+            ids,
+            vec![
+                ClientId::Num(1),
+                ClientId::Num(2),
+                ClientId::Name("acme".into())
+            ]
+        );
+        assert!(serde_json::from_str::<ClientId>("-1").is_err());
+        assert!(serde_json::from_str::<TxId>(r#""not-a-uuid""#).is_err());
+    }
+
+    #[test]
+    fn test_stable_hash() {
+        // Pinned values: changing them would move clients across shards.
+        assert_eq!(ClientId::Num(0).stable_hash(), 0xa8c7_f832_281a_39c5);
+        assert_eq!(ClientId::Name("".into()).stable_hash(), FNV_OFFSET);
+    }
+}
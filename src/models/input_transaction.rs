@@ -1,11 +1,112 @@
+use crate::models::{
+    ids::{ClientId, TxId},
+    timestamp::Timestamp,
+    transaction_kind::TransactionKind,
+};
+use anyhow::Result;
 use rust_decimal::Decimal;
 use serde::Deserialize;
 
+/// A transaction as read from a CSV row, its type still a string: see
+/// [`Transaction`] to build one in code.
 #[derive(Debug, Clone, Deserialize)]
 pub struct InputTransaction {
     #[serde(rename = "type")]
     pub transaction_type: String,
-    pub client: u16,
-    pub tx: u32,
+    pub client: ClientId,
+    pub tx: TxId,
     pub amount: Option<Decimal>,
+    #[serde(default)]
+    pub timestamp: Option<Timestamp>,
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+impl InputTransaction {
+    /// Fails on an unknown type.
+    pub fn kind(&self) -> Result<TransactionKind> {
+        self.transaction_type.parse()
+    }
+}
+
+/// A transaction to dispatch, built by kind rather than read from a CSV row
+/// like [`InputTransaction`], which it converts into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    pub kind: TransactionKind,
+    pub client: ClientId,
+    pub tx: TxId,
+    /// Set for deposits and withdrawals only.
+    pub amount: Option<Decimal>,
+    /// Stamped with the engine clock when missing.
+    pub timestamp: Option<Timestamp>,
+    /// The default tenant when missing.
+    pub tenant: Option<String>,
+}
+
+impl Transaction {
+    fn new(kind: TransactionKind, client: ClientId, tx: TxId, amount: Option<Decimal>) -> Self {
+        Self {
+            kind,
+            client,
+            tx,
+            amount,
+            timestamp: None,
+            tenant: None,
+        }
+    }
+
+    pub fn deposit(client: impl Into<ClientId>, tx: impl Into<TxId>, amount: Decimal) -> Self {
+        Self::new(
+            TransactionKind::Deposit,
+            client.into(),
+            tx.into(),
+            Some(amount),
+        )
+    }
+
+    pub fn withdrawal(client: impl Into<ClientId>, tx: impl Into<TxId>, amount: Decimal) -> Self {
+        Self::new(
+            TransactionKind::Withdrawal,
+            client.into(),
+            tx.into(),
+            Some(amount),
+        )
+    }
+
+    /// `tx` is the disputed deposit, as for resolves and chargebacks.
+    pub fn dispute(client: impl Into<ClientId>, tx: impl Into<TxId>) -> Self {
+        Self::new(TransactionKind::Dispute, client.into(), tx.into(), None)
+    }
+
+    pub fn resolve(client: impl Into<ClientId>, tx: impl Into<TxId>) -> Self {
+        Self::new(TransactionKind::Resolve, client.into(), tx.into(), None)
+    }
+
+    pub fn chargeback(client: impl Into<ClientId>, tx: impl Into<TxId>) -> Self {
+        Self::new(TransactionKind::Chargeback, client.into(), tx.into(), None)
+    }
+
+    pub fn with_timestamp(mut self, timestamp: Timestamp) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn with_tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.tenant = Some(tenant.into());
+        self
+    }
+}
+
+impl From<Transaction> for InputTransaction {
+    fn from(tx: Transaction) -> Self {
+        Self {
+            transaction_type: tx.kind.as_str().to_string(),
+            client: tx.client,
+            tx: tx.tx,
+            amount: tx.amount,
+            timestamp: tx.timestamp,
+            tenant: tx.tenant,
+        }
+    }
 }
@@ -1,4 +1,9 @@
 pub mod account;
+pub mod client;
+pub mod ids;
 pub mod input_transaction;
 pub mod output_record;
+pub mod timestamp;
 pub mod transaction;
+pub mod transaction_kind;
+pub mod views;
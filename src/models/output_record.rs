@@ -1,11 +1,101 @@
+use crate::{
+    engine::utils::AmountScale,
+    models::{
+        account::Account,
+        client::{ClientInfo, ClientStatus},
+        ids::ClientId,
+    },
+};
+use chrono::NaiveDate;
 use rust_decimal::Decimal;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize)]
+/// A row of the balances output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct OutputRecord {
-    pub client: u16,
+    pub client: ClientId,
     pub available: Decimal,
     pub held: Decimal,
     pub total: Decimal,
     pub locked: bool,
 }
+
+impl OutputRecord {
+    pub(crate) fn new(client: ClientId, account: &Account, scale: AmountScale) -> Self {
+        Self {
+            client,
+            available: account.available_as_decimal(scale),
+            held: account.held_as_decimal(scale),
+            total: account.total_as_decimal(scale),
+            locked: account.locked,
+        }
+    }
+}
+
+/// The csv crate cannot serialize flattened structs, hence the repeated
+/// account columns.
+#[derive(Debug, Serialize)]
+pub struct DetailedOutputRecord {
+    pub client: ClientId,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    pub name: Option<String>,
+    pub kyc_tier: Option<String>,
+    pub country: Option<String>,
+    pub opened: Option<NaiveDate>,
+    pub status: Option<ClientStatus>,
+}
+
+impl DetailedOutputRecord {
+    pub(crate) fn new(record: OutputRecord, info: Option<&ClientInfo>) -> Self {
+        let (name, kyc_tier, country, opened, status) = match info.cloned() {
+            Some(info) => (
+                info.name,
+                info.kyc_tier,
+                info.country,
+                info.opened,
+                Some(info.status),
+            ),
+            None => (None, None, None, None, None),
+        };
+        Self {
+            client: record.client,
+            available: record.available,
+            held: record.held,
+            total: record.total,
+            locked: record.locked,
+            name,
+            kyc_tier,
+            country,
+            opened,
+            status,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TenantOutputRecord {
+    pub tenant: String,
+    pub currency: Option<String>,
+    pub client: ClientId,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl TenantOutputRecord {
+    pub(crate) fn new(tenant: String, currency: Option<String>, record: OutputRecord) -> Self {
+        Self {
+            tenant,
+            currency,
+            client: record.client,
+            available: record.available,
+            held: record.held,
+            total: record.total,
+            locked: record.locked,
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use anyhow::{anyhow, Error, Result};
+use chrono::{DateTime, SecondsFormat};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+/// Milliseconds since the Unix epoch. Parsed from either an RFC 3339 date or
+/// an integer number of milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(i64);
+
+impl Timestamp {
+    pub fn from_millis(millis: i64) -> Self {
+        Timestamp(millis)
+    }
+
+    pub fn now() -> Self {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Timestamp(since_epoch.as_millis() as i64)
+    }
+
+    pub fn as_millis(&self) -> i64 {
+        self.0
+    }
+
+    pub fn day(&self) -> i64 {
+        self.0.div_euclid(MILLIS_PER_DAY)
+    }
+
+    pub fn elapsed_since(&self, earlier: Timestamp) -> Duration {
+        Duration::from_millis(self.0.saturating_sub(earlier.0).max(0) as u64)
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(millis) = s.parse::<i64>() {
+            return Ok(Timestamp(millis));
+        }
+        DateTime::parse_from_rfc3339(s)
+            .map(|date| Timestamp(date.timestamp_millis()))
+            .map_err(|_| anyhow!("Invalid timestamp: {}", s))
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match DateTime::from_timestamp_millis(self.0) {
+            Some(date) => f.write_str(&date.to_rfc3339_opts(SecondsFormat::Millis, true)),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TimestampVisitor;
+
+        impl de::Visitor<'_> for TimestampVisitor {
+            type Value = Timestamp;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an RFC 3339 date or epoch milliseconds")
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<Timestamp, E> {
+                Ok(Timestamp(value))
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Timestamp, E> {
+                i64::try_from(value)
+                    .map(Timestamp)
+                    .map_err(|_| E::custom("timestamp out of range"))
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Timestamp, E> {
+                value.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(TimestampVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamps() {
+        let rfc3339: Timestamp = "2024-03-01T12:00:00.250Z".parse().unwrap();
+        let offset: Timestamp = "2024-03-01T13:00:00.250+01:00".parse().unwrap();
+        let millis: Timestamp = "1709294400250".parse().unwrap();
+
+        assert_eq!(rfc3339, millis);
+        assert_eq!(offset, millis);
+        assert_eq!(rfc3339.to_string(), "2024-03-01T12:00:00.250Z");
+        assert!("yesterday".parse::<Timestamp>().is_err());
+    }
+
+    #[test]
+    fn test_day_and_elapsed() {
+        let start: Timestamp = "2024-03-01T23:59:59Z".parse().unwrap();
+        let end: Timestamp = "2024-03-02T00:00:01Z".parse().unwrap();
+
+        assert_eq!(end.day() - start.day(), 1);
+        assert_eq!(end.elapsed_since(start), Duration::from_secs(2));
+        assert_eq!(start.elapsed_since(end), Duration::ZERO);
+    }
+}
@@ -1,6 +1,26 @@
-#[derive(Debug, Clone, Copy)]
+use crate::models::{ids::ClientId, timestamp::Timestamp, transaction_kind::TransactionKind};
+use serde::{Deserialize, Serialize};
+
+/// Where a deposit is in the dispute process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeState {
+    #[default]
+    Undisputed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
-    pub(crate) client: u16,
+    pub(crate) client: ClientId,
+    pub(crate) kind: TransactionKind,
     pub(crate) amount: u32,
-    pub(crate) disputed: bool,
+    pub(crate) state: DisputeState,
+    pub(crate) timestamp: Option<Timestamp>,
+    // When the transaction was last used in the cache, to keep the recency
+    // order across shards.
+    #[serde(skip)]
+    pub(crate) last_used: u64,
 }
@@ -0,0 +1,47 @@
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// The type of a transaction, as written in the input rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionKind {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+}
+
+impl TransactionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionKind::Deposit => "deposit",
+            TransactionKind::Withdrawal => "withdrawal",
+            TransactionKind::Dispute => "dispute",
+            TransactionKind::Resolve => "resolve",
+            TransactionKind::Chargeback => "chargeback",
+        }
+    }
+}
+
+impl FromStr for TransactionKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deposit" => Ok(TransactionKind::Deposit),
+            "withdrawal" => Ok(TransactionKind::Withdrawal),
+            "dispute" => Ok(TransactionKind::Dispute),
+            "resolve" => Ok(TransactionKind::Resolve),
+            "chargeback" => Ok(TransactionKind::Chargeback),
+            _ => Err(anyhow!("Unexpected transaction type: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for TransactionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
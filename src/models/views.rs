@@ -0,0 +1,41 @@
+use crate::{
+    engine::utils::AmountScale,
+    models::{
+        ids::{ClientId, TxId},
+        output_record::OutputRecord,
+        timestamp::Timestamp,
+        transaction::{DisputeState, Transaction},
+        transaction_kind::TransactionKind,
+    },
+};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+/// The balances of an account, as returned by the engine queries. Same
+/// shape as a row of the balances output.
+pub type AccountView = OutputRecord;
+
+/// A deposit or withdrawal kept by the engine.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TransactionView {
+    pub tx: TxId,
+    pub client: ClientId,
+    pub kind: TransactionKind,
+    pub amount: Decimal,
+    pub state: DisputeState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<Timestamp>,
+}
+
+impl TransactionView {
+    pub(crate) fn new(tx_id: TxId, tx: &Transaction, scale: AmountScale) -> Self {
+        Self {
+            tx: tx_id,
+            client: tx.client.clone(),
+            kind: tx.kind,
+            amount: scale.to_decimal(tx.amount as u64),
+            state: tx.state,
+            timestamp: tx.timestamp,
+        }
+    }
+}
@@ -0,0 +1,429 @@
+use crate::models::{
+    ids::ClientId,
+    output_record::{DetailedOutputRecord, OutputRecord, TenantOutputRecord},
+};
+use anyhow::{anyhow, Error, Result};
+use rust_decimal::Decimal;
+use std::{cmp::Ordering, collections::HashSet, io::Write, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Client,
+    Total,
+    Held,
+}
+
+impl FromStr for SortKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "client" => Ok(SortKey::Client),
+            "total" => Ok(SortKey::Total),
+            "held" => Ok(SortKey::Held),
+            _ => Err(anyhow!("Unexpected sort key: {}", s)),
+        }
+    }
+}
+
+// A selected column, written under `name`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub field: String,
+    pub name: String,
+}
+
+impl FromStr for Column {
+    type Err = Error;
+
+    // `field` or `field=name`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (field, name) = s.split_once('=').unwrap_or((s, s));
+        if field.is_empty() || name.is_empty() {
+            return Err(anyhow!("Invalid column: {}", s));
+        }
+        Ok(Column {
+            field: field.to_string(),
+            name: name.to_string(),
+        })
+    }
+}
+
+// Written in the client column of the footer, which holds no account.
+const FOOTER_LABEL: &str = "total";
+
+#[derive(Debug, Clone, Default)]
+pub struct OutputOptions {
+    // Rows keep the engine order when not set: by client, grouped by tenant
+    // for tenant output.
+    pub sort: Option<SortKey>,
+    pub descending: bool,
+    pub locked_only: bool,
+    pub non_zero: bool,
+    pub clients: Option<HashSet<ClientId>>,
+    // Strictly above.
+    pub min_total: Option<Decimal>,
+    // All the columns of the record, in order, when not set.
+    pub columns: Option<Vec<Column>>,
+    // A last row labelled `total` in the client column, with the sum of the
+    // amounts and the number of locked accounts. One per tenant for tenant
+    // output, as their currencies do not add up.
+    pub footer: bool,
+}
+
+// The output records, whatever their extra columns.
+pub trait AccountRow {
+    const FIELDS: &'static [&'static str];
+
+    fn client(&self) -> &ClientId;
+    fn available(&self) -> Decimal;
+    fn held(&self) -> Decimal;
+    fn total(&self) -> Decimal;
+    fn locked(&self) -> bool;
+    // One value per field of `FIELDS`, empty when missing.
+    fn values(&self) -> Vec<String>;
+    // Rows of different tenants get footers of their own.
+    fn tenant(&self) -> Option<&str> {
+        None
+    }
+}
+
+fn optional(value: &Option<impl ToString>) -> String {
+    value.as_ref().map(ToString::to_string).unwrap_or_default()
+}
+
+impl AccountRow for OutputRecord {
+    const FIELDS: &'static [&'static str] = &["client", "available", "held", "total", "locked"];
+
+    fn client(&self) -> &ClientId {
+        &self.client
+    }
+    fn available(&self) -> Decimal {
+        self.available
+    }
+    fn held(&self) -> Decimal {
+        self.held
+    }
+    fn total(&self) -> Decimal {
+        self.total
+    }
+    fn locked(&self) -> bool {
+        self.locked
+    }
+    fn values(&self) -> Vec<String> {
+        vec![
+            self.client.to_string(),
+            self.available.to_string(),
+            self.held.to_string(),
+            self.total.to_string(),
+            self.locked.to_string(),
+        ]
+    }
+}
+
+impl AccountRow for DetailedOutputRecord {
+    const FIELDS: &'static [&'static str] = &[
+        "client",
+        "available",
+        "held",
+        "total",
+        "locked",
+        "name",
+        "kyc_tier",
+        "country",
+        "opened",
+        "status",
+    ];
+
+    fn client(&self) -> &ClientId {
+        &self.client
+    }
+    fn available(&self) -> Decimal {
+        self.available
+    }
+    fn held(&self) -> Decimal {
+        self.held
+    }
+    fn total(&self) -> Decimal {
+        self.total
+    }
+    fn locked(&self) -> bool {
+        self.locked
+    }
+    fn values(&self) -> Vec<String> {
+        vec![
+            self.client.to_string(),
+            self.available.to_string(),
+            self.held.to_string(),
+            self.total.to_string(),
+            self.locked.to_string(),
+            optional(&self.name),
+            optional(&self.kyc_tier),
+            optional(&self.country),
+            optional(&self.opened),
+            optional(&self.status),
+        ]
+    }
+}
+
+impl AccountRow for TenantOutputRecord {
+    const FIELDS: &'static [&'static str] = &[
+        "tenant",
+        "currency",
+        "client",
+        "available",
+        "held",
+        "total",
+        "locked",
+    ];
+
+    fn client(&self) -> &ClientId {
+        &self.client
+    }
+    fn available(&self) -> Decimal {
+        self.available
+    }
+    fn held(&self) -> Decimal {
+        self.held
+    }
+    fn total(&self) -> Decimal {
+        self.total
+    }
+    fn locked(&self) -> bool {
+        self.locked
+    }
+    fn tenant(&self) -> Option<&str> {
+        Some(&self.tenant)
+    }
+    fn values(&self) -> Vec<String> {
+        vec![
+            self.tenant.clone(),
+            optional(&self.currency),
+            self.client.to_string(),
+            self.available.to_string(),
+            self.held.to_string(),
+            self.total.to_string(),
+            self.locked.to_string(),
+        ]
+    }
+}
+
+impl OutputOptions {
+    fn keeps(&self, row: &impl AccountRow) -> bool {
+        let empty = row.available().is_zero() && row.held().is_zero();
+        (!self.locked_only || row.locked())
+            && (!self.non_zero || !empty)
+            && self
+                .clients
+                .as_ref()
+                .is_none_or(|clients| clients.contains(row.client()))
+            && self.min_total.is_none_or(|min| row.total() > min)
+    }
+
+    // Filters and sorts `rows`. The sort is stable, so rows with the same key
+    // keep the engine order, even when descending.
+    pub fn apply<R: AccountRow>(&self, mut rows: Vec<R>) -> Vec<R> {
+        rows.retain(|row| self.keeps(row));
+        let order = |ordering: Ordering| match self.descending {
+            true => ordering.reverse(),
+            false => ordering,
+        };
+        match self.sort {
+            Some(SortKey::Client) => rows.sort_by(|a, b| order(a.client().cmp(b.client()))),
+            Some(SortKey::Total) => rows.sort_by(|a, b| order(a.total().cmp(&b.total()))),
+            Some(SortKey::Held) => rows.sort_by(|a, b| order(a.held().cmp(&b.held()))),
+            None if self.descending => rows.reverse(),
+            None => {}
+        }
+        rows
+    }
+
+    // Indexes in `R::FIELDS` and header names of the selected columns.
+    fn selection<R: AccountRow>(&self) -> Result<Vec<(usize, &str)>> {
+        match &self.columns {
+            None => Ok(R::FIELDS.iter().copied().enumerate().collect()),
+            Some(columns) => columns
+                .iter()
+                .map(|column| {
+                    R::FIELDS
+                        .iter()
+                        .position(|field| *field == column.field)
+                        .map(|index| (index, column.name.as_str()))
+                        .ok_or_else(|| anyhow!("Unknown column: {}", column.field))
+                })
+                .collect(),
+        }
+    }
+
+    pub fn write<R: AccountRow>(&self, rows: Vec<R>, writer: impl Write) -> Result<()> {
+        let selection = self.selection::<R>()?;
+        let rows = self.apply(rows);
+        let mut writer = csv::Writer::from_writer(writer);
+
+        writer.write_record(selection.iter().map(|(_, name)| name))?;
+        for row in &rows {
+            let values = row.values();
+            writer.write_record(selection.iter().map(|(index, _)| &values[*index]))?;
+        }
+
+        if self.footer {
+            // In the order tenants first appear, a single group without them.
+            let mut groups: Vec<(Option<&str>, Vec<&R>)> = Vec::new();
+            for row in &rows {
+                match groups
+                    .iter_mut()
+                    .find(|(tenant, _)| *tenant == row.tenant())
+                {
+                    Some((_, group)) => group.push(row),
+                    None => groups.push((row.tenant(), vec![row])),
+                }
+            }
+            if groups.is_empty() {
+                groups.push((None, Vec::new()));
+            }
+
+            for (_, group) in groups {
+                let sum = |amount: fn(&R) -> Decimal| {
+                    group.iter().map(|row| amount(row)).sum::<Decimal>()
+                };
+                let locked = group.iter().filter(|row| row.locked()).count();
+                let first = group.first().map(|row| row.values());
+                let footer = selection.iter().map(|(index, _)| match R::FIELDS[*index] {
+                    "client" => FOOTER_LABEL.to_string(),
+                    "available" => sum(R::available).to_string(),
+                    "held" => sum(R::held).to_string(),
+                    "total" => sum(R::total).to_string(),
+                    "locked" => format!("{} locked", locked),
+                    "tenant" | "currency" => first
+                        .as_ref()
+                        .map(|values| values[*index].clone())
+                        .unwrap_or_default(),
+                    _ => String::new(),
+                });
+                writer.write_record(footer)?;
+            }
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(client: u64, available: i64, held: i64, locked: bool) -> OutputRecord {
+        OutputRecord {
+            client: ClientId::Num(client),
+            available: Decimal::from(available),
+            held: Decimal::from(held),
+            total: Decimal::from(available + held),
+            locked,
+        }
+    }
+
+    fn rows() -> Vec<OutputRecord> {
+        vec![
+            record(1, 10, 0, false),
+            record(2, 0, 0, true),
+            record(3, 5, 20, false),
+            record(4, 30, 0, true),
+        ]
+    }
+
+    fn write(options: &OutputOptions) -> String {
+        let mut out = Vec::new();
+        options.write(rows(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_sort_and_filter() {
+        let clients = |options: &OutputOptions| -> Vec<ClientId> {
+            options
+                .apply(rows())
+                .into_iter()
+                .map(|row| row.client)
+                .collect()
+        };
+
+        let mut options = OutputOptions {
+            sort: Some(SortKey::Total),
+            descending: true,
+            ..OutputOptions::default()
+        };
+        assert_eq!(clients(&options), [4, 3, 1, 2].map(ClientId::Num));
+
+        options.non_zero = true;
+        options.min_total = Some(Decimal::from(10));
+        assert_eq!(clients(&options), [4, 3].map(ClientId::Num));
+
+        let options = OutputOptions {
+            locked_only: true,
+            clients: Some([2, 3].map(ClientId::Num).into()),
+            ..OutputOptions::default()
+        };
+        assert_eq!(clients(&options), [ClientId::Num(2)]);
+    }
+
+    #[test]
+    fn test_columns_and_footer() {
+        let options = OutputOptions {
+            sort: Some(SortKey::Held),
+            descending: true,
+            columns: Some(vec!["client=id".parse().unwrap(), "total".parse().unwrap()]),
+            footer: true,
+            ..OutputOptions::default()
+        };
+        assert_eq!(
+            write(&options),
+            "id,total\n3,25\n1,10\n2,0\n4,30\ntotal,65\n"
+        );
+
+        let options = OutputOptions {
+            footer: true,
+            locked_only: true,
+            ..OutputOptions::default()
+        };
+        assert_eq!(
+            write(&options),
+            "client,available,held,total,locked\n2,0,0,0,true\n4,30,0,30,true\ntotal,30,0,30,2 locked\n"
+        );
+
+        let options = OutputOptions {
+            columns: Some(vec!["name".parse().unwrap()]),
+            ..OutputOptions::default()
+        };
+        assert!(options.write(rows(), Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_footer_per_tenant() {
+        let tenant = |tenant: &str, currency: &str, record: OutputRecord| {
+            TenantOutputRecord::new(tenant.to_string(), Some(currency.to_string()), record)
+        };
+        let rows = vec![
+            tenant("acme", "EUR", record(1, 10, 0, false)),
+            tenant("acme", "EUR", record(2, 5, 5, true)),
+            tenant("globex", "USD", record(1, 100, 0, false)),
+        ];
+        let options = OutputOptions {
+            sort: Some(SortKey::Total),
+            footer: true,
+            ..OutputOptions::default()
+        };
+
+        let mut out = Vec::new();
+        options.write(rows, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "tenant,currency,client,available,held,total,locked\n\
+             acme,EUR,1,10,0,10,false\n\
+             acme,EUR,2,5,5,10,true\n\
+             globex,USD,1,100,0,100,false\n\
+             acme,EUR,total,15,5,20,1 locked\n\
+             globex,USD,total,100,0,100,0 locked\n"
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use crate::{
+    engine::config::ErrorMode,
+    models::{
+        ids::{ClientId, TxId},
+        input_transaction::InputTransaction,
+        output_record::OutputRecord,
+    },
+    ToyEngine,
+};
+use anyhow::{Context, Result};
+use csv::ReaderBuilder;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Read,
+    path::Path,
+};
+use tracing::warn;
+
+// A client whose final account differs between the expected and the actual
+// run. The columns of a side are empty when the client has no account there.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct ClientDiff {
+    pub client: ClientId,
+    pub expected_available: Option<Decimal>,
+    pub actual_available: Option<Decimal>,
+    pub expected_held: Option<Decimal>,
+    pub actual_held: Option<Decimal>,
+    pub expected_total: Option<Decimal>,
+    pub actual_total: Option<Decimal>,
+    pub expected_locked: Option<bool>,
+    pub actual_locked: Option<bool>,
+    // Only known when both sides are replayed: the first transaction of the
+    // client after which its accounts differed.
+    pub first_divergence: Option<TxId>,
+}
+
+impl ClientDiff {
+    fn new(
+        client: ClientId,
+        expected: Option<&OutputRecord>,
+        actual: Option<&OutputRecord>,
+        first_divergence: Option<TxId>,
+    ) -> Self {
+        Self {
+            client,
+            expected_available: expected.map(|r| r.available),
+            actual_available: actual.map(|r| r.available),
+            expected_held: expected.map(|r| r.held),
+            actual_held: actual.map(|r| r.held),
+            expected_total: expected.map(|r| r.total),
+            actual_total: actual.map(|r| r.total),
+            expected_locked: expected.map(|r| r.locked),
+            actual_locked: actual.map(|r| r.locked),
+            first_divergence,
+        }
+    }
+}
+
+// Reads CSV transactions like the main binary does: malformed rows are
+// logged and counted, unless aborting, which fails on the first of them.
+pub fn read_transactions(
+    input: impl Read,
+    errors: ErrorMode,
+) -> Result<(Vec<InputTransaction>, usize)> {
+    let mut reader = ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(input);
+    let mut transactions = Vec::new();
+    let mut malformed = 0;
+    for row in reader.deserialize::<InputTransaction>() {
+        match row {
+            Ok(transaction) => transactions.push(transaction),
+            Err(e) if errors == ErrorMode::Abort => return Err(e).context("Malformed row"),
+            Err(e) => {
+                warn!(error = %e, "malformed row");
+                malformed += 1;
+            }
+        }
+    }
+    Ok((transactions, malformed))
+}
+
+// Rejected transactions are only logged by the engine, unless aborting.
+pub fn dispatch(engine: &mut ToyEngine, tx: InputTransaction, errors: ErrorMode) -> Result<()> {
+    let tx_id = tx.tx;
+    match engine.dispatch(tx) {
+        Err(e) if errors == ErrorMode::Abort => {
+            Err(e.context(format!("Transaction {} rejected", tx_id)))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Runs the same transactions through both engines, in lockstep, and diffs
+// the resulting accounts.
+//
+// Divergence is tracked on the account of the transaction's client: an
+// account only touched as a side effect, like the house account collecting
+// fees, shows up in the diff without a first divergence.
+pub fn compare_engines(
+    transactions: impl IntoIterator<Item = InputTransaction>,
+    expected: &mut ToyEngine,
+    actual: &mut ToyEngine,
+    errors: ErrorMode,
+) -> Result<Vec<ClientDiff>> {
+    let mut diverged = HashMap::new();
+    for tx in transactions {
+        let client = tx.client.clone();
+        let tx_id = tx.tx;
+        dispatch(expected, tx.clone(), errors)?;
+        dispatch(actual, tx, errors)?;
+        if !diverged.contains_key(&client) && expected.account(&client) != actual.account(&client) {
+            diverged.insert(client, tx_id);
+        }
+    }
+    Ok(diff(
+        expected.get_all_accounts(),
+        actual.get_all_accounts(),
+        &diverged,
+    ))
+}
+
+// Diffs the accounts of an engine against a snapshot of expected output.
+pub fn compare_snapshot(expected: Vec<OutputRecord>, actual: &ToyEngine) -> Vec<ClientDiff> {
+    diff(expected, actual.get_all_accounts(), &HashMap::new())
+}
+
+// Reads an output file as written by the main binary.
+pub fn read_snapshot(path: impl AsRef<Path>) -> Result<Vec<OutputRecord>> {
+    let path = path.as_ref();
+    let mut reader = ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)
+        .with_context(|| format!("Cannot read snapshot {}", path.display()))?;
+    reader
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("Invalid snapshot {}", path.display()))
+}
+
+fn diff(
+    expected: Vec<OutputRecord>,
+    actual: Vec<OutputRecord>,
+    diverged: &HashMap<ClientId, TxId>,
+) -> Vec<ClientDiff> {
+    let mut clients: BTreeMap<ClientId, (Option<OutputRecord>, Option<OutputRecord>)> =
+        BTreeMap::new();
+    for record in expected {
+        let client = record.client.clone();
+        clients.entry(client).or_default().0 = Some(record);
+    }
+    for record in actual {
+        let client = record.client.clone();
+        clients.entry(client).or_default().1 = Some(record);
+    }
+
+    clients
+        .into_iter()
+        .filter(|(_, (expected, actual))| expected != actual)
+        .map(|(client, (expected, actual))| {
+            let first_divergence = diverged.get(&client).copied();
+            ClientDiff::new(client, expected.as_ref(), actual.as_ref(), first_divergence)
+        })
+        .collect()
+}
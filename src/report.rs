@@ -0,0 +1,446 @@
+use crate::{
+    engine::{
+        ledger::LedgerEvent,
+        projections::{OpenDispute, OpenDisputes, Projection},
+        Outcome, TransactionConflict,
+    },
+    models::{
+        ids::{ClientId, TxId},
+        input_transaction::InputTransaction,
+        timestamp::Timestamp,
+        transaction_kind::TransactionKind,
+    },
+    ToyEngine,
+};
+use anyhow::{anyhow, Error, Result};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    str::FromStr,
+};
+
+pub const DEFAULT_TOP_CLIENTS: usize = 10;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReportFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for ReportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(ReportFormat::Text),
+            "json" => Ok(ReportFormat::Json),
+            _ => Err(anyhow!("Unexpected report format: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AgedDispute {
+    #[serde(flatten)]
+    pub dispute: OpenDispute,
+    pub opened: Option<Timestamp>,
+    // Whole days between the dispute and the latest timestamp of the input,
+    // unknown without timestamps.
+    pub age_days: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ClientVolume {
+    pub client: ClientId,
+    pub volume: Decimal,
+}
+
+// System-wide aggregates of a run. Amounts are the ones applied, truncated to
+// the amount scale, withdrawals and chargebacks without their fees.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Report {
+    pub deposits: Decimal,
+    pub withdrawals: Decimal,
+    pub fees: Decimal,
+    pub held: Decimal,
+    pub charged_back: Decimal,
+    pub locked_accounts: usize,
+    pub open_disputes: Vec<AgedDispute>,
+    // Deposited plus withdrawn amounts, highest first.
+    pub top_clients: Vec<ClientVolume>,
+    // Rows that were not applied, by reason. Replayed duplicates are not
+    // counted, they are already applied.
+    pub rejections: BTreeMap<String, u64>,
+}
+
+// Follows the transactions dispatched to an engine, the rest of the report
+// being read from the engine state when it is built.
+#[derive(Debug, Default)]
+pub struct ReportBuilder {
+    deposits: Decimal,
+    withdrawals: Decimal,
+    fees: Decimal,
+    charged_back: Decimal,
+    volumes: HashMap<ClientId, Decimal>,
+    disputes: OpenDisputes,
+    opened: HashMap<TxId, Option<Timestamp>>,
+    rejections: BTreeMap<String, u64>,
+    latest: Option<Timestamp>,
+}
+
+impl ReportBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Must be called right after `tx` is dispatched to `engine`.
+    pub fn record(&mut self, engine: &ToyEngine, tx: &InputTransaction, result: &Result<Outcome>) {
+        if tx.timestamp > self.latest {
+            self.latest = tx.timestamp;
+        }
+        let outcome = match result {
+            Ok(outcome) => outcome,
+            Err(e) => return self.reject(rejection(tx, e)),
+        };
+        match outcome {
+            Outcome::Applied => {}
+            Outcome::Duplicate => return,
+            Outcome::Ignored(reason) => return self.reject(reason.to_string()),
+        }
+
+        for event in engine.last_events() {
+            self.disputes.apply(event);
+            match event {
+                LedgerEvent::Deposited { client, amount, .. } => {
+                    self.deposits += amount;
+                    *self.volumes.entry(client.clone()).or_default() += amount;
+                }
+                LedgerEvent::Withdrawn { client, amount, .. } => {
+                    self.withdrawals += amount;
+                    *self.volumes.entry(client.clone()).or_default() += amount;
+                }
+                LedgerEvent::FeeCharged { amount, .. } => self.fees += amount,
+                LedgerEvent::DisputeOpened { tx: disputed, .. } => {
+                    self.opened.insert(*disputed, tx.timestamp);
+                }
+                LedgerEvent::ChargedBack { amount, .. } => self.charged_back += amount,
+                _ => {}
+            }
+        }
+    }
+
+    // A row that could not be read.
+    pub fn malformed(&mut self) {
+        self.reject("malformed row".to_string());
+    }
+
+    fn reject(&mut self, reason: String) {
+        *self.rejections.entry(reason).or_default() += 1;
+    }
+
+    pub fn build(self, engine: &ToyEngine, top: usize) -> Report {
+        let accounts = engine.get_all_accounts();
+
+        let open_disputes = self
+            .disputes
+            .disputes()
+            .into_iter()
+            .map(|dispute| {
+                let opened = self.opened.get(&dispute.tx).copied().flatten();
+                AgedDispute {
+                    age_days: opened
+                        .zip(self.latest)
+                        .map(|(opened, latest)| latest.elapsed_since(opened).as_secs() / 86_400),
+                    dispute,
+                    opened,
+                }
+            })
+            .collect();
+
+        let mut top_clients: Vec<ClientVolume> = self
+            .volumes
+            .into_iter()
+            .map(|(client, volume)| ClientVolume {
+                client,
+                volume: volume.normalize(),
+            })
+            .collect();
+        top_clients.sort_by(|a, b| {
+            b.volume
+                .cmp(&a.volume)
+                .then_with(|| a.client.cmp(&b.client))
+        });
+        top_clients.truncate(top);
+
+        Report {
+            deposits: self.deposits.normalize(),
+            withdrawals: self.withdrawals.normalize(),
+            fees: self.fees.normalize(),
+            held: accounts
+                .iter()
+                .map(|record| record.held)
+                .sum::<Decimal>()
+                .normalize(),
+            charged_back: self.charged_back.normalize(),
+            locked_accounts: accounts.iter().filter(|record| record.locked).count(),
+            open_disputes,
+            top_clients,
+            rejections: self.rejections,
+        }
+    }
+}
+
+// Errors naming the row are grouped under a fixed reason, the others under
+// their root cause, whatever context the engine gave them.
+fn rejection(tx: &InputTransaction, e: &Error) -> String {
+    if tx.transaction_type.parse::<TransactionKind>().is_err() {
+        "unknown transaction type".to_string()
+    } else if e.is::<TransactionConflict>() {
+        "conflicting transaction id".to_string()
+    } else {
+        e.root_cause().to_string()
+    }
+}
+
+impl Report {
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "deposits: {}", self.deposits);
+        let _ = writeln!(out, "withdrawals: {}", self.withdrawals);
+        let _ = writeln!(out, "fees: {}", self.fees);
+        let _ = writeln!(out, "held: {}", self.held);
+        let _ = writeln!(out, "charged back: {}", self.charged_back);
+        let _ = writeln!(out, "locked accounts: {}", self.locked_accounts);
+
+        let _ = writeln!(out, "open disputes: {}", self.open_disputes.len());
+        for dispute in &self.open_disputes {
+            let age = match dispute.age_days {
+                Some(days) => format!("{} day(s)", days),
+                None => "unknown age".to_string(),
+            };
+            let _ = writeln!(
+                out,
+                "  tx {} client {}: {} ({})",
+                dispute.dispute.tx, dispute.dispute.client, dispute.dispute.amount, age
+            );
+        }
+
+        let _ = writeln!(out, "top clients by volume:");
+        for (rank, client) in self.top_clients.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "  {}. client {}: {}",
+                rank + 1,
+                client.client,
+                client.volume
+            );
+        }
+
+        let rejected: u64 = self.rejections.values().sum();
+        let _ = writeln!(out, "rejected rows: {}", rejected);
+        for (reason, count) in &self.rejections {
+            let _ = writeln!(out, "  {}: {}", reason, count);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ledger::{LedgerBackend, LedgerRecord};
+
+    fn transaction(
+        kind: &str,
+        client: u64,
+        tx: u64,
+        amount: Option<&str>,
+        at: &str,
+    ) -> InputTransaction {
+        InputTransaction {
+            transaction_type: kind.to_string(),
+            client: ClientId::Num(client),
+            tx: TxId::Num(tx),
+            amount: amount.map(|amount| amount.parse().unwrap()),
+            timestamp: Some(at.parse().unwrap()),
+            tenant: None,
+        }
+    }
+
+    fn report(transactions: Vec<InputTransaction>) -> Report {
+        report_of(ToyEngine::new(), transactions)
+    }
+
+    fn report_of(mut engine: ToyEngine, transactions: Vec<InputTransaction>) -> Report {
+        let mut builder = ReportBuilder::new();
+        for tx in transactions {
+            let result = engine.dispatch(tx.clone());
+            builder.record(&engine, &tx, &result);
+        }
+        builder.build(&engine, 2)
+    }
+
+    #[test]
+    fn test_aggregates() {
+        let report = report(vec![
+            transaction("deposit", 1, 1, Some("10.00"), "2024-01-01T00:00:00Z"),
+            transaction("deposit", 2, 2, Some("5.5"), "2024-01-01T00:00:00Z"),
+            transaction("deposit", 3, 3, Some("1"), "2024-01-01T00:00:00Z"),
+            transaction("withdrawal", 2, 4, Some("2"), "2024-01-02T00:00:00Z"),
+            transaction("withdrawal", 3, 5, Some("9"), "2024-01-02T00:00:00Z"),
+            transaction("dispute", 1, 1, None, "2024-01-03T00:00:00Z"),
+            transaction("dispute", 3, 3, None, "2024-01-03T00:00:00Z"),
+            transaction("chargeback", 3, 3, None, "2024-01-04T00:00:00Z"),
+            transaction("refund", 1, 6, None, "2024-01-05T12:00:00Z"),
+        ]);
+
+        assert_eq!(report.deposits, Decimal::from_str("16.5").unwrap());
+        assert_eq!(report.withdrawals, Decimal::from(2));
+        assert_eq!(report.held, Decimal::from(10));
+        assert_eq!(report.charged_back, Decimal::from(1));
+        assert_eq!(report.locked_accounts, 1);
+        assert_eq!(report.open_disputes.len(), 1);
+        assert_eq!(report.open_disputes[0].dispute.tx, TxId::Num(1));
+        assert_eq!(report.open_disputes[0].age_days, Some(2));
+        let top: Vec<_> = report
+            .top_clients
+            .iter()
+            .map(|c| c.client.clone())
+            .collect();
+        assert_eq!(top, [1, 2].map(ClientId::Num));
+        assert_eq!(report.rejections["insufficient funds"], 1);
+        assert_eq!(report.rejections["unknown transaction type"], 1);
+    }
+
+    #[test]
+    fn test_applied_amounts() {
+        let fees = toml::from_str(
+            r#"
+            house_account = 0
+
+            [withdrawal]
+            kind = "flat"
+            amount = 1
+
+            [chargeback]
+            kind = "flat"
+            amount = 5
+            "#,
+        )
+        .unwrap();
+        let report = report_of(
+            ToyEngine::new().with_fees(fees),
+            vec![
+                transaction("deposit", 1, 1, Some("10.009"), "2024-01-01T00:00:00Z"),
+                transaction("deposit", 1, 2, Some("20"), "2024-01-01T00:00:00Z"),
+                transaction("withdrawal", 1, 3, Some("4.999"), "2024-01-02T00:00:00Z"),
+                transaction("dispute", 1, 1, None, "2024-01-03T00:00:00Z"),
+                transaction("chargeback", 1, 1, None, "2024-01-04T00:00:00Z"),
+            ],
+        );
+
+        // Truncated to cents, the fees apart.
+        assert_eq!(report.deposits, Decimal::from(30));
+        assert_eq!(report.withdrawals, Decimal::from_str("4.99").unwrap());
+        assert_eq!(report.fees, Decimal::from(6));
+        assert_eq!(report.charged_back, Decimal::from(10));
+        assert_eq!(
+            report.top_clients[0].volume,
+            Decimal::from_str("34.99").unwrap()
+        );
+        assert!(report.to_text().contains("fees: 6\n"));
+    }
+
+    #[test]
+    fn test_fees() {
+        let fees = toml::from_str(
+            r#"
+            house_account = "fees"
+
+            [withdrawal]
+            kind = "percentage"
+            rate = 0.01
+            min = 0.50
+
+            [chargeback]
+            kind = "flat"
+            amount = 20
+            "#,
+        )
+        .unwrap();
+        let report = report_of(
+            ToyEngine::new().with_fees(fees),
+            vec![
+                transaction("deposit", 1, 1, Some("500"), "2024-01-01T00:00:00Z"),
+                transaction("withdrawal", 1, 2, Some("200"), "2024-01-02T00:00:00Z"),
+                transaction("withdrawal", 1, 3, Some("10"), "2024-01-02T00:00:00Z"),
+                transaction("deposit", 2, 4, Some("30"), "2024-01-03T00:00:00Z"),
+                transaction("deposit", 2, 5, Some("10"), "2024-01-03T00:00:00Z"),
+                transaction("dispute", 2, 4, None, "2024-01-04T00:00:00Z"),
+                transaction("chargeback", 2, 4, None, "2024-01-05T00:00:00Z"),
+                // Not applied, so no fee either.
+                transaction("withdrawal", 1, 6, Some("1000"), "2024-01-06T00:00:00Z"),
+            ],
+        );
+
+        // 1% of 200, the 0.50 minimum on 10, and a chargeback fee capped by
+        // the 10 left available.
+        assert_eq!(report.fees, Decimal::from_str("12.5").unwrap());
+        assert_eq!(report.withdrawals, Decimal::from(210));
+        assert_eq!(report.charged_back, Decimal::from(30));
+        assert_eq!(report.rejections["insufficient funds"], 1);
+    }
+
+    struct FullDisk;
+
+    impl LedgerBackend for FullDisk {
+        fn append(&mut self, _record: &LedgerRecord) -> Result<()> {
+            Err(anyhow!("disk full"))
+        }
+    }
+
+    #[test]
+    fn test_rejections_by_reason() {
+        let at = "2024-01-01T00:00:00Z";
+        let report = report(vec![
+            transaction("deposit", 1, 1, Some("10"), at),
+            transaction("deposit", 2, 1, Some("10"), at),
+            transaction("deposit", 3, 1, Some("20"), at),
+            transaction("deposit", 1, 2, None, at),
+            transaction("deposit", 1, 3, None, at),
+            transaction("deposit", 1, 4, Some("-1"), at),
+            transaction("transfer", 1, 5, Some("1"), at),
+            transaction("withdrawal", 4, 6, Some("1"), at),
+        ]);
+        assert_eq!(
+            report.rejections,
+            BTreeMap::from([
+                ("Amount cannot be negative".to_string(), 1),
+                ("Deposit requires amount".to_string(), 2),
+                ("conflicting transaction id".to_string(), 2),
+                ("unknown account".to_string(), 1),
+                ("unknown transaction type".to_string(), 1),
+            ])
+        );
+
+        // The ledger error of each row comes with its record number.
+        let mut builder = ReportBuilder::new();
+        let mut engine = ToyEngine::new().with_ledger(FullDisk);
+        for tx in [
+            transaction("deposit", 1, 1, Some("10"), at),
+            transaction("deposit", 2, 2, Some("10"), at),
+        ] {
+            let result = engine.dispatch(tx.clone());
+            builder.record(&engine, &tx, &result);
+        }
+        builder.malformed();
+        let report = builder.build(&engine, 2);
+        assert_eq!(report.rejections["disk full"], 2);
+        assert_eq!(report.rejections["malformed row"], 1);
+        assert_eq!(report.rejections.len(), 2);
+        assert!(report.to_text().contains("rejected rows: 3\n"));
+    }
+}
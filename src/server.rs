@@ -0,0 +1,129 @@
+use crate::{
+    engine::{Outcome, TransactionConflict},
+    models::{ids::ClientId, input_transaction::InputTransaction},
+    ToyEngine,
+};
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use std::{io::Read, net::TcpListener};
+use tiny_http::{Header, Method, Response, Server};
+
+// A transaction is a small JSON object: larger bodies are refused with 413
+// rather than read in memory.
+pub const MAX_BODY_BYTES: u64 = 16 * 1024;
+
+// Serves the HTTP/JSON API until the listener fails. Requests are handled one
+// at a time, in arrival order, by the same engine. `/metrics` is served in the
+// Prometheus text format instead of JSON.
+pub fn serve(listener: TcpListener, mut engine: ToyEngine) -> Result<()> {
+    let server = Server::from_listener(listener, None).map_err(|e| anyhow!(e))?;
+    let content_type = Header::from_bytes("Content-Type", "application/json")
+        .map_err(|_| anyhow!("Invalid header"))?;
+
+    let text = Header::from_bytes("Content-Type", "text/plain; version=0.0.4")
+        .map_err(|_| anyhow!("Invalid header"))?;
+
+    for mut request in server.incoming_requests() {
+        if *request.method() == Method::Get && request.url() == "/metrics" {
+            let response =
+                Response::from_string(engine.metrics().to_prometheus()).with_header(text.clone());
+            let _ = request.respond(response);
+            continue;
+        }
+
+        let (status, payload) = match read_body(&mut request) {
+            Ok(body) => route(&mut engine, request.method(), request.url(), &body),
+            Err(response) => response,
+        };
+
+        let response = Response::from_string(payload.to_string())
+            .with_status_code(status)
+            .with_header(content_type.clone());
+        let _ = request.respond(response);
+    }
+
+    Ok(())
+}
+
+// Reads one byte past the limit, to tell a body of exactly the limit from a
+// larger one whatever its announced length.
+fn read_body(request: &mut tiny_http::Request) -> Result<String, (u16, Value)> {
+    let too_large = || error(413, format!("body larger than {} bytes", MAX_BODY_BYTES));
+    if request
+        .body_length()
+        .is_some_and(|length| length as u64 > MAX_BODY_BYTES)
+    {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_BODY_BYTES + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| error(400, e))?;
+    if body.len() as u64 > MAX_BODY_BYTES {
+        return Err(too_large());
+    }
+    String::from_utf8(body).map_err(|e| error(400, e))
+}
+
+pub fn route(engine: &mut ToyEngine, method: &Method, url: &str, body: &str) -> (u16, Value) {
+    let path = url.split('?').next().unwrap_or_default();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    match (method, segments.as_slice()) {
+        (Method::Get, ["health"]) => (200, json!({ "status": "ok" })),
+        (Method::Post, ["transactions"]) => submit(engine, body),
+        (Method::Get, ["transactions", tx]) => match tx.parse() {
+            Ok(tx) => found(engine.transaction(tx)),
+            Err(e) => error(400, e),
+        },
+        (Method::Get, ["accounts"]) => (200, json!(engine.get_all_accounts())),
+        (Method::Get, ["accounts", client]) => match client.parse::<ClientId>() {
+            Ok(client) => found(engine.account(&client)),
+            Err(e) => error(400, e),
+        },
+        (Method::Get, ["accounts", client, "disputes"]) => match client.parse::<ClientId>() {
+            Ok(client) => (200, json!(engine.disputed_transactions(&client))),
+            Err(e) => error(400, e),
+        },
+        (Method::Get, ["locked-accounts"]) => (200, json!(engine.locked_accounts())),
+        _ => error(404, "not found"),
+    }
+}
+
+fn submit(engine: &mut ToyEngine, body: &str) -> (u16, Value) {
+    let tx: InputTransaction = match serde_json::from_str(body) {
+        Ok(tx) => tx,
+        Err(e) => return error(400, e),
+    };
+
+    let result = engine.dispatch(tx);
+    let status = match &result {
+        Ok(_) => 200,
+        Err(e) if e.is::<TransactionConflict>() => 409,
+        Err(_) => 422,
+    };
+    (status, outcome_json(&result))
+}
+
+pub(crate) fn outcome_json(result: &Result<Outcome>) -> Value {
+    match result {
+        Ok(Outcome::Ignored(reason)) => {
+            json!({ "outcome": "ignored", "reason": reason.to_string() })
+        }
+        Ok(outcome) => json!({ "outcome": outcome.to_string() }),
+        Err(e) => json!({ "outcome": "rejected", "error": e.to_string() }),
+    }
+}
+
+fn found(record: Option<impl serde::Serialize>) -> (u16, Value) {
+    match record {
+        Some(record) => (200, json!(record)),
+        None => error(404, "not found"),
+    }
+}
+
+fn error(status: u16, e: impl ToString) -> (u16, Value) {
+    (status, json!({ "error": e.to_string() }))
+}
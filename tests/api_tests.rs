@@ -0,0 +1,123 @@
+use anyhow::Result;
+use rust_decimal::Decimal;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+// Only the root re-exports: this file breaking means the stable API did.
+use toypay::{
+    ArchiveBackend, ArchivedTransaction, ClientId, DisputeState, EngineConfig, IgnoreReason,
+    InputTransaction, Outcome, StorageConfig, Timestamp, ToyEngine, Transaction, TransactionKind,
+    TxId,
+};
+
+#[derive(Default)]
+struct MemoryArchive(Arc<Mutex<HashMap<TxId, ArchivedTransaction>>>);
+
+impl ArchiveBackend for MemoryArchive {
+    fn append(&mut self, tx_id: TxId, transaction: ArchivedTransaction) -> Result<()> {
+        self.0.lock().unwrap().insert(tx_id, transaction);
+        Ok(())
+    }
+
+    fn get(&self, tx_id: TxId) -> Result<Option<ArchivedTransaction>> {
+        Ok(self.0.lock().unwrap().get(&tx_id).cloned())
+    }
+}
+
+fn at(date: &str) -> Timestamp {
+    date.parse().unwrap()
+}
+
+#[test]
+fn test_typed_transactions() {
+    let deposit = Transaction::deposit(1u16, 7u32, Decimal::from(10))
+        .with_timestamp(at("2024-01-01T00:00:00Z"))
+        .with_tenant("acme");
+
+    assert_eq!(deposit.kind, TransactionKind::Deposit);
+    assert_eq!(deposit.client, ClientId::Num(1));
+    assert_eq!(deposit.tx, TxId::Num(7));
+    assert_eq!(deposit.amount, Some(Decimal::from(10)));
+    assert_eq!(deposit.tenant.as_deref(), Some("acme"));
+    assert_eq!(
+        Transaction::chargeback(1u16, 7u32).kind,
+        TransactionKind::Chargeback
+    );
+    assert_eq!(Transaction::dispute(1u16, 7u32).amount, None);
+
+    // The CSV rows the engine reads.
+    let row = InputTransaction::from(deposit.clone());
+    assert_eq!(row.transaction_type, "deposit");
+    assert_eq!(row.kind().unwrap(), TransactionKind::Deposit);
+    assert_eq!(row.timestamp, deposit.timestamp);
+    assert_eq!(row.tenant.as_deref(), Some("acme"));
+}
+
+#[test]
+fn test_engine_builder() -> Result<()> {
+    let outcomes = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&outcomes);
+    let now = Arc::new(Mutex::new(at("2024-01-01T00:00:00Z")));
+    let clock = Arc::clone(&now);
+
+    let mut engine = ToyEngine::builder()
+        .with_config(EngineConfig {
+            dispute_window: Some(30),
+            storage: StorageConfig {
+                shards: 1,
+                cache_capacity: 1,
+                ..StorageConfig::default()
+            },
+            ..EngineConfig::default()
+        })
+        .with_archive(MemoryArchive::default())
+        .with_clock(move || *clock.lock().unwrap())
+        .with_hook(move |tx, result| {
+            let outcome = result.as_ref().map_or("rejected", Outcome::label);
+            seen.lock().unwrap().push((tx.tx, outcome));
+        })
+        .build()?;
+
+    engine.dispatch(Transaction::deposit(1u16, 1u32, Decimal::from(10)))?;
+    engine.dispatch(Transaction::deposit(1u16, 2u32, Decimal::from(5)))?;
+    // Re-submitted rows are stamped anew and still seen as duplicates.
+    assert_eq!(
+        engine.dispatch(Transaction::deposit(1u16, 1u32, Decimal::from(10)))?,
+        Outcome::Duplicate
+    );
+
+    // Tx 1 was evicted to the archive, and the clock stamped it: the window
+    // applies.
+    *now.lock().unwrap() = at("2024-03-01T00:00:00Z");
+    assert_eq!(
+        engine.dispatch(Transaction::dispute(1u16, 1u32))?,
+        Outcome::Ignored(IgnoreReason::DisputeWindowExpired)
+    );
+    *now.lock().unwrap() = at("2024-01-15T00:00:00Z");
+    assert_eq!(
+        engine.dispatch(Transaction::dispute(1u16, 1u32))?,
+        Outcome::Applied
+    );
+    let view = engine.transaction(TxId::Num(1)).unwrap();
+    assert_eq!(view.timestamp, Some(at("2024-01-01T00:00:00Z")));
+    assert_eq!(view.state, DisputeState::Disputed);
+
+    assert_eq!(
+        *outcomes.lock().unwrap(),
+        [
+            (TxId::Num(1), "applied"),
+            (TxId::Num(2), "applied"),
+            (TxId::Num(1), "duplicate"),
+            (TxId::Num(1), "ignored"),
+            (TxId::Num(1), "applied"),
+        ]
+    );
+
+    let invalid = EngineConfig {
+        amount_scale: 5,
+        ..EngineConfig::default()
+    };
+    assert!(ToyEngine::builder().with_config(invalid).build().is_err());
+    Ok(())
+}
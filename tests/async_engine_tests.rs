@@ -0,0 +1,175 @@
+use anyhow::Result;
+use rust_decimal::Decimal;
+use std::{num::NonZeroUsize, str::FromStr};
+use toypay::{
+    engine::{fees::FeeSchedule, AsyncEngine, IgnoreReason, Outcome, TransactionConflict},
+    models::{ids::ClientId, input_transaction::InputTransaction},
+    ToyEngine,
+};
+
+fn create_transaction(
+    transaction_type: &str,
+    client: u16,
+    tx: u32,
+    amount: Option<&str>,
+) -> InputTransaction {
+    InputTransaction {
+        transaction_type: transaction_type.to_string(),
+        client: client.into(),
+        tx: tx.into(),
+        amount: amount.map(|a| Decimal::from_str(a).unwrap()),
+        timestamp: None,
+        tenant: None,
+    }
+}
+
+fn assert_send_sync_clone<T: Send + Sync + Clone>() {}
+
+#[test]
+fn test_handle_is_send_sync_and_clone() {
+    assert_send_sync_clone::<AsyncEngine>();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_submissions() -> Result<()> {
+    let engine = AsyncEngine::spawn(4, ToyEngine::new);
+
+    let tasks: Vec<_> = (1..=16u16)
+        .map(|client| {
+            let engine = engine.clone();
+            tokio::spawn(async move {
+                for i in 0..50u32 {
+                    let tx = client as u32 * 1_000 + i;
+                    let deposit = create_transaction("deposit", client, tx, Some("2.00"));
+                    assert_eq!(engine.dispatch(deposit).await?, Outcome::Applied);
+                }
+                let withdrawal = create_transaction(
+                    "withdrawal",
+                    client,
+                    client as u32 * 1_000 + 999,
+                    Some("30"),
+                );
+                engine.dispatch(withdrawal).await
+            })
+        })
+        .collect();
+
+    for task in tasks {
+        assert_eq!(task.await??, Outcome::Applied);
+    }
+    assert_eq!(engine.queue_depths(), vec![0; 4]);
+
+    let metrics = engine.metrics().await?;
+    assert_eq!(metrics.count("deposit", "applied"), 800);
+    assert_eq!(metrics.accounts, 16);
+    assert_eq!(metrics.shards.len(), 4);
+    let text = metrics.to_prometheus();
+    for shard in 0..4 {
+        assert!(text.contains(&format!("toypay_queue_depth{{shard=\"{}\"}} 0\n", shard)));
+    }
+
+    let accounts = engine.clone().shutdown().await?;
+    assert_eq!(accounts.len(), 16);
+    assert!(accounts.windows(2).all(|w| w[0].client < w[1].client));
+    assert!(accounts
+        .iter()
+        .all(|a| a.available == Decimal::from_str("70").unwrap()));
+
+    let late = create_transaction("deposit", 1, 1, Some("1.00"));
+    assert!(engine.dispatch(late).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_shutdown_drains_and_merges_house_accounts() -> Result<()> {
+    let fees: FeeSchedule = toml::from_str(
+        r#"
+        house_account = 0
+        withdrawal = { kind = "flat", amount = 1 }
+        "#,
+    )?;
+    let engine = AsyncEngine::spawn(3, || ToyEngine::new().with_fees(fees.clone()));
+
+    let mut pending = Vec::new();
+    for client in 1..=6u16 {
+        let engine = engine.clone();
+        pending.push(tokio::spawn(async move {
+            let tx = client as u32 * 10;
+            engine
+                .dispatch(create_transaction("deposit", client, tx, Some("10")))
+                .await?;
+            engine
+                .dispatch(create_transaction("withdrawal", client, tx + 1, Some("5")))
+                .await
+        }));
+    }
+    for task in pending {
+        assert_eq!(task.await??, Outcome::Applied);
+    }
+
+    let accounts = engine.shutdown().await?;
+    let house = accounts
+        .iter()
+        .find(|a| a.client == ClientId::from(0u16))
+        .unwrap();
+    assert_eq!(house.total, Decimal::from(6));
+    assert_eq!(accounts.len(), 7);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cache_capacity_is_shared_by_the_shards() -> Result<()> {
+    let capacity = NonZeroUsize::new(8).unwrap();
+    let engine = AsyncEngine::spawn(2, || {
+        ToyEngine::new()
+            .with_shard_count(1)
+            .with_cache_capacity(capacity)
+    });
+
+    for tx in 1..=10u32 {
+        engine
+            .dispatch(create_transaction("deposit", 1, tx, Some("1")))
+            .await?;
+    }
+    let metrics = engine.metrics().await?;
+    let shard = metrics
+        .shards
+        .iter()
+        .find(|shard| shard.cached_transactions > 0)
+        .unwrap();
+    assert_eq!(shard.cached_transactions, 4);
+    assert_eq!(shard.evictions, 6);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ids_conflict_across_shards() -> Result<()> {
+    let engine = AsyncEngine::spawn(4, ToyEngine::new);
+
+    let deposit = create_transaction("deposit", 1, 1, Some("10"));
+    assert_eq!(engine.dispatch(deposit.clone()).await?, Outcome::Applied);
+    assert_eq!(engine.dispatch(deposit).await?, Outcome::Duplicate);
+    // Clients spread over every shard, the one of client 1 included.
+    for client in 2..=16u16 {
+        let reused = create_transaction("deposit", client, 1, Some("10"));
+        let error = engine.dispatch(reused).await.unwrap_err();
+        assert!(error.downcast_ref::<TransactionConflict>().is_some());
+    }
+
+    // An id is only taken once applied.
+    let withdrawal = create_transaction("withdrawal", 1, 2, Some("50"));
+    assert_eq!(
+        engine.dispatch(withdrawal).await?,
+        Outcome::Ignored(IgnoreReason::InsufficientFunds)
+    );
+    let deposit = create_transaction("deposit", 3, 2, Some("5"));
+    assert_eq!(engine.dispatch(deposit).await?, Outcome::Applied);
+
+    let accounts = engine.shutdown().await?;
+    assert_eq!(accounts.len(), 2);
+
+    Ok(())
+}
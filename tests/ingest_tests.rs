@@ -0,0 +1,121 @@
+use serde_json::{json, Value};
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    thread,
+};
+use toypay::{ingest, ToyEngine};
+
+fn start_ingest(queue_capacity: usize) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || ingest::serve(listener, ToyEngine::new(), queue_capacity));
+    addr
+}
+
+fn send(addr: SocketAddr, lines: &[String]) -> Vec<Value> {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    for line in lines {
+        writeln!(stream, "{}", line).unwrap();
+    }
+    stream.shutdown(Shutdown::Write).unwrap();
+
+    BufReader::new(stream)
+        .lines()
+        .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_csv_and_json_lines_are_acknowledged_in_order() {
+    let addr = start_ingest(16);
+
+    let lines = [
+        "type,client,tx,amount",
+        "deposit,1,1,10.0",
+        r#"{"type": "withdrawal", "client": 1, "tx": 2, "amount": "4"}"#,
+        "withdrawal, 1, 3, 100",
+        "deposit,1,1,10.0",
+        "refund,1,4,1",
+        "not a transaction",
+        "dispute,1,1",
+    ]
+    .map(String::from);
+
+    let acks = send(addr, &lines);
+    assert_eq!(acks.len(), 7);
+    assert_eq!(acks[0], json!({ "tx": 1, "outcome": "applied" }));
+    assert_eq!(acks[1], json!({ "tx": 2, "outcome": "applied" }));
+    assert_eq!(
+        acks[2],
+        json!({ "tx": 3, "outcome": "ignored", "reason": "insufficient funds" })
+    );
+    assert_eq!(acks[3], json!({ "tx": 1, "outcome": "duplicate" }));
+    assert_eq!(acks[4]["outcome"], "rejected");
+    assert_eq!(acks[4]["tx"], 4);
+    assert_eq!(acks[5]["outcome"], "rejected");
+    assert!(acks[5].get("tx").is_none());
+    assert_eq!(
+        acks[6],
+        json!({ "tx": 1, "outcome": "ignored", "reason": "insufficient funds" })
+    );
+}
+
+#[test]
+fn test_concurrent_connections_with_small_queue() {
+    let addr = start_ingest(1);
+
+    let senders: Vec<_> = (1..=8u16)
+        .map(|client| {
+            thread::spawn(move || {
+                let lines: Vec<String> = (0..200u32)
+                    .map(|i| format!("deposit,{},{},1.0", client, client as u32 * 1_000 + i))
+                    .collect();
+                send(addr, &lines)
+            })
+        })
+        .collect();
+
+    for sender in senders {
+        let acks = sender.join().unwrap();
+        assert_eq!(acks.len(), 200);
+        assert!(acks.iter().all(|ack| ack["outcome"] == "applied"));
+    }
+}
+
+#[test]
+fn test_metrics_listener() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let metrics = TcpListener::bind("127.0.0.1:0").unwrap();
+    let (addr, metrics_addr) = (
+        listener.local_addr().unwrap(),
+        metrics.local_addr().unwrap(),
+    );
+    thread::spawn(move || {
+        ingest::serve_with_metrics(listener, ToyEngine::new(), 16, Some(metrics))
+    });
+
+    let acks = send(
+        addr,
+        &[
+            "deposit,1,1,10.0".to_string(),
+            "deposit,1,1,10.0".to_string(),
+        ],
+    );
+    assert_eq!(acks.len(), 2);
+
+    let mut stream = TcpStream::connect(metrics_addr).unwrap();
+    write!(
+        stream,
+        "GET /metrics HTTP/1.1\r\nHost: {metrics_addr}\r\nConnection: close\r\n\r\n"
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    assert!(
+        response.contains("toypay_transactions_total{type=\"deposit\",outcome=\"duplicate\"} 1\n")
+    );
+    assert!(response.contains("toypay_queue_depth{shard=\"0\"} 0\n"));
+    assert!(response.contains("toypay_queue_depth{shard=\"7\"} 0\n"));
+}
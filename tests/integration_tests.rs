@@ -1,7 +1,39 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use rust_decimal::Decimal;
-use std::str::FromStr;
-use toypay::{models::input_transaction::InputTransaction, ToyEngine};
+use std::{
+    io,
+    num::NonZeroUsize,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use toypay::{
+    engine::{
+        archive::TransactionArchive,
+        config::{EngineConfig, ErrorMode, StorageConfig},
+        fees::FeeSchedule,
+        ledger::{as_of, LedgerBackend, LedgerEvent, LedgerFile, LedgerRecord, PointInTime},
+        observer::{EngineEvent, EngineObserver, JsonLinesObserver, LockReason},
+        outbox::{DomainEvent, Outbox, OutboxConsumer},
+        policy::{AccountPolicy, DisputePolicy, LockedAccountPolicy},
+        projections::{project, Balances, OpenDispute, OpenDisputes},
+        registry::ClientRegistry,
+        reorder::ReorderBuffer,
+        risk::{RiskAction, RiskConfig, RiskFlag, RiskSignal},
+        rules::{LimitsConfig, RuleViolation},
+        tenants::{TenantConfig, TenantRouter, TenantsConfig},
+        IgnoreReason, Outcome, TransactionConflict,
+    },
+    models::{
+        client::{ClientInfo, ClientStatus},
+        ids::{ClientId, TxId},
+        input_transaction::InputTransaction,
+        output_record::OutputRecord,
+        transaction::DisputeState,
+        transaction_kind::TransactionKind,
+    },
+    replay, ToyEngine,
+};
 
 fn create_transaction(
     transaction_type: &str,
@@ -11,12 +43,22 @@ fn create_transaction(
 ) -> InputTransaction {
     InputTransaction {
         transaction_type: transaction_type.to_string(),
-        client,
-        tx,
+        client: client.into(),
+        tx: tx.into(),
         amount: amount.map(|a| Decimal::from_str(a).unwrap()),
+        timestamp: None,
+        tenant: None,
     }
 }
 
+fn client_id(id: u16) -> ClientId {
+    ClientId::from(id)
+}
+
+fn tx_id(id: u32) -> TxId {
+    TxId::from(id)
+}
+
 fn dispatch_transaction(transactions: Vec<InputTransaction>) -> Result<ToyEngine> {
     let mut engine = ToyEngine::new();
 
@@ -44,13 +86,13 @@ fn test_complete_transaction_workflow() -> Result<()> {
 
     assert_eq!(accounts.len(), 2);
 
-    let client1 = accounts.iter().find(|a| a.client == 1).unwrap();
+    let client1 = accounts.iter().find(|a| a.client == client_id(1)).unwrap();
     assert_eq!(client1.available, Decimal::from_str("105.00")?);
     assert_eq!(client1.held, Decimal::ZERO);
     assert_eq!(client1.total, Decimal::from_str("105.00")?);
     assert!(!client1.locked);
 
-    let client2 = accounts.iter().find(|a| a.client == 2).unwrap();
+    let client2 = accounts.iter().find(|a| a.client == client_id(2)).unwrap();
     assert_eq!(client2.available, Decimal::from_str("60.00")?);
     assert_eq!(client2.held, Decimal::ZERO);
     assert_eq!(client2.total, Decimal::from_str("60.00")?);
@@ -81,19 +123,19 @@ fn test_dispute_resolution_and_chargeback_workflow() -> Result<()> {
 
     assert_eq!(accounts.len(), 3);
 
-    let client1 = accounts.iter().find(|a| a.client == 1).unwrap();
+    let client1 = accounts.iter().find(|a| a.client == client_id(1)).unwrap();
     assert_eq!(client1.available, Decimal::from_str("140.00")?);
     assert_eq!(client1.held, Decimal::ZERO);
     assert_eq!(client1.total, Decimal::from_str("140.00")?);
     assert!(!client1.locked);
 
-    let client2 = accounts.iter().find(|a| a.client == 2).unwrap();
+    let client2 = accounts.iter().find(|a| a.client == client_id(2)).unwrap();
     assert_eq!(client2.available, Decimal::from_str("75.00")?);
     assert_eq!(client2.held, Decimal::ZERO);
     assert_eq!(client2.total, Decimal::from_str("75.00")?);
     assert!(client2.locked);
 
-    let client3 = accounts.iter().find(|a| a.client == 3).unwrap();
+    let client3 = accounts.iter().find(|a| a.client == client_id(3)).unwrap();
     assert_eq!(client3.available, Decimal::from_str("50.00")?);
     assert_eq!(client3.held, Decimal::ZERO);
     assert_eq!(client3.total, Decimal::from_str("50.00")?);
@@ -101,3 +143,1482 @@ fn test_dispute_resolution_and_chargeback_workflow() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_fees_are_credited_to_house_account() -> Result<()> {
+    let fees: FeeSchedule = toml::from_str(
+        r#"
+        house_account = 0
+
+        [withdrawal]
+        kind = "percentage"
+        rate = 0.01
+        min = 0.50
+
+        [chargeback]
+        kind = "flat"
+        amount = 20
+        "#,
+    )?;
+    let mut engine = ToyEngine::new().with_fees(fees);
+
+    let transactions = vec![
+        create_transaction("deposit", 1, 1, Some("100.00")),
+        create_transaction("withdrawal", 1, 2, Some("10.00")),
+        create_transaction("withdrawal", 1, 3, Some("89.50")),
+        create_transaction("deposit", 2, 4, Some("50.00")),
+        create_transaction("deposit", 2, 5, Some("30.00")),
+        create_transaction("dispute", 2, 4, None),
+        create_transaction("chargeback", 2, 4, None),
+    ];
+    for transaction in transactions {
+        engine.dispatch(transaction)?;
+    }
+
+    let accounts = engine.get_all_accounts();
+    assert_eq!(accounts.len(), 3);
+
+    let house = accounts.iter().find(|a| a.client == client_id(0)).unwrap();
+    assert_eq!(house.total, Decimal::from_str("20.50")?);
+
+    let client1 = accounts.iter().find(|a| a.client == client_id(1)).unwrap();
+    assert_eq!(client1.available, Decimal::from_str("89.50")?);
+
+    let client2 = accounts.iter().find(|a| a.client == client_id(2)).unwrap();
+    assert_eq!(client2.available, Decimal::from_str("10.00")?);
+    assert_eq!(client2.held, Decimal::ZERO);
+    assert!(client2.locked);
+
+    Ok(())
+}
+
+#[test]
+fn test_house_account_cannot_transact() -> Result<()> {
+    let mut engine = ToyEngine::new().with_fees(FeeSchedule::from_file("sample_data/fees.toml")?);
+    let house = ClientId::Name("fees".into());
+    let row = |kind: &str, tx: u32, amount: Option<&str>| InputTransaction {
+        client: house.clone(),
+        ..create_transaction(kind, 0, tx, amount)
+    };
+
+    engine.dispatch(create_transaction("deposit", 1, 1, Some("100.00")))?;
+    engine.dispatch(create_transaction("withdrawal", 1, 2, Some("10.00")))?;
+    assert_eq!(
+        engine.account(&house).unwrap().available,
+        Decimal::from_str("0.50")?
+    );
+
+    // Neither deposits nor fee-free withdrawals, whatever the name.
+    assert!(engine.dispatch(row("deposit", 3, Some("1000.00"))).is_err());
+    assert!(engine.dispatch(row("withdrawal", 4, Some("0.50"))).is_err());
+    assert!(engine.dispatch(row("dispute", 2, None)).is_err());
+    assert_eq!(
+        engine.account(&house).unwrap().available,
+        Decimal::from_str("0.50")?
+    );
+
+    // A numeric house account is reserved all the same.
+    let mut engine = ToyEngine::new().with_fees(toml::from_str("house_account = 0")?);
+    assert!(engine
+        .dispatch(create_transaction("deposit", 0, 1, Some("1.00")))
+        .is_err());
+    assert!(engine.account(&client_id(0)).is_none());
+    Ok(())
+}
+
+#[test]
+fn test_limits_reject_before_funds_move() -> Result<()> {
+    let limits: LimitsConfig = toml::from_str(
+        r#"
+        max_withdrawal = 50
+        max_balance = 200
+
+        [[clients]]
+        client = 2
+        max_withdrawal = 500
+        "#,
+    )?;
+    let mut engine = ToyEngine::new().with_limits(limits);
+
+    engine.dispatch(create_transaction("deposit", 1, 1, Some("150.00")))?;
+    engine.dispatch(create_transaction("deposit", 2, 2, Some("150.00")))?;
+
+    let rejected = engine
+        .dispatch(create_transaction("withdrawal", 1, 3, Some("60.00")))
+        .unwrap_err();
+    assert_eq!(
+        rejected.downcast_ref::<RuleViolation>(),
+        Some(&RuleViolation::MaxWithdrawal)
+    );
+
+    let rejected = engine
+        .dispatch(create_transaction("deposit", 1, 4, Some("60.00")))
+        .unwrap_err();
+    assert_eq!(
+        rejected.downcast_ref::<RuleViolation>(),
+        Some(&RuleViolation::MaxBalance)
+    );
+
+    assert_eq!(
+        engine.dispatch(create_transaction("withdrawal", 2, 5, Some("60.00")))?,
+        Outcome::Applied
+    );
+
+    let accounts = engine.get_all_accounts();
+    let client1 = accounts.iter().find(|a| a.client == client_id(1)).unwrap();
+    assert_eq!(client1.total, Decimal::from_str("150.00")?);
+    let client2 = accounts.iter().find(|a| a.client == client_id(2)).unwrap();
+    assert_eq!(client2.total, Decimal::from_str("90.00")?);
+
+    Ok(())
+}
+
+#[test]
+fn test_repeated_disputes_freeze_account() -> Result<()> {
+    let risk: RiskConfig = toml::from_str(
+        r#"
+        dispute_burst = { disputes = 2, window = 4, action = "freeze" }
+        chargebacks = { count = 1, action = "flag" }
+        "#,
+    )?;
+    let mut engine = ToyEngine::new().with_risk(risk);
+
+    let transactions = vec![
+        create_transaction("deposit", 1, 1, Some("10.00")),
+        create_transaction("deposit", 1, 2, Some("20.00")),
+        create_transaction("dispute", 1, 1, None),
+        create_transaction("dispute", 1, 2, None),
+        create_transaction("deposit", 1, 3, Some("5.00")),
+        create_transaction("deposit", 2, 4, Some("10.00")),
+        create_transaction("dispute", 2, 4, None),
+        create_transaction("chargeback", 2, 4, None),
+    ];
+    for transaction in transactions {
+        engine.dispatch(transaction)?;
+    }
+
+    let accounts = engine.get_all_accounts();
+    let client1 = accounts.iter().find(|a| a.client == client_id(1)).unwrap();
+    assert_eq!(client1.held, Decimal::from_str("30.00")?);
+    assert_eq!(client1.total, Decimal::from_str("30.00")?);
+    assert!(client1.locked);
+
+    assert_eq!(
+        engine.risk_flags(),
+        vec![
+            RiskFlag {
+                client: client_id(1),
+                signal: RiskSignal::DisputeBurst,
+                action: RiskAction::Freeze,
+            },
+            RiskFlag {
+                client: client_id(2),
+                signal: RiskSignal::Chargebacks,
+                action: RiskAction::Flag,
+            },
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_timestamped_input_with_reordering_and_dispute_window() -> Result<()> {
+    let input = "\
+type,client,tx,amount,timestamp
+deposit,1,1,100.0,2024-01-01T09:00:00Z
+withdrawal,1,3,30.0,2024-03-01T10:00:05Z
+deposit,1,2,20.0,2024-03-01T10:00:00Z
+dispute,1,2,,1709373600000
+dispute,1,1,,2024-04-15T09:00:00Z
+";
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(input.as_bytes());
+
+    let mut engine = ToyEngine::new().with_dispute_policy(DisputePolicy {
+        max_age: Some(Duration::from_secs(90 * 86_400)),
+    });
+    let mut buffer = ReorderBuffer::new(Duration::from_secs(10));
+    let mut outcomes = Vec::new();
+
+    for transaction in reader.deserialize::<InputTransaction>() {
+        buffer.push(transaction?)?;
+        while let Some(transaction) = buffer.pop_ready() {
+            outcomes.push(engine.dispatch(transaction)?);
+        }
+    }
+    while let Some(transaction) = buffer.pop() {
+        outcomes.push(engine.dispatch(transaction)?);
+    }
+
+    assert_eq!(
+        outcomes,
+        vec![
+            Outcome::Applied,
+            Outcome::Applied,
+            Outcome::Applied,
+            Outcome::Applied,
+            Outcome::Ignored(IgnoreReason::DisputeWindowExpired),
+        ]
+    );
+
+    let accounts = engine.get_all_accounts();
+    let client1 = accounts.iter().find(|a| a.client == client_id(1)).unwrap();
+    assert_eq!(client1.available, Decimal::from_str("70.00")?);
+    assert_eq!(client1.held, Decimal::from_str("20.00")?);
+
+    Ok(())
+}
+
+#[test]
+fn test_resubmitted_rows_are_idempotent() -> Result<()> {
+    let mut engine = ToyEngine::new();
+
+    let rows = vec![
+        (
+            create_transaction("deposit", 1, 1, Some("100.00")),
+            Outcome::Applied,
+        ),
+        (
+            create_transaction("deposit", 1, 1, Some("100.00")),
+            Outcome::Duplicate,
+        ),
+        (
+            create_transaction("withdrawal", 1, 2, Some("30.00")),
+            Outcome::Applied,
+        ),
+        (
+            create_transaction("withdrawal", 1, 2, Some("30.00")),
+            Outcome::Duplicate,
+        ),
+        (
+            create_transaction("deposit", 1, 3, Some("10.00")),
+            Outcome::Applied,
+        ),
+        (create_transaction("dispute", 1, 3, None), Outcome::Applied),
+        (
+            create_transaction("dispute", 1, 3, None),
+            Outcome::Duplicate,
+        ),
+        (create_transaction("resolve", 1, 3, None), Outcome::Applied),
+        (
+            create_transaction("resolve", 1, 3, None),
+            Outcome::Duplicate,
+        ),
+        (
+            create_transaction("dispute", 1, 2, None),
+            Outcome::Ignored(IgnoreReason::NotDisputable),
+        ),
+    ];
+    for (transaction, expected) in rows {
+        assert_eq!(engine.dispatch(transaction)?, expected);
+    }
+
+    for conflicting in [
+        create_transaction("deposit", 1, 1, Some("90.00")),
+        create_transaction("withdrawal", 1, 1, Some("100.00")),
+        create_transaction("deposit", 2, 2, Some("30.00")),
+    ] {
+        let err = engine.dispatch(conflicting).unwrap_err();
+        assert!(err.downcast_ref::<TransactionConflict>().is_some());
+    }
+
+    let accounts = engine.get_all_accounts();
+    assert_eq!(accounts.len(), 1);
+    assert_eq!(accounts[0].available, Decimal::from_str("80.00")?);
+    assert_eq!(accounts[0].held, Decimal::ZERO);
+
+    Ok(())
+}
+
+#[test]
+fn test_query_api() -> Result<()> {
+    let engine = dispatch_transaction(vec![
+        create_transaction("deposit", 1, 1, Some("10.00")),
+        create_transaction("deposit", 1, 2, Some("20.00")),
+        create_transaction("deposit", 1, 3, Some("30.00")),
+        create_transaction("dispute", 1, 3, None),
+        create_transaction("dispute", 1, 1, None),
+        create_transaction("deposit", 2, 4, Some("5.00")),
+        create_transaction("dispute", 2, 4, None),
+        create_transaction("chargeback", 2, 4, None),
+    ])?;
+
+    let account = engine.account(&client_id(1)).unwrap();
+    assert_eq!(account.available, Decimal::from_str("20.00")?);
+    assert_eq!(account.held, Decimal::from_str("40.00")?);
+    assert!(!account.locked);
+
+    let tx = engine.transaction(tx_id(2)).unwrap();
+    assert_eq!(
+        (tx.client, tx.amount),
+        (client_id(1), Decimal::from_str("20.00")?)
+    );
+    assert_eq!(tx.kind, TransactionKind::Deposit);
+    assert_eq!(tx.state, DisputeState::Undisputed);
+    assert_eq!(
+        engine.transaction(tx_id(4)).unwrap().state,
+        DisputeState::ChargedBack
+    );
+
+    let disputed: Vec<TxId> = engine
+        .disputed_transactions(&client_id(1))
+        .iter()
+        .map(|view| view.tx)
+        .collect();
+    assert_eq!(disputed, vec![tx_id(1), tx_id(3)]);
+    assert!(engine.disputed_transactions(&client_id(2)).is_empty());
+
+    let locked = engine.locked_accounts();
+    assert_eq!(locked.len(), 1);
+    assert_eq!(locked[0].client, client_id(2));
+
+    // Looking up unknown ids must not create anything.
+    assert!(engine.account(&client_id(42)).is_none());
+    assert!(engine.transaction(tx_id(42)).is_none());
+    assert!(engine.disputed_transactions(&client_id(42)).is_empty());
+    assert_eq!(engine.get_all_accounts().len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_query_api_after_rejections_and_resolutions() -> Result<()> {
+    let mut engine = ToyEngine::new();
+    for transaction in [
+        create_transaction("deposit", 1, 1, Some("10.00")),
+        create_transaction("dispute", 1, 1, None),
+        create_transaction("resolve", 1, 1, None),
+        create_transaction("withdrawal", 1, 2, Some("4.00")),
+    ] {
+        engine.dispatch(transaction)?;
+    }
+    // Rows refused for lack of funds or a wrong client leave no trace in the
+    // queries.
+    let _ = engine.dispatch(create_transaction("withdrawal", 1, 3, Some("100.00")));
+    let _ = engine.dispatch(create_transaction("dispute", 2, 2, None));
+
+    let withdrawal = engine.transaction(tx_id(2)).unwrap();
+    assert_eq!(withdrawal.kind, TransactionKind::Withdrawal);
+    assert_eq!(withdrawal.state, DisputeState::Undisputed);
+    assert_eq!(
+        engine.transaction(tx_id(1)).unwrap().state,
+        DisputeState::Resolved
+    );
+    assert!(engine.transaction(tx_id(3)).is_none());
+    assert!(engine.disputed_transactions(&client_id(1)).is_empty());
+    assert!(engine.locked_accounts().is_empty());
+    assert!(engine.account(&client_id(2)).is_none());
+
+    // A view is the balances row of its account.
+    assert_eq!(
+        engine.account(&client_id(1)),
+        engine.get_all_accounts().into_iter().next()
+    );
+    assert_eq!(
+        engine.account(&client_id(1)).unwrap().total,
+        Decimal::from_str("6.00")?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_account_policies() -> Result<()> {
+    let transactions = || {
+        vec![
+            create_transaction("withdrawal", 1, 1, Some("10.00")),
+            create_transaction("dispute", 2, 99, None),
+            create_transaction("deposit", 3, 2, Some("5.00")),
+        ]
+    };
+    let clients = |engine: &ToyEngine| -> Vec<ClientId> {
+        engine
+            .get_all_accounts()
+            .into_iter()
+            .map(|a| a.client)
+            .collect()
+    };
+
+    let mut engine = ToyEngine::new();
+    for transaction in transactions() {
+        engine.dispatch(transaction)?;
+    }
+    assert_eq!(clients(&engine), vec![client_id(3)]);
+
+    let mut engine = ToyEngine::new().with_account_policy(AccountPolicy::OnReference);
+    for transaction in transactions() {
+        engine.dispatch(transaction)?;
+    }
+    assert_eq!(clients(&engine), [1, 2, 3].map(client_id));
+
+    let registry = ClientRegistry::from_iter([1, 2].map(|id| ClientInfo::new(client_id(id))));
+    let mut engine = ToyEngine::new()
+        .with_registry(registry)
+        .with_account_policy(AccountPolicy::Registered);
+    assert_eq!(clients(&engine), [1, 2].map(client_id));
+    let outcomes = transactions()
+        .into_iter()
+        .map(|transaction| engine.dispatch(transaction))
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(
+        outcomes,
+        vec![
+            Outcome::Ignored(IgnoreReason::InsufficientFunds),
+            Outcome::Ignored(IgnoreReason::UnknownTransaction),
+            Outcome::Ignored(IgnoreReason::UnknownAccount),
+        ]
+    );
+    assert_eq!(clients(&engine), [1, 2].map(client_id));
+
+    Ok(())
+}
+
+#[test]
+fn test_client_registry() -> Result<()> {
+    let client = |client, tier: &str, status| ClientInfo {
+        kyc_tier: Some(tier.to_string()),
+        status,
+        ..ClientInfo::new(client_id(client))
+    };
+    let registry = ClientRegistry::from_iter([
+        client(1, "basic", ClientStatus::Active),
+        client(2, "verified", ClientStatus::Active),
+        client(3, "basic", ClientStatus::Suspended),
+    ]);
+    let limits: LimitsConfig = toml::from_str(
+        r#"
+        max_withdrawal = 100
+
+        [[tiers]]
+        tier = "basic"
+        max_withdrawal = 10
+
+        [[clients]]
+        client = 2
+        max_balance = 1000
+        "#,
+    )?;
+    let mut engine = ToyEngine::new().with_registry(registry).with_limits(limits);
+
+    for client in 1..=2 {
+        engine.dispatch(create_transaction(
+            "deposit",
+            client,
+            client as u32,
+            Some("500"),
+        ))?;
+    }
+    assert_eq!(
+        engine.dispatch(create_transaction("deposit", 3, 3, Some("500")))?,
+        Outcome::Ignored(IgnoreReason::ClientInactive)
+    );
+
+    let err = engine
+        .dispatch(create_transaction("withdrawal", 1, 10, Some("50")))
+        .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<RuleViolation>(),
+        Some(&RuleViolation::MaxWithdrawal)
+    );
+    assert_eq!(
+        engine.dispatch(create_transaction("withdrawal", 2, 11, Some("50")))?,
+        Outcome::Applied
+    );
+
+    let accounts = engine.get_detailed_accounts();
+    assert_eq!(accounts.len(), 2);
+    assert_eq!(accounts[0].kyc_tier.as_deref(), Some("basic"));
+    assert_eq!(accounts[1].status, Some(ClientStatus::Active));
+    assert_eq!(
+        engine.client(&client_id(3)).unwrap().status,
+        ClientStatus::Suspended
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_wide_and_named_ids() -> Result<()> {
+    let uuid = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+    let input = format!(
+        "type,client,tx,amount\n\
+         deposit,4000000000,18000000000000000000,10.0\n\
+         deposit,acme-eu,{uuid},20.0\n\
+         withdrawal,4000000000,7,5.0\n\
+         dispute,acme-eu,{uuid},\n"
+    );
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(input.as_bytes());
+
+    let mut engine = ToyEngine::new();
+    for transaction in reader.deserialize::<InputTransaction>() {
+        assert_eq!(engine.dispatch(transaction?)?, Outcome::Applied);
+    }
+
+    let accounts = engine.get_all_accounts();
+    assert_eq!(accounts[0].client, ClientId::Num(4_000_000_000));
+    assert_eq!(accounts[1].client, ClientId::Name("acme-eu".into()));
+    assert_eq!(accounts[1].held, Decimal::from(20));
+
+    let tx = engine.transaction(uuid.parse()?).unwrap();
+    assert_eq!(tx.state, DisputeState::Disputed);
+    assert!(engine
+        .transaction(TxId::Num(18_000_000_000_000_000_000))
+        .is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_rebalance_keeps_cache_capacity_and_recency() -> Result<()> {
+    let mut engine = ToyEngine::new()
+        .with_shard_count(2)
+        .with_cache_capacity(NonZeroUsize::new(3).unwrap());
+    // Clients 1 to 3 share a shard, 5 to 7 the other one.
+    for client in [1, 2, 3, 5, 6, 7] {
+        let tx = client as u32;
+        engine.dispatch(create_transaction("deposit", client, tx, Some("1.00")))?;
+    }
+    engine.dispatch(create_transaction("dispute", 1, 1, None))?;
+    assert!(engine
+        .metrics()
+        .shards
+        .iter()
+        .all(|shard| shard.evictions == 0));
+
+    // One shard holds the six transactions, and the next one pushes out the
+    // least recently used of them all.
+    engine.rebalance(1);
+    assert_eq!(engine.metrics().shards[0].cached_transactions, 6);
+    engine.dispatch(create_transaction("deposit", 4, 8, Some("1.00")))?;
+    assert_eq!(engine.metrics().shards[0].evictions, 1);
+    assert!(engine.transaction(tx_id(2)).is_none());
+    assert!(engine.transaction(tx_id(1)).is_some());
+    assert!(engine.transaction(tx_id(5)).is_some());
+    Ok(())
+}
+
+#[test]
+fn test_rebalance_keeps_state() -> Result<()> {
+    let mut engine = ToyEngine::new().with_shard_count(3);
+    assert_eq!(engine.shard_count(), 3);
+
+    for client in 1..=50u16 {
+        let tx = client as u32;
+        engine.dispatch(create_transaction("deposit", client, tx, Some("10.00")))?;
+        if client % 5 == 0 {
+            engine.dispatch(create_transaction("dispute", client, tx, None))?;
+        }
+    }
+    let before = format!("{:?}", engine.get_all_accounts());
+
+    engine.rebalance(11);
+    assert_eq!(engine.shard_count(), 11);
+    assert_eq!(format!("{:?}", engine.get_all_accounts()), before);
+    assert_eq!(
+        engine.disputed_transactions(&client_id(10))[0].tx,
+        tx_id(10)
+    );
+
+    // Transactions still resolve and deduplicate after the migration.
+    assert_eq!(
+        engine.dispatch(create_transaction("resolve", 10, 10, None))?,
+        Outcome::Applied
+    );
+    assert_eq!(
+        engine.dispatch(create_transaction("deposit", 7, 7, Some("10.00")))?,
+        Outcome::Duplicate
+    );
+
+    engine.rebalance(1);
+    assert_eq!(engine.account(&client_id(10)).unwrap().held, Decimal::ZERO);
+    assert_eq!(engine.get_all_accounts().len(), 50);
+
+    Ok(())
+}
+
+#[test]
+fn test_tenant_isolation() -> Result<()> {
+    let config: TenantsConfig = toml::from_str(
+        r#"
+        [tenants.acme]
+        currency = "EUR"
+
+        [tenants.acme.fees]
+        house_account = 0
+
+        [tenants.acme.fees.withdrawal]
+        kind = "flat"
+        amount = 1
+        "#,
+    )?;
+    let mut router = TenantRouter::new(|tenant: &TenantConfig| {
+        Ok(ToyEngine::new().with_fees(tenant.fees.clone().unwrap_or_default()))
+    })
+    .with_tenants(config);
+
+    let tenant_transaction = |tenant: Option<&str>, kind, amount| {
+        let mut tx = create_transaction(kind, 1, 1, amount);
+        tx.tenant = tenant.map(str::to_string);
+        tx
+    };
+
+    // Same client and transaction ids, different tenants.
+    for tenant in [Some("acme"), Some("globex"), None] {
+        assert_eq!(
+            router.dispatch(tenant_transaction(tenant, "deposit", Some("10.00")))?,
+            Outcome::Applied
+        );
+    }
+    let mut withdrawal = tenant_transaction(Some("acme"), "withdrawal", Some("5.00"));
+    withdrawal.tx = tx_id(2);
+    assert_eq!(router.dispatch(withdrawal)?, Outcome::Applied);
+    assert_eq!(
+        router.dispatch(tenant_transaction(Some("globex"), "dispute", None))?,
+        Outcome::Applied
+    );
+
+    let accounts = router.get_all_accounts();
+    let rows: Vec<_> = accounts
+        .iter()
+        .map(|r| {
+            (
+                r.tenant.as_str(),
+                r.currency.as_deref(),
+                r.client.to_string(),
+                r.available,
+                r.held,
+            )
+        })
+        .collect();
+    assert_eq!(
+        rows,
+        vec![
+            (
+                "acme",
+                Some("EUR"),
+                "0".to_string(),
+                Decimal::from(1),
+                Decimal::ZERO
+            ),
+            (
+                "acme",
+                Some("EUR"),
+                "1".to_string(),
+                Decimal::from(4),
+                Decimal::ZERO
+            ),
+            (
+                "default",
+                None,
+                "1".to_string(),
+                Decimal::from(10),
+                Decimal::ZERO
+            ),
+            (
+                "globex",
+                None,
+                "1".to_string(),
+                Decimal::ZERO,
+                Decimal::from(10)
+            ),
+        ]
+    );
+    assert!(router.engine("globex").is_some());
+    assert!(router.engine("initech").is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_tenant_engine_errors() -> Result<()> {
+    let mut router = TenantRouter::new(|_: &TenantConfig| Err(anyhow!("no engine")));
+    let error = router
+        .dispatch(create_transaction("deposit", 1, 1, Some("1.00")))
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Cannot create the engine of tenant default"
+    );
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("tenants.toml");
+    std::fs::write(&path, "[tenants.acme]\ndispute_window = 100000\n")?;
+    let error = TenantsConfig::from_file(&path).unwrap_err();
+    assert!(format!("{:#}", error).contains("dispute_window must be at most 36500 days"));
+    Ok(())
+}
+
+#[test]
+fn test_replay_compare() -> Result<()> {
+    let transactions = || {
+        let mut late_dispute = create_transaction("dispute", 1, 1, None);
+        late_dispute.timestamp = Some("2024-03-01T00:00:00Z".parse().unwrap());
+        let mut deposit = create_transaction("deposit", 1, 1, Some("10.00"));
+        deposit.timestamp = Some("2024-01-01T00:00:00Z".parse().unwrap());
+        vec![
+            create_transaction("deposit", 2, 2, Some("5.00")),
+            deposit,
+            create_transaction("deposit", 1, 3, Some("1.00")),
+            late_dispute,
+        ]
+    };
+
+    let mut expected = ToyEngine::new();
+    let mut actual = ToyEngine::new().with_dispute_policy(DisputePolicy {
+        max_age: Some(Duration::from_secs(30 * 86_400)),
+    });
+    let diffs =
+        replay::compare_engines(transactions(), &mut expected, &mut actual, ErrorMode::Skip)?;
+
+    assert_eq!(diffs.len(), 1);
+    assert_eq!(diffs[0].client, client_id(1));
+    assert_eq!(diffs[0].expected_held, Some(Decimal::from(10)));
+    assert_eq!(diffs[0].actual_held, Some(Decimal::ZERO));
+    assert_eq!(diffs[0].first_divergence, Some(tx_id(1)));
+
+    // Against a snapshot, missing and extra clients show up with one empty
+    // side, and equal amounts match whatever their scale.
+    let record = |client: u16, available: &str| OutputRecord {
+        client: client_id(client),
+        available: Decimal::from_str(available).unwrap(),
+        held: Decimal::ZERO,
+        total: Decimal::from_str(available).unwrap(),
+        locked: false,
+    };
+    let diffs = replay::compare_snapshot(vec![record(2, "5.0000"), record(3, "1")], &actual);
+    let clients: Vec<_> = diffs
+        .iter()
+        .map(|d| (d.client.clone(), d.first_divergence))
+        .collect();
+    assert_eq!(clients, vec![(client_id(1), None), (client_id(3), None)]);
+    assert_eq!(diffs[0].expected_available, None);
+    assert_eq!(diffs[1].actual_available, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_replay_input_errors() -> Result<()> {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,10.0\n\
+                 deposit,2,2,lots\n\
+                 withdrawal,1,3,4.0\n";
+
+    let (transactions, malformed) = replay::read_transactions(input.as_bytes(), ErrorMode::Skip)?;
+    assert_eq!(malformed, 1);
+    assert_eq!(
+        transactions.iter().map(|tx| tx.tx).collect::<Vec<_>>(),
+        [tx_id(1), tx_id(3)]
+    );
+    let error = replay::read_transactions(input.as_bytes(), ErrorMode::Abort).unwrap_err();
+    assert!(error.to_string().contains("Malformed row"));
+
+    // A rejected transaction stops the comparison when aborting.
+    let rejected = vec![create_transaction("refund", 1, 4, Some("1.0"))];
+    let (mut expected, mut actual) = (ToyEngine::new(), ToyEngine::new());
+    assert!(replay::compare_engines(
+        rejected.clone(),
+        &mut expected,
+        &mut actual,
+        ErrorMode::Skip
+    )?
+    .is_empty());
+    assert!(
+        replay::compare_engines(rejected, &mut expected, &mut actual, ErrorMode::Abort).is_err()
+    );
+    Ok(())
+}
+
+#[derive(Clone, Default)]
+struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_dispatch_tracing() -> Result<()> {
+    let logs = LogBuffer::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .json()
+        .with_max_level(tracing::Level::INFO)
+        .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+        .with_writer(move || writer.clone())
+        .finish();
+
+    tracing::subscriber::with_default(subscriber, || -> Result<()> {
+        let mut engine = ToyEngine::new();
+        engine.dispatch(create_transaction("deposit", 1, 1, Some("10.00")))?;
+        engine.dispatch(create_transaction("withdrawal", 1, 2, Some("50.00")))?;
+        assert!(engine
+            .dispatch(create_transaction("refund", 1, 3, None))
+            .is_err());
+        Ok(())
+    })?;
+
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone())?;
+    let lines: Vec<serde_json::Value> = logs
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    let closed: Vec<_> = lines
+        .iter()
+        .filter(|line| line["fields"]["message"] == "close")
+        .map(|line| &line["span"])
+        .collect();
+
+    assert_eq!(closed.len(), 3);
+    assert_eq!(closed[0]["client"], "1");
+    assert_eq!(closed[0]["tx"], "1");
+    assert_eq!(closed[0]["type"], "deposit");
+    assert_eq!(closed[0]["outcome"], "applied");
+    assert!(closed[0]["shard"].is_u64());
+    assert!(closed[0]["latency_us"].is_u64());
+    assert_eq!(closed[1]["outcome"], "ignored");
+    assert_eq!(closed[2]["outcome"], "rejected");
+
+    // Events carry why a transaction was not applied.
+    let reason = lines
+        .iter()
+        .find(|line| line["fields"]["message"] == "transaction ignored")
+        .unwrap();
+    assert_eq!(reason["fields"]["reason"], "insufficient funds");
+    assert_eq!(reason["level"], "INFO");
+    assert!(lines
+        .iter()
+        .any(|line| line["level"] == "WARN" && line["span"]["tx"] == "3"));
+
+    Ok(())
+}
+
+#[test]
+fn test_engine_metrics() -> Result<()> {
+    let mut engine = ToyEngine::new().with_shard_count(2);
+    engine.dispatch(create_transaction("deposit", 1, 1, Some("10.00")))?;
+    engine.dispatch(create_transaction("deposit", 2, 2, Some("10.00")))?;
+    engine.dispatch(create_transaction("withdrawal", 1, 3, Some("50.00")))?;
+    engine.dispatch(create_transaction("dispute", 2, 2, None))?;
+    engine.dispatch(create_transaction("chargeback", 2, 2, None))?;
+    assert!(engine
+        .dispatch(create_transaction("refund", 1, 4, None))
+        .is_err());
+
+    let metrics = engine.metrics();
+    assert_eq!(metrics.total(), 6);
+    assert_eq!(metrics.count("deposit", "applied"), 2);
+    assert_eq!(metrics.count("withdrawal", "ignored"), 1);
+    assert_eq!(metrics.count("unknown", "rejected"), 1);
+    assert_eq!(metrics.dispute_rate(), Some(0.5));
+    assert_eq!(metrics.chargeback_rate(), Some(1.0));
+    assert_eq!(metrics.resolve_rate(), Some(0.0));
+    assert_eq!(metrics.accounts, 2);
+    assert_eq!(metrics.locked_accounts, 1);
+    assert_eq!(metrics.latency.count(), 6);
+    assert_eq!(metrics.shards.len(), 2);
+    assert_eq!(
+        metrics
+            .shards
+            .iter()
+            .map(|shard| shard.cached_transactions)
+            .sum::<usize>(),
+        2
+    );
+    assert!(metrics.shards.iter().all(|shard| shard.evictions == 0));
+
+    Ok(())
+}
+
+#[test]
+fn test_evicted_transactions_archive() -> Result<()> {
+    let small_cache = |engine: ToyEngine| {
+        engine
+            .with_shard_count(1)
+            .with_cache_capacity(NonZeroUsize::new(1).unwrap())
+    };
+    let deposits = [
+        create_transaction("deposit", 1, 1, Some("10.00")),
+        create_transaction("deposit", 1, 2, Some("5.00")),
+    ];
+
+    // Without an archive, the evicted deposit cannot be disputed anymore.
+    let mut engine = small_cache(ToyEngine::new());
+    for deposit in deposits.clone() {
+        engine.dispatch(deposit)?;
+    }
+    assert_eq!(engine.metrics().shards[0].evictions, 1);
+    assert_eq!(
+        engine.dispatch(create_transaction("dispute", 1, 1, None))?,
+        Outcome::Ignored(IgnoreReason::UnknownTransaction)
+    );
+
+    let dir = tempfile::tempdir()?;
+    let archive = TransactionArchive::create(dir.path().join("archive.jsonl"))?;
+    let mut engine = small_cache(ToyEngine::new()).with_archive(archive);
+    for deposit in deposits.clone() {
+        engine.dispatch(deposit)?;
+    }
+    assert_eq!(engine.dispatch(deposits[0].clone())?, Outcome::Duplicate);
+    assert!(engine
+        .dispatch(create_transaction("deposit", 2, 1, Some("1.00")))
+        .is_err());
+
+    // The dispute brings tx 1 back to the cache, pushing tx 2 out, and both
+    // keep their state through further evictions and a rebalance.
+    assert_eq!(
+        engine.dispatch(create_transaction("dispute", 1, 1, None))?,
+        Outcome::Applied
+    );
+    assert_eq!(
+        engine.dispatch(create_transaction("dispute", 1, 2, None))?,
+        Outcome::Applied
+    );
+    engine.rebalance(2);
+    assert_eq!(
+        engine.transaction(tx_id(1)).unwrap().state,
+        DisputeState::Disputed
+    );
+    assert_eq!(
+        engine.dispatch(create_transaction("chargeback", 1, 1, None))?,
+        Outcome::Applied
+    );
+
+    let account = engine.account(&client_id(1)).unwrap();
+    assert_eq!(account.held, Decimal::from(5));
+    assert_eq!(account.total, Decimal::from(5));
+    assert!(account.locked);
+    Ok(())
+}
+
+#[test]
+fn test_engine_config() -> Result<()> {
+    let config = EngineConfig::from_file("sample_data/engine.toml")?;
+    assert_eq!(config.storage.shards, 8);
+    assert_eq!(config.dispute_window, Some(90));
+
+    let config = EngineConfig {
+        amount_scale: 3,
+        locked_accounts: LockedAccountPolicy::AcceptDeposits,
+        storage: StorageConfig {
+            shards: 2,
+            ..StorageConfig::default()
+        },
+        ..EngineConfig::default()
+    };
+    let mut engine = ToyEngine::from_config(&config)?;
+    assert_eq!(engine.shard_count(), 2);
+
+    // Amounts keep three decimal places, and a locked account still takes
+    // deposits.
+    engine.dispatch(create_transaction("deposit", 1, 1, Some("1.005")))?;
+    assert_eq!(
+        engine.account(&client_id(1)).unwrap().available,
+        Decimal::from_str("1.005")?
+    );
+    engine.dispatch(create_transaction("dispute", 1, 1, None))?;
+    engine.dispatch(create_transaction("chargeback", 1, 1, None))?;
+    assert_eq!(
+        engine.dispatch(create_transaction("deposit", 1, 3, Some("2.5")))?,
+        Outcome::Applied
+    );
+    assert_eq!(
+        engine.dispatch(create_transaction("withdrawal", 1, 4, Some("1")))?,
+        Outcome::Ignored(IgnoreReason::AccountLocked)
+    );
+    let account = engine.account(&client_id(1)).unwrap();
+    assert_eq!(account.available, Decimal::from_str("2.5")?);
+    assert!(account.locked);
+
+    // Extra decimal places are truncated.
+    let mut engine = ToyEngine::from_config(&EngineConfig::default())?;
+    engine.dispatch(create_transaction("deposit", 1, 1, Some("1.005")))?;
+    assert_eq!(
+        engine.account(&client_id(1)).unwrap().available,
+        Decimal::from(1)
+    );
+
+    let invalid = EngineConfig {
+        storage: StorageConfig {
+            shards: 0,
+            ..StorageConfig::default()
+        },
+        ..EngineConfig::default()
+    };
+    assert!(ToyEngine::from_config(&invalid).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_balances_beyond_a_transaction_amount() -> Result<()> {
+    let config = EngineConfig {
+        amount_scale: 4,
+        ..EngineConfig::default()
+    };
+    let mut engine = ToyEngine::from_config(&config)?;
+
+    // A single amount is at most 429496.7295 with four decimal places, but
+    // a balance adds up many of them.
+    for tx in 1..=3 {
+        engine.dispatch(create_transaction("deposit", 1, tx, Some("400000.5")))?;
+    }
+    engine.dispatch(create_transaction("dispute", 1, 2, None))?;
+    let account = engine.account(&client_id(1)).unwrap();
+    assert_eq!(account.available, Decimal::from_str("800001")?);
+    assert_eq!(account.held, Decimal::from_str("400000.5")?);
+    assert_eq!(account.total, Decimal::from_str("1200001.5")?);
+
+    assert_eq!(
+        engine.dispatch(create_transaction("withdrawal", 1, 4, Some("429496.7295")))?,
+        Outcome::Applied
+    );
+    assert!(engine
+        .dispatch(create_transaction("deposit", 1, 5, Some("429496.7296")))
+        .is_err());
+    assert_eq!(
+        engine.account(&client_id(1)).unwrap().available,
+        Decimal::from_str("370504.2705")?
+    );
+    Ok(())
+}
+
+#[derive(Clone, Default)]
+struct RecordingObserver(Arc<Mutex<Vec<String>>>);
+
+impl EngineObserver for RecordingObserver {
+    fn on_applied(&mut self, tx: &InputTransaction) {
+        self.0.lock().unwrap().push(format!("applied {}", tx.tx));
+    }
+
+    fn on_ignored(&mut self, tx: &InputTransaction, reason: IgnoreReason) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("ignored {}: {}", tx.tx, reason));
+    }
+
+    fn on_rejected(&mut self, tx: &InputTransaction, _error: &anyhow::Error) {
+        self.0.lock().unwrap().push(format!("rejected {}", tx.tx));
+    }
+
+    fn on_chargeback(&mut self, client: &ClientId, tx: TxId, amount: Decimal) {
+        let event = format!("chargeback {} of {} by {}", tx, amount, client);
+        self.0.lock().unwrap().push(event);
+    }
+
+    fn on_account_locked(&mut self, client: &ClientId, reason: LockReason) {
+        let event = format!("locked {} ({:?})", client, reason);
+        self.0.lock().unwrap().push(event);
+    }
+}
+
+#[test]
+fn test_engine_observers() -> Result<()> {
+    let risk: RiskConfig = toml::from_str(
+        r#"
+        dispute_burst = { disputes = 2, window = 4, action = "freeze" }
+        "#,
+    )?;
+    let observer = RecordingObserver::default();
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("events.jsonl");
+    let mut engine = ToyEngine::new()
+        .with_risk(risk)
+        .with_observer(observer.clone())
+        .with_observer(JsonLinesObserver::create(&path)?);
+
+    let transactions = vec![
+        create_transaction("deposit", 1, 1, Some("10.00")),
+        create_transaction("deposit", 1, 1, Some("10.00")),
+        create_transaction("withdrawal", 1, 2, Some("50.00")),
+        create_transaction("dispute", 1, 1, None),
+        create_transaction("chargeback", 1, 1, None),
+        create_transaction("deposit", 2, 1, Some("1.00")),
+        create_transaction("deposit", 3, 3, Some("10.00")),
+        create_transaction("deposit", 3, 4, Some("10.00")),
+        create_transaction("dispute", 3, 3, None),
+        create_transaction("dispute", 3, 4, None),
+        create_transaction("deposit", 4, 5, Some("2.129")),
+    ];
+    for transaction in transactions {
+        let _ = engine.dispatch(transaction);
+    }
+
+    // Duplicates are not notified, and consequences follow their outcome.
+    assert_eq!(
+        *observer.0.lock().unwrap(),
+        [
+            "applied 1",
+            "ignored 2: insufficient funds",
+            "applied 1",
+            "applied 1",
+            "chargeback 1 of 10 by 1",
+            "locked 1 (Chargeback)",
+            "rejected 1",
+            "applied 3",
+            "applied 4",
+            "applied 3",
+            "applied 4",
+            "locked 3 (RiskMonitor)",
+            "applied 5",
+        ]
+    );
+
+    let events = std::fs::read_to_string(&path)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<EngineEvent>, _>>()?;
+    assert_eq!(events.len(), 16);
+    assert_eq!(
+        events[3],
+        EngineEvent::DisputeOpened {
+            client: client_id(1),
+            tx: tx_id(1),
+            amount: Decimal::from(10),
+        }
+    );
+    assert!(matches!(&events[7], EngineEvent::Rejected { client, .. } if *client == client_id(2)));
+    // The amount applied, as in the ledger, not the one of the input row.
+    assert_eq!(
+        events[15],
+        EngineEvent::Applied {
+            client: client_id(4),
+            tx: tx_id(5),
+            kind: "deposit".to_string(),
+            amount: Some(Decimal::from_str("2.12")?),
+        }
+    );
+    Ok(())
+}
+
+#[test]
+fn test_outbox() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("outbox.jsonl");
+
+    let mut engine = ToyEngine::new()
+        .with_fees(FeeSchedule::from_file("sample_data/fees.toml")?)
+        .with_ledger(Outbox::open(&path)?);
+    let transactions = vec![
+        create_transaction("deposit", 1, 1, Some("10.004")),
+        create_transaction("withdrawal", 1, 2, Some("4.00")),
+        create_transaction("withdrawal", 1, 3, Some("40.00")),
+        create_transaction("deposit", 1, 4, Some("5.00")),
+        create_transaction("dispute", 1, 4, None),
+        create_transaction("resolve", 1, 4, None),
+        create_transaction("dispute", 1, 1, None),
+    ];
+    for transaction in transactions {
+        engine.dispatch(transaction)?;
+    }
+
+    // Amounts are the ones applied, truncated to the scale, and fees are
+    // posted too. The house account opening is left out.
+    let mut consumer = OutboxConsumer::open(&path)?;
+    let entries = consumer.poll()?;
+    assert_eq!(
+        entries.iter().map(|entry| entry.seq).collect::<Vec<_>>(),
+        [1, 2, 3, 4, 5, 6, 7]
+    );
+    assert_eq!(
+        entries[0].event,
+        DomainEvent::Deposited {
+            client: client_id(1),
+            tx: tx_id(1),
+            amount: Decimal::from(10),
+        }
+    );
+    assert_eq!(
+        entries[1].event,
+        DomainEvent::Withdrawn {
+            client: client_id(1),
+            tx: tx_id(2),
+            amount: Decimal::from(4),
+        }
+    );
+    assert_eq!(
+        entries[2].event,
+        DomainEvent::FeeCharged {
+            client: client_id(1),
+            house: ClientId::Name("fees".into()),
+            amount: Decimal::from_str("0.50")?,
+        }
+    );
+    assert_eq!(
+        entries[5].event,
+        DomainEvent::DisputeResolved {
+            client: client_id(1),
+            tx: tx_id(4),
+            amount: Decimal::from(5),
+        }
+    );
+    consumer.ack(7)?;
+
+    // A restarted engine goes on numbering where the outbox stopped, and a
+    // restarted consumer only sees what was not acknowledged.
+    let mut engine = ToyEngine::new().with_ledger(Outbox::open(&path)?);
+    engine.dispatch(create_transaction("deposit", 1, 1, Some("10.00")))?;
+    engine.dispatch(create_transaction("dispute", 1, 1, None))?;
+    engine.dispatch(create_transaction("chargeback", 1, 1, None))?;
+
+    let mut consumer = OutboxConsumer::open(&path)?;
+    assert_eq!(consumer.acked(), 7);
+    let entries = consumer.poll()?;
+    assert_eq!(
+        entries.iter().map(|entry| entry.seq).collect::<Vec<_>>(),
+        [8, 9, 10, 11]
+    );
+    assert_eq!(
+        entries[3].event,
+        DomainEvent::AccountLocked {
+            client: client_id(1),
+            reason: LockReason::Chargeback,
+        }
+    );
+    Ok(())
+}
+
+#[derive(Clone, Default)]
+struct MemoryLedger(Arc<Mutex<Vec<LedgerRecord>>>);
+
+impl LedgerBackend for MemoryLedger {
+    fn append(&mut self, record: &LedgerRecord) -> Result<()> {
+        self.0.lock().unwrap().push(record.clone());
+        Ok(())
+    }
+}
+
+#[test]
+fn test_engine_rebuilt_from_ledger() -> Result<()> {
+    let fees = FeeSchedule::from_file("sample_data/fees.toml")?;
+    let ledger = MemoryLedger::default();
+    let mut engine = ToyEngine::new()
+        .with_fees(fees.clone())
+        .with_account_policy(AccountPolicy::OnReference)
+        .with_ledger(ledger.clone());
+
+    let transactions = vec![
+        create_transaction("deposit", 1, 1, Some("100.00")),
+        create_transaction("deposit", 2, 2, Some("50.00")),
+        create_transaction("withdrawal", 1, 3, Some("20.00")),
+        create_transaction("withdrawal", 2, 4, Some("500.00")),
+        create_transaction("dispute", 2, 2, None),
+        create_transaction("deposit", 1, 5, Some("30.00")),
+        create_transaction("dispute", 1, 5, None),
+        create_transaction("chargeback", 1, 5, None),
+        create_transaction("dispute", 3, 9, None),
+    ];
+    for transaction in transactions {
+        engine.dispatch(transaction)?;
+    }
+
+    let records = ledger.0.lock().unwrap().clone();
+    // The house account is opened first, without a transaction. The ignored
+    // withdrawal changed nothing, the unknown dispute opened an account.
+    assert_eq!(
+        records.iter().map(|record| record.seq).collect::<Vec<_>>(),
+        [1, 2, 3, 4, 5, 6, 7, 8, 9]
+    );
+    assert_eq!(
+        records[0].events,
+        [LedgerEvent::AccountOpened {
+            client: ClientId::Name("fees".into())
+        }]
+    );
+    assert_eq!((records[0].tx, records[0].kind), (None, None));
+    assert_eq!(records[4].tx, Some(tx_id(2)));
+
+    let config = EngineConfig::default();
+    let mut rebuilt = ToyEngine::from_ledger(&config, records.clone())?
+        .with_fees(fees)
+        .with_ledger(ledger.clone());
+    assert_eq!(rebuilt.get_all_accounts(), engine.get_all_accounts());
+    assert_eq!(
+        project::<Balances>(&records).records(),
+        engine.get_all_accounts()
+    );
+    assert_eq!(
+        project::<OpenDisputes>(&records).disputes(),
+        [OpenDispute {
+            client: client_id(2),
+            tx: tx_id(2),
+            amount: Decimal::from(50),
+        }]
+    );
+
+    // Transactions and dispute states came back too, and the ledger goes on
+    // where it stopped.
+    assert_eq!(
+        rebuilt.dispatch(create_transaction("deposit", 1, 1, Some("100.00")))?,
+        Outcome::Duplicate
+    );
+    assert_eq!(
+        rebuilt.dispatch(create_transaction("resolve", 2, 2, None))?,
+        Outcome::Applied
+    );
+    assert_eq!(ledger.0.lock().unwrap().last().unwrap().seq, 10);
+
+    // Balances as of the second deposit of client 1, before its chargeback.
+    let before =
+        ToyEngine::from_ledger(&config, as_of(&records, PointInTime::Tx(tx_id(5))).to_vec())?;
+    let account = before.account(&client_id(1)).unwrap();
+    assert_eq!(account.available, Decimal::from_str("109.50")?);
+    assert!(!account.locked);
+    let account = engine.account(&client_id(1)).unwrap();
+    assert_eq!(account.available, Decimal::from_str("64.50")?);
+    assert!(account.locked);
+    Ok(())
+}
+
+#[test]
+fn test_history_replay_archives_its_evictions() -> Result<()> {
+    let config = EngineConfig {
+        storage: StorageConfig {
+            shards: 1,
+            cache_capacity: 1,
+            ..StorageConfig::default()
+        },
+        ..EngineConfig::default()
+    };
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("archive.jsonl");
+    let ledger = MemoryLedger::default();
+    let mut engine = ToyEngine::builder()
+        .with_config(config.clone())
+        .with_archive(TransactionArchive::create(&path)?)
+        .with_ledger(ledger.clone())
+        .build()?;
+    engine.dispatch(create_transaction("deposit", 1, 1, Some("10.00")))?;
+    engine.dispatch(create_transaction("deposit", 1, 2, Some("5.00")))?;
+    let records = ledger.0.lock().unwrap().clone();
+
+    // Rebuilt with the archive of the previous run, or with a new one: tx 1
+    // is evicted by the replay either way, and still found.
+    for archive in [
+        TransactionArchive::open(&path)?,
+        TransactionArchive::create(dir.path().join("new.jsonl"))?,
+    ] {
+        let mut rebuilt = ToyEngine::builder()
+            .with_config(config.clone())
+            .with_archive(archive)
+            .with_history(records.clone())
+            .build()?;
+        assert_eq!(rebuilt.metrics().shards[0].evictions, 1);
+        assert_eq!(
+            rebuilt.dispatch(create_transaction("dispute", 1, 1, None))?,
+            Outcome::Applied
+        );
+    }
+    Ok(())
+}
+
+struct BrokenLedger;
+
+impl LedgerBackend for BrokenLedger {
+    fn append(&mut self, _record: &LedgerRecord) -> Result<()> {
+        Err(anyhow!("disk full"))
+    }
+}
+
+#[test]
+fn test_ledger_failure_fails_dispatch() -> Result<()> {
+    let mut engine = ToyEngine::new()
+        .with_account_policy(AccountPolicy::OnReference)
+        .with_ledger(BrokenLedger);
+
+    let error = engine
+        .dispatch(create_transaction("deposit", 1, 1, Some("10.00")))
+        .unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("Cannot append record 1 to the ledger"));
+    assert!(engine.account(&client_id(1)).is_none());
+    assert!(engine.transaction(tx_id(1)).is_none());
+
+    // The account opened by the dispute is not there either.
+    assert!(engine
+        .dispatch(create_transaction("dispute", 2, 1, None))
+        .is_err());
+    assert!(engine.get_all_accounts().is_empty());
+
+    // The backends before the broken one take their record back.
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("ledger.jsonl");
+    let mut engine = ToyEngine::new()
+        .with_ledger(LedgerFile::open(&path)?)
+        .with_ledger(BrokenLedger);
+    assert!(engine
+        .dispatch(create_transaction("deposit", 1, 1, Some("10.00")))
+        .is_err());
+    assert!(LedgerFile::read(&path)?.is_empty());
+    Ok(())
+}
+
+// Fails while its flag is set.
+struct FlakyLedger(Arc<Mutex<bool>>);
+
+impl LedgerBackend for FlakyLedger {
+    fn append(&mut self, _record: &LedgerRecord) -> Result<()> {
+        match *self.0.lock().unwrap() {
+            true => Err(anyhow!("disk full")),
+            false => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn test_ledger_failure_leaves_limits_and_risk_unchanged() -> Result<()> {
+    let limits: LimitsConfig = toml::from_str(
+        r#"
+        rolling_withdrawals = { window = 4, max_total = 100 }
+        "#,
+    )?;
+    let risk: RiskConfig = toml::from_str(
+        r#"
+        dispute_burst = { disputes = 1, window = 4, action = "freeze" }
+        "#,
+    )?;
+    let failing = Arc::new(Mutex::new(false));
+    let mut engine = ToyEngine::new()
+        .with_limits(limits)
+        .with_risk(risk)
+        .with_ledger(FlakyLedger(failing.clone()));
+    engine.dispatch(create_transaction("deposit", 1, 1, Some("100.00")))?;
+    engine.dispatch(create_transaction("deposit", 1, 4, Some("200.00")))?;
+
+    *failing.lock().unwrap() = true;
+    assert!(engine
+        .dispatch(create_transaction("withdrawal", 1, 2, Some("80.00")))
+        .is_err());
+    assert!(engine
+        .dispatch(create_transaction("dispute", 1, 1, None))
+        .is_err());
+    assert!(engine.risk_flags().is_empty());
+    assert!(!engine.account(&client_id(1)).unwrap().locked);
+
+    // Neither the withdrawal counts towards the rolling total, nor the
+    // dispute towards the burst, which fires again once written.
+    *failing.lock().unwrap() = false;
+    assert_eq!(
+        engine.dispatch(create_transaction("withdrawal", 1, 3, Some("80.00")))?,
+        Outcome::Applied
+    );
+    assert_eq!(
+        engine.dispatch(create_transaction("dispute", 1, 1, None))?,
+        Outcome::Applied
+    );
+    assert_eq!(engine.risk_flags().len(), 1);
+    assert!(engine.account(&client_id(1)).unwrap().locked);
+    Ok(())
+}
@@ -0,0 +1,192 @@
+use serde_json::{json, Value};
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+};
+use toypay::{server, ToyEngine};
+
+fn start_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || server::serve(listener, ToyEngine::new()));
+    addr
+}
+
+fn request(addr: SocketAddr, method: &str, path: &str, body: Option<Value>) -> (u16, Value) {
+    let body = body.map(|b| b.to_string()).unwrap_or_default();
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{method} {path} HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\n\
+         Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    let (head, payload) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, serde_json::from_str(payload).unwrap())
+}
+
+fn post(addr: SocketAddr, tx: Value) -> (u16, Value) {
+    request(addr, "POST", "/transactions", Some(tx))
+}
+
+#[test]
+fn test_health() {
+    let addr = start_server();
+    assert_eq!(
+        request(addr, "GET", "/health", None),
+        (200, json!({ "status": "ok" }))
+    );
+}
+
+#[test]
+fn test_transactions_and_accounts() {
+    let addr = start_server();
+
+    let deposit = json!({ "type": "deposit", "client": 1, "tx": 1, "amount": "100.00" });
+    assert_eq!(
+        post(addr, deposit.clone()),
+        (200, json!({ "outcome": "applied" }))
+    );
+    assert_eq!(
+        post(addr, deposit),
+        (200, json!({ "outcome": "duplicate" }))
+    );
+
+    let withdrawal = json!({ "type": "withdrawal", "client": 1, "tx": 2, "amount": 500 });
+    assert_eq!(
+        post(addr, withdrawal),
+        (
+            200,
+            json!({ "outcome": "ignored", "reason": "insufficient funds" })
+        )
+    );
+
+    let dispute = json!({ "type": "dispute", "client": 1, "tx": 1 });
+    assert_eq!(post(addr, dispute), (200, json!({ "outcome": "applied" })));
+
+    let (status, body) = request(addr, "GET", "/accounts/1", None);
+    assert_eq!(status, 200);
+    assert_eq!(body["available"], "0");
+    assert_eq!(body["held"], "100");
+    assert_eq!(body["locked"], false);
+
+    let (status, body) = request(addr, "GET", "/transactions/1", None);
+    assert_eq!(status, 200);
+    assert_eq!(body["client"], 1);
+    assert_eq!(body["kind"], "deposit");
+    assert_eq!(body["state"], "disputed");
+
+    let (status, body) = request(addr, "GET", "/accounts/1/disputes", None);
+    assert_eq!(status, 200);
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    assert_eq!(body[0]["tx"], 1);
+
+    post(
+        addr,
+        json!({ "type": "deposit", "client": 2, "tx": 3, "amount": 1 }),
+    );
+    let (status, body) = request(addr, "GET", "/accounts", None);
+    assert_eq!(status, 200);
+    assert_eq!(body.as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn test_errors() {
+    let addr = start_server();
+
+    assert_eq!(request(addr, "GET", "/accounts/7", None).0, 404);
+    assert_eq!(request(addr, "GET", "/transactions/7", None).0, 404);
+    assert_eq!(request(addr, "GET", "/accounts/abc", None).0, 404);
+    assert_eq!(request(addr, "GET", "/transactions/abc", None).0, 400);
+    assert_eq!(request(addr, "GET", "/unknown", None).0, 404);
+    assert_eq!(post(addr, json!({ "type": "deposit" })).0, 400);
+
+    let (status, body) = post(addr, json!({ "type": "deposit", "client": 1, "tx": 1 }));
+    assert_eq!(status, 422);
+    assert_eq!(body["outcome"], "rejected");
+
+    post(
+        addr,
+        json!({ "type": "deposit", "client": 1, "tx": 2, "amount": 5 }),
+    );
+    let (status, _) = post(
+        addr,
+        json!({ "type": "deposit", "client": 1, "tx": 2, "amount": 6 }),
+    );
+    assert_eq!(status, 409);
+}
+
+#[test]
+fn test_body_limit() {
+    let addr = start_server();
+    let limit = server::MAX_BODY_BYTES as usize;
+
+    let padded = |size: usize| {
+        let tx = json!({ "type": "deposit", "client": 1, "tx": 1, "amount": "10" }).to_string();
+        format!("{}{}", " ".repeat(size - tx.len()), tx)
+    };
+    let raw = |head: &str, body: &str| {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "POST /transactions HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\n{head}\r\n{body}"
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    let response = raw(
+        &format!("Content-Length: {}\r\n", limit + 1),
+        &padded(limit + 1),
+    );
+    assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+
+    // Without a length announced, the body is cut at the limit all the same.
+    let body = padded(limit + 1);
+    let chunked = format!("{:x}\r\n{}\r\n0\r\n\r\n", body.len(), body);
+    let response = raw("Transfer-Encoding: chunked\r\n", &chunked);
+    assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+
+    let response = raw(&format!("Content-Length: {}\r\n", limit), &padded(limit));
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+}
+
+#[test]
+fn test_metrics() {
+    let addr = start_server();
+    post(
+        addr,
+        json!({ "type": "deposit", "client": 1, "tx": 1, "amount": "10" }),
+    );
+    post(addr, json!({ "type": "dispute", "client": 1, "tx": 1 }));
+    post(addr, json!({ "type": "dispute", "client": 1, "tx": 1 }));
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "GET /metrics HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\n\r\n"
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.contains("text/plain"));
+    assert!(
+        response.contains("toypay_transactions_total{type=\"dispute\",outcome=\"applied\"} 1\n")
+    );
+    assert!(
+        response.contains("toypay_transactions_total{type=\"dispute\",outcome=\"duplicate\"} 1\n")
+    );
+    assert!(response.contains("toypay_dispute_rate 1\n"));
+    assert!(response.contains("toypay_accounts 1\n"));
+}